@@ -0,0 +1,133 @@
+//! Rendering of [`SetAnimation`]s.
+
+use smart_leds::RGB8;
+use smart_leds::hsv::{Hsv, hsv2rgb};
+use spark_messages::{Color, Config, Pattern, Scene, SetAnimation};
+
+/// What a light plays when a button is pressed: a sweep through the hues, as long and as bright as
+/// configured.
+pub fn button_animation(config: &Config) -> SetAnimation {
+    SetAnimation {
+        pattern: Pattern::HueSweep,
+        color: Color::Hsv {
+            hue: 0,
+            sat: 255,
+            val: 255,
+        },
+        speed: 100,
+        brightness: config.brightness,
+        duration_ms: Some(config.animation_timeout_ms),
+    }
+}
+
+/// `animation`, `steps` brighter, or dimmer if negative. Dimming stops short of going dark.
+pub fn brighten(animation: &SetAnimation, steps: i32) -> SetAnimation {
+    SetAnimation {
+        brightness: (animation.brightness as i32 + steps).clamp(1, 255) as u8,
+        ..*animation
+    }
+}
+
+/// `animation`, with its base color `steps` further around the hues.
+pub fn shift_hue(animation: &SetAnimation, steps: u32) -> SetAnimation {
+    let base = to_hsv(animation.color);
+    SetAnimation {
+        color: Color::Hsv {
+            hue: base.hue.wrapping_add(steps as u8),
+            sat: base.sat,
+            val: base.val,
+        },
+        ..*animation
+    }
+}
+
+/// The brightness to drive strip `strip` of `scene` at.
+pub fn strip_brightness(scene: &Scene, strip: usize) -> u8 {
+    scale(scene.animation.brightness, scene.strip_level(strip))
+}
+
+/// Every pattern [`pixel`] renders.
+pub const PATTERNS: [Pattern; 6] = [
+    Pattern::Off,
+    Pattern::Solid,
+    Pattern::HueSweep,
+    Pattern::Breathe,
+    Pattern::Blink,
+    Pattern::Rainbow,
+];
+
+/// The color of LED `index` out of `count` at network time `time_ms`, so that lights playing the
+/// same animation show the same thing. Brightness and gamma correction are left to the caller.
+pub fn pixel(animation: &SetAnimation, time_ms: u64, index: usize, count: usize) -> RGB8 {
+    let base = to_hsv(animation.color);
+    // Wraps around every 256 steps, i.e. every cycle.
+    let step = (time_ms * animation.speed as u64 / 1000) as u8;
+
+    let color = match animation.pattern {
+        Pattern::Off => return RGB8::default(),
+        Pattern::Solid => base,
+        Pattern::HueSweep => Hsv {
+            hue: base.hue.wrapping_add(step),
+            ..base
+        },
+        Pattern::Breathe => Hsv {
+            val: scale(base.val, triangle(step)),
+            ..base
+        },
+        Pattern::Blink if step >= 128 => return RGB8::default(),
+        Pattern::Blink => base,
+        Pattern::Rainbow => Hsv {
+            hue: base
+                .hue
+                .wrapping_add(step)
+                .wrapping_add((index * 256 / count) as u8),
+            ..base
+        },
+        _ => base,
+    };
+    hsv2rgb(color)
+}
+
+fn to_hsv(color: Color) -> Hsv {
+    let (r, g, b) = match color {
+        Color::Hsv { hue, sat, val } => return Hsv { hue, sat, val },
+        Color::Rgb { r, g, b } => (r as i32, g as i32, b as i32),
+    };
+
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    if delta == 0 {
+        return Hsv {
+            hue: 0,
+            sat: 0,
+            val: max as u8,
+        };
+    }
+
+    // Hues go around in 256 steps, a sixth of which is 43.
+    let hue = if max == r {
+        43 * (g - b) / delta
+    } else if max == g {
+        85 + 43 * (b - r) / delta
+    } else {
+        171 + 43 * (r - g) / delta
+    };
+    Hsv {
+        hue: hue.rem_euclid(256) as u8,
+        sat: (255 * delta / max) as u8,
+        val: max as u8,
+    }
+}
+
+/// Up from 0 to 254 over the first half of a cycle, and back down over the second.
+fn triangle(step: u8) -> u8 {
+    if step < 128 {
+        step * 2
+    } else {
+        (255 - step) * 2
+    }
+}
+
+fn scale(value: u8, level: u8) -> u8 {
+    (value as u16 * level as u16 / 255) as u8
+}
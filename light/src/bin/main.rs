@@ -9,112 +9,283 @@
 #[allow(unused_imports)]
 use esp_backtrace as _;
 
+use core::cell::{Cell, RefCell};
+
 use embassy_executor::Spawner;
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::signal::Signal;
 use embassy_time::{Duration, Instant, Ticker, Timer};
 use esp_hal::clock::CpuClock;
+use esp_hal::efuse::Efuse;
+use esp_hal::gpio::{Input, InputConfig, Pull};
 use esp_hal::rmt::{ConstChannelAccess, Rmt, Tx};
 use esp_hal::rng::Rng;
+use esp_hal::rtc_cntl::{SocResetReason, reset_reason};
+use esp_hal::system::Cpu;
 use esp_hal::time::Rate;
 use esp_hal::timer::systimer::SystemTimer;
 use esp_hal::timer::timg::TimerGroup;
 use esp_hal_smartled::{SmartLedsAdapter, smart_led_buffer};
 use esp_println::println;
-use esp_wifi::esp_now::{EspNowManager, EspNowReceiver};
+use esp_storage::FlashStorage;
+use esp_wifi::esp_now::{EspNowManager, EspNowReceiver, EspNowSender};
 use esp_wifi::{
     EspWifiController,
     esp_now::{BROADCAST_ADDRESS, PeerInfo},
     init,
 };
-use smart_leds::{
-    RGB8, SmartLedsWrite, brightness, gamma,
-    hsv::{Hsv, hsv2rgb},
+use light::animation;
+use light::storage::{MAX_REMOTES, Storage};
+use light::update::Update;
+use smart_leds::{RGB8, SmartLedsWrite, brightness, gamma};
+use spark_messages::{
+    Announce, ButtonEventType, ButtonNumber, CONFIRM_TIMEOUT_MS, Capabilities, ClockSync, Config,
+    ConfigEntry, ConfigError, ConfigKey, ConfigValue, DEDUP_WINDOW_MS, DedupCache, ErrorCode,
+    FirmwareVersion, HANDSHAKE_RETRY_MS, HEARTBEAT_INTERVAL_MS, Heartbeat, KEY_EXCHANGE_TIMEOUT_MS,
+    Key, KeyExchange, LightMode, LightStatus, LinkMonitor, Lmk, MAX_FRAME_LEN, MacAddress, Message,
+    MessageType, OpenError, PairingResponder, Pattern, PixelBuffer, ProtocolVersions, Ramp,
+    Reassembler, ResetReason, Role, STREAM_TIMEOUT_MS, SYNC_INTERVAL_MS, Scene, SceneError,
+    SceneSet, SceneStore, SetAnimation, open, parse_key, sign,
 };
-use spark_messages::{Message, MessageType, PROTOCOL_VERSION};
 
-static REMOTE_MAC: [u8; 6] = [0xC8, 0xF0, 0x9E, 0x2C, 0x28, 0x8C];
+/// Pre-shared key of this installation, as 64 hex digits. Remotes and lights only talk to devices
+/// built with the same key.
+const KEY: Key = parse_key(env!("SPARK_KEY"));
+
+/// Largest message remotes can send in fragments.
+const MAX_REASSEMBLED_LEN: usize = 1024;
+
+const FIRMWARE_VERSION: FirmwareVersion = FirmwareVersion::parse(env!("CARGO_PKG_VERSION"));
+
+/// Holding this button goes around the hues. Holding any other brightens and dims, in turns.
+const HUE_BUTTON: ButtonNumber = ButtonNumber::Button3;
+
+/// How fast holding a button ramps, in steps per second. Brightness and hue both have 256 steps.
+const RAMP_STEPS_PER_S: u32 = 128;
+
+/// Fewest milliseconds between two [`LightStatus`] pushes. What changes meanwhile, like the
+/// brightness while a remote ramps it, goes out in one push once the interval is over.
+const STATUS_INTERVAL_MS: u64 = 250;
+
+/// Message IDs remembered per sender, enough for the repeats of a held button over the whole
+/// [`DEDUP_WINDOW_MS`].
+const DEDUP_IDS: usize = 64;
+
+/// LEDs per strip there are buffers for. [`ConfigKey::LedCount`] can't go higher.
+const MAX_LEDS: usize = 8;
+
+const STRIPS: usize = 4;
+
+const CAPABILITIES: Capabilities = Capabilities {
+    strips: STRIPS as u8,
+    leds_per_strip: MAX_LEDS as u16,
+};
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
 
-static LIGHT_TRIGGER: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static LIGHT_TRIGGER: Signal<CriticalSectionRawMutex, Scene> = Signal::new();
 
-#[embassy_executor::task]
-async fn light_task(
-    mut led1: SmartLedsAdapter<ConstChannelAccess<Tx, 0>, 193>,
-    mut led2: SmartLedsAdapter<ConstChannelAccess<Tx, 1>, 193>,
-    mut led3: SmartLedsAdapter<ConstChannelAccess<Tx, 2>, 193>,
-    mut led4: SmartLedsAdapter<ConstChannelAccess<Tx, 3>, 193>,
-) {
-    // Turn off all pixels at startup
-    let off = &[RGB8::default(); 8];
-    led1.write(off.iter().cloned()).unwrap();
-    led2.write(off.iter().cloned()).unwrap();
-    led3.write(off.iter().cloned()).unwrap();
-    led4.write(off.iter().cloned()).unwrap();
-
-    let mut color = Hsv {
-        hue: 0,
-        sat: 255,
-        val: 255,
-    };
+/// Signalled when the BOOT button is pressed, confirming pairing with a remote.
+static PAIRING_CONFIRMED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
 
-    let mut data;
-    loop {
-        // Wait for button press
-        LIGHT_TRIGGER.wait().await;
+/// Streamed pixels of every strip, published whenever what shows changes.
+static PIXELS: Signal<CriticalSectionRawMutex, Pixels> = Signal::new();
 
-        // Animation runs for 3 seconds, unless restarted by button press
-        let mut deadline = Instant::now() + Duration::from_secs(3);
+/// What `light_task` is showing, published whenever that changes.
+static SHOWING: Signal<CriticalSectionRawMutex, Showing> = Signal::new();
 
-        'anim: loop {
-            let frame_timer = Timer::after(Duration::from_millis(10));
-            let animation_timer = Timer::at(deadline);
+/// Estimate of the network time, which animations are rendered against.
+static CLOCK: Mutex<CriticalSectionRawMutex, RefCell<ClockSync>> =
+    Mutex::new(RefCell::new(ClockSync::new()));
+
+/// The settings in storage, for `light_task` to read. `None` until loaded.
+static CONFIG: Mutex<CriticalSectionRawMutex, Cell<Option<Config>>> = Mutex::new(Cell::new(None));
+
+/// The last thing that went wrong, reported in [`LightStatus`].
+static LAST_ERROR: Mutex<CriticalSectionRawMutex, Cell<Option<ErrorCode>>> =
+    Mutex::new(Cell::new(None));
+
+type Pixels = [[[u8; 3]; MAX_LEDS]; STRIPS];
+
+#[derive(Debug, Copy, Clone)]
+struct Showing {
+    mode: LightMode,
+    pattern: Pattern,
+    brightness: u8,
+}
+
+const NOTHING: Showing = Showing {
+    mode: LightMode::Off,
+    pattern: Pattern::Off,
+    brightness: 0,
+};
+
+fn network_time_ms() -> u64 {
+    let now = Instant::now().as_micros();
+    CLOCK.lock(|clock| clock.borrow().network_time(now)) / 1000
+}
+
+fn config() -> Config {
+    CONFIG.lock(Cell::get).unwrap_or_default()
+}
+
+fn record_error(code: ErrorCode) {
+    LAST_ERROR.lock(|last_error| last_error.set(Some(code)));
+}
+
+/// The LED strips, in strip order.
+struct Strips {
+    led1: SmartLedsAdapter<ConstChannelAccess<Tx, 0>, 193>,
+    led2: SmartLedsAdapter<ConstChannelAccess<Tx, 1>, 193>,
+    led3: SmartLedsAdapter<ConstChannelAccess<Tx, 2>, 193>,
+    led4: SmartLedsAdapter<ConstChannelAccess<Tx, 3>, 193>,
+}
+
+impl Strips {
+    /// Write `pixels(strip)` to every strip.
+    fn write<I: Iterator<Item = RGB8>>(&mut self, mut pixels: impl FnMut(usize) -> I) {
+        self.led1.write(pixels(0)).unwrap();
+        self.led2.write(pixels(1)).unwrap();
+        self.led3.write(pixels(2)).unwrap();
+        self.led4.write(pixels(3)).unwrap();
+    }
+
+    fn off(&mut self) {
+        self.write(|_| [RGB8::default(); MAX_LEDS].into_iter());
+    }
+}
+
+/// Show streamed pixels as they are, until none came for [`STREAM_TIMEOUT_MS`]. Returns the last
+/// scene triggered meanwhile, to play next.
+async fn stream(strips: &mut Strips, mut pixels: Pixels) -> Option<Scene> {
+    SHOWING.signal(Showing {
+        mode: LightMode::Streaming,
+        pattern: Pattern::Off,
+        brightness: u8::MAX,
+    });
+    let mut next = None;
+    loop {
+        strips.write(|strip| {
+            pixels[strip]
+                .map(|[r, g, b]| RGB8::new(r, g, b))
+                .into_iter()
+        });
+        let deadline = Instant::now() + Duration::from_millis(STREAM_TIMEOUT_MS);
+        loop {
+            let streamed = PIXELS.wait();
             let trigger = LIGHT_TRIGGER.wait();
+            let stream_timer = Timer::at(deadline);
 
-            match embassy_futures::select::select3(trigger, animation_timer, frame_timer).await {
-                embassy_futures::select::Either3::First(_) => {
-                    deadline = Instant::now() + Duration::from_secs(3);
-                    continue 'anim;
+            match embassy_futures::select::select3(streamed, trigger, stream_timer).await {
+                embassy_futures::select::Either3::First(streamed) => {
+                    pixels = streamed;
+                    break;
                 }
-                embassy_futures::select::Either3::Second(_) => {
-                    break 'anim;
+                embassy_futures::select::Either3::Second(scene) => next = Some(scene),
+                embassy_futures::select::Either3::Third(_) => return next,
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn light_task(mut strips: Strips) {
+    // Turn off all pixels at startup
+    strips.off();
+
+    loop {
+        // Wait for something to play, or for pixels to stream
+        let streamed = PIXELS.wait();
+        let trigger = LIGHT_TRIGGER.wait();
+        let mut scene = match embassy_futures::select::select(trigger, streamed).await {
+            embassy_futures::select::Either::First(scene) => scene,
+            embassy_futures::select::Either::Second(pixels) => {
+                match stream(&mut strips, pixels).await {
+                    Some(scene) => scene,
+                    None => {
+                        strips.off();
+                        SHOWING.signal(NOTHING);
+                        continue;
+                    }
                 }
-                embassy_futures::select::Either3::Third(_) => {
-                    color.hue = color.hue.wrapping_add(1);
-                    // Convert from the HSV color space (where we can easily transition from one
-                    // color to the other) to the RGB color space that we can then send to the LED
-                    data = [hsv2rgb(color)];
-                    // When sending to the LED, we do a gamma correction first (see smart_leds
-                    // documentation for details) and then limit the brightness to 10 out of 255 so
-                    // that the output it's not too bright.
-
-                    let data2: &[RGB8; 8] = &[
-                        brightness(gamma(data.iter().cloned()), 25).next().unwrap(),
-                        brightness(gamma(data.iter().cloned()), 25).next().unwrap(),
-                        brightness(gamma(data.iter().cloned()), 25).next().unwrap(),
-                        brightness(gamma(data.iter().cloned()), 25).next().unwrap(),
-                        brightness(gamma(data.iter().cloned()), 25).next().unwrap(),
-                        brightness(gamma(data.iter().cloned()), 25).next().unwrap(),
-                        brightness(gamma(data.iter().cloned()), 25).next().unwrap(),
-                        brightness(gamma(data.iter().cloned()), 25).next().unwrap(),
-                    ];
-
-                    led1.write(data2.iter().cloned()).unwrap();
-                    led2.write(data2.iter().cloned()).unwrap();
-                    led3.write(data2.iter().cloned()).unwrap();
-                    led4.write(data2.iter().cloned()).unwrap();
+            }
+        };
+
+        // Animations run for their duration, or until the next one, unless latched
+        'anim: loop {
+            let animation = scene.animation;
+            if animation.pattern == Pattern::Off {
+                break 'anim;
+            }
+            SHOWING.signal(Showing {
+                mode: match animation.duration_ms {
+                    Some(_) => LightMode::Timed,
+                    None => LightMode::Latched,
+                },
+                pattern: animation.pattern,
+                brightness: animation.brightness,
+            });
+            let started = Instant::now();
+            let deadline = animation.duration_ms.map_or(Instant::MAX, |ms| {
+                started + Duration::from_millis(ms as u64)
+            });
+
+            loop {
+                let frame_timer = Timer::after(Duration::from_millis(10));
+                let animation_timer = Timer::at(deadline);
+                let trigger = LIGHT_TRIGGER.wait();
+                let streamed = PIXELS.wait();
+
+                match embassy_futures::select::select4(
+                    trigger,
+                    animation_timer,
+                    frame_timer,
+                    streamed,
+                )
+                .await
+                {
+                    embassy_futures::select::Either4::First(next) => {
+                        scene = next;
+                        continue 'anim;
+                    }
+                    embassy_futures::select::Either4::Second(_) => {
+                        break 'anim;
+                    }
+                    embassy_futures::select::Either4::Third(_) => {
+                        let time = network_time_ms();
+                        let count = (config().led_count as usize).min(MAX_LEDS);
+                        let pixels: [RGB8; MAX_LEDS] = core::array::from_fn(|i| {
+                            if i < count {
+                                animation::pixel(&animation, time, i, count)
+                            } else {
+                                RGB8::default()
+                            }
+                        });
+                        // When sending to the LED, we do a gamma correction first (see smart_leds
+                        // documentation for details) and then limit the brightness so that the
+                        // output it's not too bright. Every strip is dimmed to its level.
+                        strips.write(|strip| {
+                            let level = animation::strip_brightness(&scene, strip);
+                            brightness(gamma(pixels.into_iter()), level)
+                        });
+                    }
+                    // Streamed pixels take over, then the animation starts over, or whatever was
+                    // triggered meanwhile plays.
+                    embassy_futures::select::Either4::Fourth(pixels) => {
+                        if let Some(next) = stream(&mut strips, pixels).await {
+                            scene = next;
+                        }
+                        continue 'anim;
+                    }
                 }
             }
         }
 
-        let off = &[RGB8::default(); 8];
-        led1.write(off.iter().cloned()).unwrap();
-        led2.write(off.iter().cloned()).unwrap();
-        led3.write(off.iter().cloned()).unwrap();
-        led4.write(off.iter().cloned()).unwrap();
+        strips.off();
+        SHOWING.signal(NOTHING);
     }
 }
 
@@ -128,38 +299,675 @@ macro_rules! mk_static {
     }};
 }
 
+/// Register a remote to unicast to, encrypting traffic with `lmk` if there's one. Registering it
+/// again switches to the new key, or back to plaintext.
+fn add_unicast_peer(manager: &EspNowManager<'static>, peer_address: MacAddress, lmk: Option<Lmk>) {
+    let peer = PeerInfo {
+        interface: esp_wifi::esp_now::EspNowWifiInterface::Sta,
+        peer_address,
+        lmk,
+        channel: None,
+        encrypt: lmk.is_some(),
+    };
+    if manager.peer_exists(&peer_address) {
+        manager.modify_peer(peer).unwrap();
+    } else {
+        manager.add_peer(peer).unwrap();
+    }
+}
+
+async fn send_message(sender: &mut EspNowSender<'static>, dst: &MacAddress, message: &Message) {
+    let mut tx_buf: [u8; MAX_FRAME_LEN] = [0; MAX_FRAME_LEN];
+    let len = message.encode(&mut tx_buf).unwrap().len();
+    // Relayed messages are signed as their origin, see `Message::relayed`.
+    let signer = message.origin(Efuse::mac_address());
+    let data = sign(&KEY, &signer, &mut tx_buf, len).unwrap();
+
+    if let Err(e) = sender.send_async(dst, data).await {
+        println!("failed to send: {:?}", e);
+        record_error(ErrorCode::SendFailed);
+    }
+}
+
+async fn send(
+    sender: &mut EspNowSender<'static>,
+    storage: &mut Storage,
+    dst: &MacAddress,
+    message_type: MessageType,
+) {
+    let message = Message::new(message_type, storage.next_counter()).with_id(storage.next_id());
+    send_message(sender, dst, &message).await;
+}
+
+/// Tell every paired remote what this light is showing.
+async fn push_status(sender: &mut EspNowSender<'static>, storage: &mut Storage, showing: &Showing) {
+    let remotes: heapless::Vec<MacAddress, MAX_REMOTES> =
+        storage.state().remotes.iter().map(|r| r.mac).collect();
+    for remote in &remotes {
+        send(sender, storage, remote, status(showing)).await;
+    }
+}
+
+fn status(showing: &Showing) -> MessageType {
+    MessageType::LightStatus(LightStatus {
+        mode: showing.mode,
+        pattern: showing.pattern,
+        brightness: showing.brightness,
+        uptime_ms: Instant::now().as_millis(),
+        firmware_version: FIRMWARE_VERSION,
+        last_error: LAST_ERROR.lock(Cell::get),
+    })
+}
+
+/// Why this light last booted, as far as the protocol cares.
+fn boot_reason() -> ResetReason {
+    match reset_reason(Cpu::ProCpu) {
+        Some(SocResetReason::ChipPowerOn) => ResetReason::PowerOn,
+        Some(SocResetReason::CoreSw | SocResetReason::CpuSw) => ResetReason::Software,
+        Some(
+            SocResetReason::CoreMwdt0
+            | SocResetReason::CoreMwdt1
+            | SocResetReason::CoreRtcWdt
+            | SocResetReason::CpuMwdt0
+            | SocResetReason::CpuMwdt1
+            | SocResetReason::CpuRtcWdt
+            | SocResetReason::SysRtcWdt
+            | SocResetReason::SysSuperWdt,
+        ) => ResetReason::Watchdog,
+        Some(SocResetReason::SysBrownOut) => ResetReason::Brownout,
+        Some(SocResetReason::CoreDeepSleep) => ResetReason::DeepSleep,
+        _ => ResetReason::Other,
+    }
+}
+
+fn announce(storage: &Storage) -> MessageType {
+    let leds = storage.config().led_count.min(MAX_LEDS as u16);
+    MessageType::Announce(Announce {
+        id: storage.address().id,
+        firmware_version: FIRMWARE_VERSION,
+        protocol_versions: ProtocolVersions::SUPPORTED,
+        strips: heapless::Vec::from_slice(&[leds; CAPABILITIES.strips as usize]).unwrap(),
+        patterns: animation::PATTERNS.into_iter().collect(),
+    })
+}
+
+/// What remotes last had this light do, for held buttons to carry on from.
+struct Controls {
+    /// The scene last started, which holds adjust and [`MessageType::SceneStore`] saves.
+    scene: Option<Scene>,
+    /// In button order.
+    ramps: [Ramp; 4],
+    /// Whether the next hold dims rather than brightens.
+    dim_next: bool,
+}
+
+impl Controls {
+    const fn new() -> Self {
+        Self {
+            scene: None,
+            ramps: [Ramp::new(RAMP_STEPS_PER_S); 4],
+            dim_next: false,
+        }
+    }
+
+    /// Play `animation` on every strip alike.
+    fn play(&mut self, animation: SetAnimation) {
+        self.show(Scene::new(animation));
+    }
+
+    fn show(&mut self, scene: Scene) {
+        self.scene = Some(scene.clone());
+        LIGHT_TRIGGER.signal(scene);
+    }
+
+    fn on_button(&mut self, button: ButtonNumber, event_type: ButtonEventType) {
+        let ramp = &mut self.ramps[button as usize];
+        // Holding a button makes for a long press too, which the hold has taken care of.
+        if event_type == ButtonEventType::LongPress && ramp.is_holding() {
+            return;
+        }
+        let was_holding = ramp.is_holding();
+        let steps = ramp.on_event(&event_type);
+        match event_type {
+            ButtonEventType::ShortPress { .. } | ButtonEventType::LongPress => {
+                self.play(animation::button_animation(&config()))
+            }
+            ButtonEventType::HoldRepeat { .. } if steps > 0 => {
+                let current = self
+                    .scene
+                    .clone()
+                    .unwrap_or_else(|| Scene::new(animation::button_animation(&config())));
+                let next = match button {
+                    HUE_BUTTON => animation::shift_hue(&current.animation, steps),
+                    _ if self.dim_next => animation::brighten(&current.animation, -(steps as i32)),
+                    _ => animation::brighten(&current.animation, steps as i32),
+                };
+                self.show(Scene {
+                    animation: next,
+                    ..current
+                });
+            }
+            // A hold is over.
+            _ if was_holding && button != HUE_BUTTON => self.dim_next = !self.dim_next,
+            _ => {}
+        }
+    }
+}
+
+/// Whether `message_type` is one remotes send until acknowledged. Held buttons are sent once, as
+/// they would be stale by the time a retry went out.
+fn needs_ack(message_type: &MessageType) -> bool {
+    matches!(
+        message_type,
+        MessageType::ButtonEvent {
+            event_type: ButtonEventType::ShortPress { .. } | ButtonEventType::LongPress,
+            ..
+        } | MessageType::ButtonChord(_)
+            | MessageType::ButtonSequence(_)
+            | MessageType::SetAnimation(_)
+            | MessageType::SetAddress(_)
+            | MessageType::SceneStore(_)
+            | MessageType::SceneRecall { .. }
+    )
+}
+
+/// [`Storage::accept`], or [`Storage::accept_frequent`] for what comes too often to persist for:
+/// streamed pixels, held buttons and firmware chunks.
+fn accept(storage: &mut Storage, origin: MacAddress, message: &Message) -> bool {
+    let frequent = match message.message_type {
+        MessageType::ButtonEvent { .. } => !needs_ack(&message.message_type),
+        MessageType::PixelFrame(_) | MessageType::OtaChunk(_) => true,
+        _ => false,
+    };
+    if frequent {
+        storage.accept_frequent(origin, message.counter)
+    } else {
+        storage.accept(origin, message.counter)
+    }
+}
+
+/// Whether to relay `message_type` once accepted from a paired remote, see
+/// [`ConfigKey::RelayHops`]. Pairing is kept to the remote's own range, time responses answer a
+/// single light, heartbeats are about the link they came over, and streamed pixels, firmware
+/// chunks and fragments are bulk traffic, too much to flood the network with.
+fn relays(message_type: &MessageType) -> bool {
+    !matches!(
+        message_type,
+        MessageType::Handshake(_)
+            | MessageType::HandshakeConfirm(_)
+            | MessageType::KeyShare(_)
+            | MessageType::KeyConfirm(_)
+            | MessageType::TimeResponse(_)
+            | MessageType::Heartbeat(_)
+            | MessageType::PixelFrame(_)
+            | MessageType::OtaChunk(_)
+            | MessageType::Fragment(_)
+    )
+}
+
+/// Act on a message from a paired remote, once it has been authenticated and checked for replays.
+fn act_on(controls: &mut Controls, message_type: MessageType) {
+    match message_type {
+        MessageType::ButtonEvent {
+            button_number,
+            event_type,
+        } => controls.on_button(button_number, event_type),
+        MessageType::SetAnimation(animation) => controls.play(animation),
+        _ => {
+            // unknown message type
+        }
+    }
+}
+
+/// Save a scene, the one this light last started unless `store` spells one out.
+fn store_scene(
+    storage: &mut Storage,
+    controls: &Controls,
+    store: SceneStore,
+) -> Result<SceneSet, SceneError> {
+    let scene = store
+        .scene
+        .or_else(|| controls.scene.clone())
+        .ok_or(SceneError::NothingShowing)?;
+    storage.store_scene(store.slot, scene)
+}
+
+/// Play a saved scene.
+fn recall_scene(
+    storage: &Storage,
+    controls: &mut Controls,
+    slot: u8,
+) -> Result<SceneSet, SceneError> {
+    let scene = storage.scenes().recall(slot)?.clone();
+    controls.show(scene);
+    Ok(storage.scenes().slots())
+}
+
+/// Change a setting, within what this light's hardware can do.
+fn configure(storage: &mut Storage, entry: &ConfigEntry) -> Result<ConfigEntry, ConfigError> {
+    if matches!(entry.value, ConfigValue::U16(count)
+        if entry.key == ConfigKey::LedCount && count as usize > MAX_LEDS)
+    {
+        return Err(ConfigError::OutOfRange {
+            key: ConfigKey::LedCount,
+            min: ConfigKey::LedCount.info().min,
+            max: ConfigValue::U16(MAX_LEDS as u16),
+        });
+    }
+
+    let entry = storage.set_config(entry)?;
+    CONFIG.lock(|config| config.set(Some(*storage.config())));
+    Ok(entry)
+}
+
+/// Confirms pairing whenever the BOOT button is pressed.
+#[embassy_executor::task]
+async fn pairing_button(mut button: Input<'static>) {
+    loop {
+        button.wait_for_falling_edge().await;
+        PAIRING_CONFIRMED.signal(());
+        // Let the contacts settle.
+        Timer::after(Duration::from_millis(50)).await;
+    }
+}
+
+/// A key exchange with a newly paired remote, see [`KeyExchange`].
+struct Keying {
+    exchange: KeyExchange,
+    started_at: u64,
+    /// When both sides confirmed.
+    finished_at: Option<u64>,
+}
+
+/// Receives messages from remotes. The light is in pairing mode for a while after boot, and only
+/// acts on messages from remotes that paired with it, addressed to it.
 #[embassy_executor::task]
 async fn listener(
-    _manager: &'static EspNowManager<'static>,
+    manager: &'static EspNowManager<'static>,
+    mut sender: EspNowSender<'static>,
     mut receiver: EspNowReceiver<'static>,
+    mut storage: Storage,
+    mut update: Update,
+    mut rng: Rng,
 ) {
+    for remote in &storage.state().remotes {
+        add_unicast_peer(manager, remote.mac, storage.lmk(remote.mac));
+    }
+
+    let mut showing = NOTHING;
+    let mut status_pending = false;
+    let mut next_status = Instant::now();
+    let mut next_retry = Instant::now();
+    let mut next_sync = Instant::now();
+    let mut next_heartbeat = Instant::now();
+    let mut heartbeat = Heartbeat {
+        seq: 0,
+        uptime_ms: 0,
+        reset_reason: boot_reason(),
+    };
+    let mut links = LinkMonitor::<MAX_REMOTES>::new();
+    let mut dedup = DedupCache::<MAX_REMOTES, DEDUP_IDS>::new(DEDUP_WINDOW_MS);
+    let mut reassembler = Reassembler::<MAX_REASSEMBLED_LEN, 2>::new();
+    let mut pixels = PixelBuffer::<STRIPS, MAX_LEDS>::new();
+    let mut pairing = PairingResponder::new(Efuse::mac_address(), CAPABILITIES);
+    pairing.start(Instant::now().as_millis());
+    let mut keying: Option<Keying> = None;
+    let mut controls = Controls::new();
+
     loop {
-        let r = receiver.receive_async().await;
-        if r.info.dst_address == BROADCAST_ADDRESS && r.info.src_address == REMOTE_MAC {
-            let message = postcard::from_bytes::<Message>(r.data());
-            match message {
-                Ok(message) => {
-                    println!("got message: {:?}", message);
-
-                    if message.protocol_version == PROTOCOL_VERSION {
-                        match message.message_type {
-                            // TODO: do different things depending on specific event
-                            MessageType::ButtonEvent { .. } => {
-                                LIGHT_TRIGGER.signal(());
-                            }
-                            _ => {
-                                // unknown message type
+        let receive = receiver.receive_async();
+        let retry_timer =
+            embassy_futures::select::select(Timer::at(next_retry), PAIRING_CONFIRMED.wait());
+        let state_change = SHOWING.wait();
+        let status_due = if status_pending {
+            next_status
+        } else {
+            Instant::MAX
+        };
+        let periodic_timer = Timer::at(next_sync.min(next_heartbeat).min(status_due));
+
+        let event =
+            embassy_futures::select::select4(receive, retry_timer, state_change, periodic_timer)
+                .await;
+        let r = match event {
+            embassy_futures::select::Either4::First(r) => r,
+            embassy_futures::select::Either4::Second(retry) => {
+                if let embassy_futures::select::Either::First(()) = retry {
+                    next_retry = Instant::now() + Duration::from_millis(HANDSHAKE_RETRY_MS);
+                }
+                let now = Instant::now().as_millis();
+                if let Some((dst, response)) = pairing.poll(now) {
+                    let message_type = MessageType::HandshakeResponse(response);
+                    send(&mut sender, &mut storage, &dst, message_type).await;
+                }
+
+                if let Some(k) = &mut keying {
+                    let remote = k.exchange.peer();
+                    if let embassy_futures::select::Either::Second(()) = retry {
+                        match k.exchange.confirm() {
+                            Ok(confirm) => {
+                                let message_type = MessageType::KeyConfirm(confirm);
+                                send(&mut sender, &mut storage, &remote, message_type).await;
                             }
+                            Err(e) => println!("can't confirm pairing yet: {:?}", e),
                         }
-                    } else {
+                    } else if let Some(message_type) = k.exchange.pending() {
+                        send(&mut sender, &mut storage, &remote, message_type).await;
+                    }
+
+                    // Stick around for a bit in case the remote didn't hear our confirmation,
+                    // it doesn't hear plaintext once we switch.
+                    if let Some(lmk) = k.exchange.lmk() {
+                        let finished_at = *k.finished_at.get_or_insert(now);
+                        if now.saturating_sub(finished_at) >= CONFIRM_TIMEOUT_MS {
+                            println!("encrypting traffic with remote {:02X?}", remote);
+                            storage.set_lmk(remote, lmk);
+                            add_unicast_peer(manager, remote, Some(lmk));
+                            keying = None;
+                        }
+                    } else if now.saturating_sub(k.started_at) >= KEY_EXCHANGE_TIMEOUT_MS {
                         println!(
-                            "ignore message with bad protocol version; expected {}",
-                            PROTOCOL_VERSION
+                            "pairing not confirmed, remote {:02X?} stays unencrypted",
+                            remote
                         );
+                        keying = None;
                     }
+                } else if let embassy_futures::select::Either::Second(()) = retry {
+                    println!("no remote to confirm pairing with");
                 }
-                Err(e) => println!("failed to decode: {:?}", e),
+                continue;
             }
+            embassy_futures::select::Either4::Third(now_showing) => {
+                // Changes come many times a second while a remote ramps the brightness, the
+                // latest goes out once STATUS_INTERVAL_MS is over.
+                showing = now_showing;
+                if Instant::now() >= next_status {
+                    push_status(&mut sender, &mut storage, &showing).await;
+                    next_status = Instant::now() + Duration::from_millis(STATUS_INTERVAL_MS);
+                } else {
+                    status_pending = true;
+                }
+                continue;
+            }
+            embassy_futures::select::Either4::Fourth(_) => {
+                if status_pending && Instant::now() >= next_status {
+                    push_status(&mut sender, &mut storage, &showing).await;
+                    status_pending = false;
+                    next_status = Instant::now() + Duration::from_millis(STATUS_INTERVAL_MS);
+                }
+                if Instant::now() >= next_sync {
+                    // The network time is that of the remote paired first.
+                    if let Some(remote) = storage.state().remotes.first() {
+                        let remote = remote.mac;
+                        let now = Instant::now().as_micros();
+                        let request = CLOCK.lock(|clock| clock.borrow_mut().request(now));
+                        let message_type = MessageType::TimeRequest(request);
+                        send(&mut sender, &mut storage, &remote, message_type).await;
+                    }
+                    next_sync = Instant::now() + Duration::from_millis(SYNC_INTERVAL_MS);
+                }
+                if Instant::now() >= next_heartbeat {
+                    heartbeat.uptime_ms = Instant::now().as_millis();
+                    let remotes: heapless::Vec<MacAddress, MAX_REMOTES> =
+                        storage.state().remotes.iter().map(|r| r.mac).collect();
+                    for remote in &remotes {
+                        let message_type = MessageType::Heartbeat(heartbeat);
+                        send(&mut sender, &mut storage, remote, message_type).await;
+                    }
+                    heartbeat.seq = heartbeat.seq.wrapping_add(1);
+                    next_heartbeat = Instant::now() + Duration::from_millis(HEARTBEAT_INTERVAL_MS);
+                }
+                continue;
+            }
+        };
+        let received_at = Instant::now().as_micros();
+
+        let src = r.info.src_address;
+        let message = match open(&KEY, &src, r.data()) {
+            Err(OpenError::Auth(_)) => {
+                println!("dropping unauthenticated frame from {:02X?}", src);
+                record_error(ErrorCode::Unauthenticated);
+                continue;
+            }
+            message => message,
+        };
+        links.on_frame(received_at / 1000, src, r.info.rx_control.rssi as i8);
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                println!("failed to decode: {:?}", e);
+                record_error(ErrorCode::Malformed);
+                continue;
+            }
+        };
+
+        // Streamed pixels come too fast to log.
+        if !matches!(message.message_type, MessageType::PixelFrame(_)) {
+            println!("got message: {}", message);
+        }
+        // Relayed messages count as sent by the remote they came from. Only the ones heard
+        // straight from it are acknowledged, acks aren't relayed.
+        let origin = message.origin(src);
+        let direct = message.origin.is_none();
+        if !dedup.accept(received_at / 1000, origin, message.id) {
+            println!("dropping duplicate message");
+            // Acknowledge it again, the sender may have missed the first ack.
+            if direct && needs_ack(&message.message_type) && storage.is_paired(src) {
+                let message_type = MessageType::Ack {
+                    counter: message.counter,
+                };
+                send(&mut sender, &mut storage, &src, message_type).await;
+            }
+            continue;
+        }
+        let addressed = message.destination.matches(&storage.address());
+
+        // Pairing, and discovery, come from remotes that aren't paired yet.
+        match &message.message_type {
+            MessageType::Handshake(handshake) => {
+                let now = Instant::now().as_millis();
+                if let Some(response) = pairing.on_handshake(now, src, handshake) {
+                    add_unicast_peer(manager, src, None);
+                    let message_type = MessageType::HandshakeResponse(response);
+                    send(&mut sender, &mut storage, &src, message_type).await;
+                }
+                continue;
+            }
+            MessageType::HandshakeConfirm(confirm) => {
+                if let Some(paired) = pairing.on_confirm(src, confirm) {
+                    println!("paired with remote {:02X?}", paired);
+                    storage.pair(paired, message.counter);
+                    println!("press BOOT and the pairing button of the remote to confirm");
+                    let mut secret = [0; 32];
+                    rng.read(&mut secret);
+                    keying = Some(Keying {
+                        exchange: KeyExchange::new(
+                            Role::Light,
+                            Efuse::mac_address(),
+                            paired,
+                            secret,
+                        ),
+                        started_at: Instant::now().as_millis(),
+                        finished_at: None,
+                    });
+                }
+                continue;
+            }
+            MessageType::KeyShare(share) => {
+                if let Some(k) = &mut keying {
+                    match k.exchange.on_share(src, share) {
+                        Ok(Some(share)) => {
+                            let message_type = MessageType::KeyShare(share);
+                            send(&mut sender, &mut storage, &src, message_type).await;
+                        }
+                        Ok(None) => {}
+                        Err(e) => println!("dropping key share: {:?}", e),
+                    }
+                }
+                continue;
+            }
+            MessageType::KeyConfirm(confirm) => {
+                if let Some(k) = &mut keying {
+                    match k.exchange.on_confirm(src, confirm) {
+                        Ok(Some(confirm)) => {
+                            let message_type = MessageType::KeyConfirm(confirm);
+                            send(&mut sender, &mut storage, &src, message_type).await;
+                        }
+                        Ok(None) => {}
+                        Err(e) => println!("dropping key confirmation: {:?}", e),
+                    }
+                }
+                continue;
+            }
+            // Answered whether paired or not, so that tools can list every light.
+            MessageType::Discover => {
+                if addressed {
+                    add_unicast_peer(manager, origin, storage.lmk(origin));
+                    let message_type = announce(&storage);
+                    send(&mut sender, &mut storage, &origin, message_type).await;
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let accepted = accept(&mut storage, origin, &message);
+        // Only once accepted, replays would flood the network again as soon as they are out of
+        // the dedup cache.
+        let relay_hops = if accepted && relays(&message.message_type) {
+            storage.config().relay_hops
+        } else {
+            0
+        };
+        if let Some(relayed) = message.relayed(src, relay_hops) {
+            send_message(&mut sender, &BROADCAST_ADDRESS, &relayed).await;
+        }
+        // The fragment completing a transfer stands in for the message it carried from here on,
+        // which is acknowledged, addressed and answered like any other.
+        let message = match &message.message_type {
+            MessageType::Fragment(fragment) if accepted => {
+                let now = Instant::now().as_millis();
+                match reassembler.on_fragment(now, origin, fragment) {
+                    Ok(Some(payload)) => match message.reassembled(payload) {
+                        Ok(message) => {
+                            println!("reassembled message: {}", message);
+                            message
+                        }
+                        Err(e) => {
+                            println!("failed to decode reassembled message: {:?}", e);
+                            record_error(ErrorCode::Malformed);
+                            continue;
+                        }
+                    },
+                    Ok(None) => continue,
+                    Err(e) => {
+                        println!("dropping fragment: {:?}", e);
+                        record_error(ErrorCode::Fragment);
+                        continue;
+                    }
+                }
+            }
+            _ => message,
+        };
+
+        // Acknowledge repeats as well, the remote may have missed the first ack.
+        if direct && needs_ack(&message.message_type) && storage.is_paired(origin) {
+            let message_type = MessageType::Ack {
+                counter: message.counter,
+            };
+            send(&mut sender, &mut storage, &origin, message_type).await;
+        }
+        if !accepted {
+            println!("ignoring message from unpaired remote, or replayed");
+            record_error(ErrorCode::Rejected);
+            continue;
+        }
+
+        match message.message_type {
+            MessageType::SetAddress(address) if addressed => {
+                println!("now at {:?}", address);
+                storage.set_address(address);
+            }
+            MessageType::SceneStore(store) if addressed => {
+                let reply = store_scene(&mut storage, &controls, store);
+                let message_type = MessageType::SceneReply(reply);
+                send(&mut sender, &mut storage, &origin, message_type).await;
+            }
+            MessageType::SceneRecall { slot } if addressed => {
+                let reply = recall_scene(&storage, &mut controls, slot);
+                let message_type = MessageType::SceneReply(reply);
+                send(&mut sender, &mut storage, &origin, message_type).await;
+            }
+            MessageType::PixelFrame(frame) if addressed => {
+                match pixels.on_frame(Instant::now().as_millis(), &frame) {
+                    Ok(true) => PIXELS.signal(*pixels.shown()),
+                    Ok(false) => {}
+                    Err(e) => println!("dropping pixels: {:?}", e),
+                }
+            }
+            MessageType::TimeResponse(response) => {
+                CLOCK.lock(|clock| clock.borrow_mut().on_response(received_at, &response));
+            }
+            MessageType::OtaBegin(_) | MessageType::OtaChunk(_) | MessageType::OtaCommit
+                if addressed =>
+            {
+                let receiver = update.receiver();
+                let status = match &message.message_type {
+                    MessageType::OtaBegin(begin) => Some(receiver.on_begin(begin)),
+                    MessageType::OtaChunk(chunk) => receiver.on_chunk(chunk),
+                    _ => Some(receiver.on_commit()),
+                };
+                if let Some(status) = status {
+                    let message_type = MessageType::OtaStatus(status);
+                    send(&mut sender, &mut storage, &origin, message_type).await;
+                }
+                if update.receiver().is_committed() {
+                    println!("booting the new firmware");
+                    update.boot();
+                }
+            }
+            MessageType::ConfigGet(key) if addressed => {
+                let message_type = MessageType::ConfigReply(Ok(storage.config().get(key)));
+                send(&mut sender, &mut storage, &origin, message_type).await;
+            }
+            MessageType::ConfigSet(entry) if addressed => {
+                let reply = configure(&mut storage, &entry);
+                println!("configured {:?}", reply);
+                let message_type = MessageType::ConfigReply(reply);
+                send(&mut sender, &mut storage, &origin, message_type).await;
+            }
+            MessageType::ConfigList if addressed => {
+                for key in ConfigKey::ALL {
+                    let message_type = MessageType::ConfigReply(Ok(storage.config().get(key)));
+                    send(&mut sender, &mut storage, &origin, message_type).await;
+                }
+            }
+            MessageType::SceneList if addressed => {
+                let message_type = MessageType::SceneReply(Ok(storage.scenes().slots()));
+                send(&mut sender, &mut storage, &origin, message_type).await;
+            }
+            MessageType::StatusRequest if addressed => {
+                send(&mut sender, &mut storage, &origin, status(&showing)).await;
+            }
+            // Only heartbeats heard straight from the remote tell about the link to it.
+            MessageType::Heartbeat(heartbeat) if direct => {
+                if links.on_heartbeat(src, &heartbeat) {
+                    println!(
+                        "remote {:02X?} restarted: {:?}",
+                        src, heartbeat.reset_reason
+                    );
+                }
+            }
+            MessageType::LinkStatsRequest { peer } if addressed => {
+                let now = Instant::now().as_millis();
+                let message_type = MessageType::LinkStats {
+                    peer,
+                    stats: links.stats(now, peer),
+                };
+                send(&mut sender, &mut storage, &origin, message_type).await;
+            }
+            message_type if addressed => act_on(&mut controls, message_type),
+            _ => {}
         }
     }
 }
@@ -220,18 +1028,21 @@ async fn main(spawner: Spawner) {
 
     let freq = Rate::from_mhz(80);
     let rmt = Rmt::new(peripherals.RMT, freq).unwrap();
-    let rmt_buffer = smart_led_buffer!(8);
+    let rmt_buffer = smart_led_buffer!(MAX_LEDS);
 
-    let led1 = SmartLedsAdapter::new(rmt.channel0, peripherals.GPIO35, rmt_buffer);
-    let led2 = SmartLedsAdapter::new(rmt.channel1, peripherals.GPIO36, rmt_buffer);
-    let led3 = SmartLedsAdapter::new(rmt.channel2, peripherals.GPIO38, rmt_buffer);
-    let led4 = SmartLedsAdapter::new(rmt.channel3, peripherals.GPIO37, rmt_buffer);
+    let strips = Strips {
+        led1: SmartLedsAdapter::new(rmt.channel0, peripherals.GPIO35, rmt_buffer),
+        led2: SmartLedsAdapter::new(rmt.channel1, peripherals.GPIO36, rmt_buffer),
+        led3: SmartLedsAdapter::new(rmt.channel2, peripherals.GPIO38, rmt_buffer),
+        led4: SmartLedsAdapter::new(rmt.channel3, peripherals.GPIO37, rmt_buffer),
+    };
 
     let timg0 = TimerGroup::new(peripherals.TIMG0);
 
+    let rng = Rng::new(peripherals.RNG);
     let esp_wifi_ctrl = &*mk_static!(
         EspWifiController<'static>,
-        init(timg0.timer0, Rng::new(peripherals.RNG)).unwrap()
+        init(timg0.timer0, rng.clone()).unwrap()
     );
 
     let wifi = peripherals.WIFI;
@@ -239,8 +1050,12 @@ async fn main(spawner: Spawner) {
     controller.set_mode(esp_wifi::wifi::WifiMode::Sta).unwrap();
     controller.start().unwrap();
 
+    let storage = Storage::load();
+    println!("loaded state: {:?}", storage.state());
+    CONFIG.lock(|config| config.set(Some(*storage.config())));
+
     let esp_now = interfaces.esp_now;
-    esp_now.set_channel(11).unwrap();
+    esp_now.set_channel(storage.config().channel).unwrap();
 
     println!("esp-now version {}", esp_now.version().unwrap());
 
@@ -256,11 +1071,23 @@ async fn main(spawner: Spawner) {
             .unwrap();
     }
 
-    let (manager, _, receiver) = esp_now.split();
+    let update = Update::new(mk_static!(FlashStorage, FlashStorage::new()));
+    // The radio is up, so this image can take the next update.
+    update.mark_valid();
+
+    let boot_button = Input::new(
+        peripherals.GPIO0,
+        InputConfig::default().with_pull(Pull::Up),
+    );
+
+    let (manager, sender, receiver) = esp_now.split();
     let manager = mk_static!(EspNowManager<'static>, manager);
 
-    spawner.spawn(listener(manager, receiver)).ok();
-    spawner.spawn(light_task(led1, led2, led3, led4)).unwrap();
+    spawner
+        .spawn(listener(manager, sender, receiver, storage, update, rng))
+        .ok();
+    spawner.spawn(pairing_button(boot_button)).unwrap();
+    spawner.spawn(light_task(strips)).unwrap();
 
     let mut ticker = Ticker::every(Duration::from_secs(1));
     loop {
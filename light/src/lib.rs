@@ -1 +1,5 @@
 #![no_std]
+
+pub mod animation;
+pub mod storage;
+pub mod update;
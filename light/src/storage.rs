@@ -0,0 +1,207 @@
+//! State the light keeps across reboots.
+
+use esp_hal::efuse::Efuse;
+use esp_println::println;
+use esp_storage::FlashStorage;
+use serde::{Deserialize, Serialize};
+use spark_messages::{
+    Address, Config, ConfigEntry, ConfigError, Journal, Lmk, MacAddress, MessageId, MessageIds,
+    ReceiveWindow, Scene, SceneBook, SceneError, SceneSet, SendCounter,
+};
+
+/// Most remotes a light can be paired with. Pairing another one forgets the oldest.
+pub const MAX_REMOTES: usize = 4;
+
+/// The `nvs` partition of `partitions.csv`, which nothing else uses.
+const STORAGE_OFFSET: u32 = 0x9000;
+const STORAGE_LEN: u32 = 0x6000;
+
+/// Enough for a [`State`] with every scene slot full and every remote keyed.
+const STATE_BUF_LEN: usize = 512;
+
+/// Stored ahead of the [`State`], and bumped whenever its layout changes, so that a state stored by
+/// other firmware is never read as this one.
+const STATE_FORMAT: u8 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PairedRemote {
+    pub mac: MacAddress,
+    pub replay: ReceiveWindow,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    pub counter_reservation: u32,
+    pub remotes: heapless::Vec<PairedRemote, MAX_REMOTES>,
+    /// `None` until configured, see [`Address::unconfigured`].
+    pub address: Option<Address>,
+    pub config: Config,
+    pub scenes: SceneBook,
+    /// Keys traffic with paired remotes is encrypted with, see [`spark_messages::KeyExchange`].
+    /// Remotes without one send plaintext.
+    pub lmks: heapless::Vec<(MacAddress, Lmk), MAX_REMOTES>,
+}
+
+pub struct Storage {
+    journal: Journal<FlashStorage>,
+    state: State,
+    counter: SendCounter,
+    ids: MessageIds,
+}
+
+impl Storage {
+    pub fn load() -> Self {
+        let mut journal = Journal::new(FlashStorage::new(), STORAGE_OFFSET, STORAGE_LEN).unwrap();
+        let mut buf = [0; STATE_BUF_LEN];
+        let state = match journal.load::<u8>(&mut buf) {
+            Ok(Some(STATE_FORMAT)) => journal
+                .load::<(u8, State)>(&mut buf)
+                .map(|stored| stored.map(|(_, state)| state)),
+            Ok(Some(format)) => {
+                println!("unknown state format {}, starting over", format);
+                Ok(None)
+            }
+            stored => stored.map(|_| None),
+        };
+        let state = match state {
+            Ok(state) => state.unwrap_or_default(),
+            Err(e) => {
+                println!("failed to load state, starting over: {:?}", e);
+                State::default()
+            }
+        };
+
+        Self {
+            journal,
+            counter: SendCounter::resume(state.counter_reservation),
+            ids: MessageIds::resume(state.counter_reservation),
+            state,
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Counter for the next message this light sends.
+    pub fn next_counter(&mut self) -> u32 {
+        let (counter, reservation) = self.counter.next_counter();
+        if let Some(reservation) = reservation {
+            self.state.counter_reservation = reservation;
+            self.persist();
+        }
+        counter
+    }
+
+    /// ID for the next message this light sends, shared by all copies of it.
+    pub fn next_id(&mut self) -> MessageId {
+        self.ids.next_id()
+    }
+
+    /// Remember a newly paired remote. `counter` is the one of the message that completed
+    /// pairing, anything up to it is rejected from now on. The remote sends plaintext until
+    /// [`Self::set_lmk`].
+    pub fn pair(&mut self, mac: MacAddress, counter: u32) {
+        self.state.remotes.retain(|remote| remote.mac != mac);
+        if self.state.remotes.is_full() {
+            self.state.remotes.remove(0);
+        }
+        let remotes = &self.state.remotes;
+        self.state
+            .lmks
+            .retain(|(remote, _)| *remote != mac && remotes.iter().any(|r| r.mac == *remote));
+        self.state
+            .remotes
+            .push(PairedRemote {
+                mac,
+                replay: ReceiveWindow::resume(counter),
+            })
+            .ok();
+        self.persist();
+    }
+
+    /// The key traffic with the remote at `mac` is encrypted with, if any.
+    pub fn lmk(&self, mac: MacAddress) -> Option<Lmk> {
+        let (_, lmk) = self.state.lmks.iter().find(|(remote, _)| *remote == mac)?;
+        Some(*lmk)
+    }
+
+    /// Encrypt traffic with the paired remote at `mac` with `lmk` from now on.
+    pub fn set_lmk(&mut self, mac: MacAddress, lmk: Lmk) {
+        if !self.is_paired(mac) {
+            return;
+        }
+        self.state.lmks.retain(|(remote, _)| *remote != mac);
+        // Every key belongs to a paired remote, so there's room.
+        self.state.lmks.push((mac, lmk)).ok();
+        self.persist();
+    }
+
+    /// The address this light answers to.
+    pub fn address(&self) -> Address {
+        self.state
+            .address
+            .unwrap_or_else(|| Address::unconfigured(Efuse::mac_address()))
+    }
+
+    pub fn set_address(&mut self, address: Address) {
+        self.state.address = Some(address);
+        self.persist();
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.state.config
+    }
+
+    /// Change a setting, if the value checks out.
+    pub fn set_config(&mut self, entry: &ConfigEntry) -> Result<ConfigEntry, ConfigError> {
+        let entry = self.state.config.set(entry)?;
+        self.persist();
+        Ok(entry)
+    }
+
+    pub fn scenes(&self) -> &SceneBook {
+        &self.state.scenes
+    }
+
+    /// Save a scene in `slot`, returning the slots holding one.
+    pub fn store_scene(&mut self, slot: u8, scene: Scene) -> Result<SceneSet, SceneError> {
+        let slots = self.state.scenes.store(slot, scene)?;
+        self.persist();
+        Ok(slots)
+    }
+
+    pub fn is_paired(&self, mac: MacAddress) -> bool {
+        self.state.remotes.iter().any(|r| r.mac == mac)
+    }
+
+    /// Whether to act on an authenticated message, i.e. it comes from a paired remote and isn't a
+    /// replay.
+    pub fn accept(&mut self, src: MacAddress, counter: u32) -> bool {
+        let Some(remote) = self.state.remotes.iter_mut().find(|r| r.mac == src) else {
+            return false;
+        };
+        let (accepted, reservation) = remote.replay.accept(counter);
+        if reservation.is_some() {
+            self.persist();
+        }
+        accepted
+    }
+
+    /// [`Storage::accept`] for messages that come too often to persist even a reservation for:
+    /// streamed pixels, held buttons and firmware chunks. One replayed after a reboot only shows
+    /// stale pixels, ramps a step or gets a chunk checked against the image's hash.
+    pub fn accept_frequent(&mut self, src: MacAddress, counter: u32) -> bool {
+        let Some(remote) = self.state.remotes.iter_mut().find(|r| r.mac == src) else {
+            return false;
+        };
+        remote.replay.accept_unreserved(counter)
+    }
+
+    fn persist(&mut self) {
+        let mut buf = [0; STATE_BUF_LEN];
+        if let Err(e) = self.journal.store(&(STATE_FORMAT, &self.state), &mut buf) {
+            println!("failed to store state: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Firmware updates, received into the OTA slot that isn't running.
+
+use esp_bootloader_esp_idf::ota::{Ota, OtaImageState, Slot};
+use esp_bootloader_esp_idf::partitions::{
+    self, AppPartitionSubType, DataPartitionSubType, FlashRegion, PARTITION_TABLE_MAX_LEN,
+    PartitionType,
+};
+use esp_storage::FlashStorage;
+use spark_messages::OtaReceiver;
+
+pub struct Update {
+    receiver: OtaReceiver<FlashRegion<'static, FlashStorage>>,
+    slot: Slot,
+}
+
+impl Update {
+    /// Get ready to receive an update into the slot that isn't running, see `partitions.csv`.
+    pub fn new(flash: &'static mut FlashStorage) -> Self {
+        let mut buf = [0; PARTITION_TABLE_MAX_LEN];
+        let table = partitions::read_partition_table(flash, &mut buf).unwrap();
+        let slot = match with_ota(|ota| ota.current_slot().unwrap()) {
+            // Blank otadata boots ota_0, there's no factory app to fall back on.
+            Slot::None => Slot::Slot1,
+            running => running.next(),
+        };
+        let subtype = match slot {
+            Slot::Slot1 => AppPartitionSubType::Ota1,
+            _ => AppPartitionSubType::Ota0,
+        };
+        let partition = table
+            .find_partition(PartitionType::App(subtype))
+            .unwrap()
+            .unwrap();
+
+        let len = partition.len();
+        Self {
+            receiver: OtaReceiver::new(partition.as_embedded_storage(flash), 0, len),
+            slot,
+        }
+    }
+
+    pub fn receiver(&mut self) -> &mut OtaReceiver<FlashRegion<'static, FlashStorage>> {
+        &mut self.receiver
+    }
+
+    /// Keep booting the running image. Until then, a bootloader with rollback enabled goes back to
+    /// the previous image on the next reset.
+    pub fn mark_valid(&self) {
+        with_ota(|ota| {
+            if let Ok(OtaImageState::New | OtaImageState::PendingVerify) = ota.current_ota_state() {
+                ota.set_current_ota_state(OtaImageState::Valid).unwrap();
+            }
+        });
+    }
+
+    /// Boot the received image from now on, and reboot into it. Only call once the receiver
+    /// [committed](OtaReceiver::is_committed) it.
+    pub fn boot(&self) -> ! {
+        with_ota(|ota| {
+            ota.set_current_slot(self.slot).unwrap();
+            ota.set_current_ota_state(OtaImageState::New).unwrap();
+        });
+        esp_hal::system::software_reset()
+    }
+}
+
+/// Run `f` on the `otadata` partition, which says which slot to boot.
+fn with_ota<R>(f: impl FnOnce(&mut Ota<'_, FlashStorage>) -> R) -> R {
+    let mut flash = FlashStorage::new();
+    let mut buf = [0; PARTITION_TABLE_MAX_LEN];
+    let table = partitions::read_partition_table(&mut flash, &mut buf).unwrap();
+    let partition = table
+        .find_partition(PartitionType::Data(DataPartitionSubType::Ota))
+        .unwrap()
+        .unwrap();
+    let mut region = partition.as_embedded_storage(&mut flash);
+    f(&mut Ota::new(&mut region).unwrap())
+}
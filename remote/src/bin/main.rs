@@ -6,14 +6,68 @@ use esp_backtrace as _;
 
 use async_button::{Button, ButtonConfig, ButtonEvent};
 use embassy_executor::Spawner;
+use embassy_time::{Duration, Instant, Timer};
 use esp_hal::clock::CpuClock;
+use esp_hal::efuse::Efuse;
 use esp_hal::gpio::{Input, InputConfig, Pull};
 use esp_hal::rng::Rng;
+use esp_hal::rtc_cntl::{SocResetReason, reset_reason};
+use esp_hal::system::Cpu;
 use esp_hal::timer::timg::TimerGroup;
 use esp_println::println;
-use esp_wifi::esp_now::{BROADCAST_ADDRESS, PeerInfo};
+use esp_wifi::esp_now::{BROADCAST_ADDRESS, EspNow, PeerInfo};
 use esp_wifi::{EspWifiController, init};
-use spark_messages::{ButtonNumber, Message, MessageType, PROTOCOL_VERSION};
+use remote::buttons::{EDGES, Watched};
+use remote::storage::{MAX_LIGHTS, Storage};
+use spark_messages::{
+    ButtonEventType, ButtonNumber, CONFIRM_TIMEOUT_MS, DeliveryEvent, Destination, Gesture,
+    GestureDetector, HANDSHAKE_RETRY_MS, HEARTBEAT_INTERVAL_MS, Heartbeat, HoldRepeater,
+    InitiatorState, Inventory, KEY_EXCHANGE_TIMEOUT_MS, Key, KeyExchange, LinkMonitor, Lmk,
+    MAX_FRAME_LEN, MacAddress, Message, MessageType, OpenError, PairingInitiator, ResetReason,
+    Retransmitter, RetryPolicy, Role, SceneStore, open, parse_key, sign,
+};
+
+/// Pre-shared key of this installation, as 64 hex digits. Remotes and lights only talk to devices
+/// built with the same key.
+const KEY: Key = parse_key(env!("SPARK_KEY"));
+
+/// Wi-Fi channel the remote talks to lights on, the default of
+/// [`spark_messages::ConfigKey::Channel`]. Lights don't let it be changed.
+const CHANNEL: u8 = 11;
+
+/// Long-pressing this button looks for another light to pair with, and pressing it once paired
+/// confirms pairing. Holding it doesn't ramp.
+const PAIRING_BUTTON: ButtonNumber = ButtonNumber::Button4;
+
+/// How long a button is held before lights start ramping.
+const RAMP_DELAY_MS: u64 = 500;
+
+/// How often lights are told to keep ramping while a button is held. More often ramps more
+/// smoothly, and keeps the air busier.
+const RAMP_INTERVAL_MS: u64 = 100;
+
+/// Holding this button together with button N saves what the lights show as scene N - 1, and
+/// tapping it then button N recalls that scene. Not the pairing button, whose long press would
+/// interrupt the chord.
+const SCENE_BUTTON: ButtonNumber = ButtonNumber::Button1;
+
+/// Which lights chords and sequences of buttons go to.
+const GESTURE_DESTINATION: Destination = Destination::All;
+
+/// Which lights each button controls, in button order. Lights are members of every group until
+/// configured otherwise.
+const BUTTON_DESTINATIONS: [Destination; 4] = [
+    Destination::Groups(1 << 0),
+    Destination::Groups(1 << 1),
+    Destination::Groups(1 << 2),
+    Destination::All,
+];
+
+/// Most lights kept track of after discovering them, paired or not.
+const MAX_DISCOVERED: usize = 16;
+
+/// Most button events waiting for an acknowledgement at a time, across all lights.
+const MAX_PENDING: usize = 16;
 
 // When you are okay with using a nightly compiler it's better to use https://docs.rs/static_cell/2.1.0/static_cell/macro.make_static.html
 macro_rules! mk_static {
@@ -25,6 +79,250 @@ macro_rules! mk_static {
     }};
 }
 
+/// Register a light to unicast to, encrypting traffic with `lmk` if there's one. Registering it
+/// again switches to the new key, or back to plaintext.
+fn add_unicast_peer(esp_now: &mut EspNow<'static>, peer_address: MacAddress, lmk: Option<Lmk>) {
+    let peer = PeerInfo {
+        interface: esp_wifi::esp_now::EspNowWifiInterface::Sta,
+        peer_address,
+        lmk,
+        channel: None,
+        encrypt: lmk.is_some(),
+    };
+    if esp_now.peer_exists(&peer_address) {
+        esp_now.modify_peer(peer).unwrap();
+    } else {
+        esp_now.add_peer(peer).unwrap();
+    }
+}
+
+async fn send_message(esp_now: &mut EspNow<'static>, dst: &MacAddress, message: &Message) {
+    let mut tx_buf: [u8; MAX_FRAME_LEN] = [0; MAX_FRAME_LEN];
+    let len = message.encode(&mut tx_buf).unwrap().len();
+    let data = sign(&KEY, &Efuse::mac_address(), &mut tx_buf, len).unwrap();
+
+    if let Err(e) = esp_now.send_async(dst, data).await {
+        println!("failed to send: {:?}", e);
+    }
+}
+
+async fn send(
+    esp_now: &mut EspNow<'static>,
+    storage: &mut Storage,
+    dst: &MacAddress,
+    message_type: MessageType,
+) {
+    let message = Message::new(message_type, storage.next_counter()).with_id(storage.next_id());
+    send_message(esp_now, dst, &message).await;
+}
+
+/// Send to every paired light, retrying until each acknowledges.
+async fn send_to_lights(
+    esp_now: &mut EspNow<'static>,
+    storage: &mut Storage,
+    retransmitter: &mut Retransmitter<Message, MAX_PENDING>,
+    destination: Destination,
+    message_type: MessageType,
+) {
+    let lights = storage.state().lights.clone();
+    let id = storage.next_id();
+    for light_mac in &lights {
+        let message = Message::new(message_type.clone(), storage.next_counter())
+            .with_destination(destination)
+            .with_id(id);
+        send_message(esp_now, light_mac, &message).await;
+        let now = Instant::now().as_millis();
+        if retransmitter
+            .sent(now, *light_mac, message.counter, message)
+            .is_err()
+        {
+            println!("too many unacknowledged messages, not retrying this one");
+        }
+    }
+}
+
+/// Send to every paired light once, for events that would be stale by the time a retry went out.
+async fn send_to_lights_once(
+    esp_now: &mut EspNow<'static>,
+    storage: &mut Storage,
+    destination: Destination,
+    message_type: MessageType,
+) {
+    let lights = storage.state().lights.clone();
+    let id = storage.next_id();
+    for light_mac in &lights {
+        let message = Message::new(message_type.clone(), storage.next_counter())
+            .with_destination(destination)
+            .with_id(id);
+        send_message(esp_now, light_mac, &message).await;
+    }
+}
+
+fn boot_reason() -> ResetReason {
+    match reset_reason(Cpu::ProCpu) {
+        Some(SocResetReason::ChipPowerOn) => ResetReason::PowerOn,
+        Some(SocResetReason::CoreSw) => ResetReason::Software,
+        Some(
+            SocResetReason::CoreMwdt0 | SocResetReason::CoreRtcWdt | SocResetReason::SysRtcWdt,
+        ) => ResetReason::Watchdog,
+        Some(SocResetReason::SysBrownOut) => ResetReason::Brownout,
+        Some(SocResetReason::CoreDeepSleep) => ResetReason::DeepSleep,
+        _ => ResetReason::Other,
+    }
+}
+
+/// The scene message `gesture` stands for, if any, see [`SCENE_BUTTON`].
+fn scene_message(gesture: &Gesture) -> Option<MessageType> {
+    match gesture {
+        Gesture::Chord(buttons) if buttons.len() == 2 && buttons.contains(SCENE_BUTTON) => {
+            let button = buttons.iter().find(|&button| button != SCENE_BUTTON)?;
+            Some(MessageType::SceneStore(SceneStore {
+                slot: button as u8,
+                scene: None,
+            }))
+        }
+        Gesture::Sequence(sequence) => match sequence.buttons[..] {
+            [SCENE_BUTTON, button] => Some(MessageType::SceneRecall { slot: button as u8 }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Look for a light in pairing mode, then agree on a key to encrypt traffic with once the user
+/// confirmed pairing with `pairing_button` and on the light. On success the light is registered as
+/// a unicast peer, with the key unless the user didn't confirm in time.
+async fn pair(
+    esp_now: &mut EspNow<'static>,
+    storage: &mut Storage,
+    pairing_button: &mut Button<Watched<Input<'static>>>,
+    rng: &mut Rng,
+) -> Option<(MacAddress, Option<Lmk>)> {
+    let mut pairing = PairingInitiator::new(Efuse::mac_address());
+    let handshake = pairing.start(Instant::now().as_millis());
+    let message_type = MessageType::Handshake(handshake);
+    send(esp_now, storage, &BROADCAST_ADDRESS, message_type).await;
+
+    let mut paired_at = None;
+    let mut key_exchange: Option<KeyExchange> = None;
+    let mut keyed_at = None;
+    loop {
+        let receive = esp_now.receive_async();
+        let retry_timer = Timer::after(Duration::from_millis(HANDSHAKE_RETRY_MS));
+
+        match embassy_futures::select::select3(receive, retry_timer, pairing_button.update()).await
+        {
+            embassy_futures::select::Either3::First(r) => {
+                let src = r.info.src_address;
+                let message = open(&KEY, &src, r.data()).ok();
+                let reply = match message {
+                    Some(Message {
+                        message_type: MessageType::HandshakeResponse(response),
+                        ..
+                    }) => {
+                        let confirm = pairing.on_response(src, &response);
+                        if confirm.is_some() {
+                            add_unicast_peer(esp_now, src, None);
+                            paired_at.get_or_insert(Instant::now());
+                        }
+                        if confirm.is_some() && key_exchange.is_none() {
+                            println!("press the pairing button to confirm, and BOOT on the light");
+                            let mut secret = [0; 32];
+                            rng.read(&mut secret);
+                            let mac = Efuse::mac_address();
+                            key_exchange = Some(KeyExchange::new(Role::Remote, mac, src, secret));
+                        }
+                        confirm.map(MessageType::HandshakeConfirm)
+                    }
+                    Some(Message {
+                        message_type: MessageType::KeyShare(share),
+                        ..
+                    }) => match key_exchange.as_mut().map(|e| e.on_share(src, &share)) {
+                        Some(Ok(share)) => share.map(MessageType::KeyShare),
+                        Some(Err(e)) => {
+                            println!("dropping key share: {:?}", e);
+                            None
+                        }
+                        None => None,
+                    },
+                    Some(Message {
+                        message_type: MessageType::KeyConfirm(confirm),
+                        ..
+                    }) => match key_exchange.as_mut().map(|e| e.on_confirm(src, &confirm)) {
+                        Some(Ok(confirm)) => confirm.map(MessageType::KeyConfirm),
+                        Some(Err(e)) => {
+                            println!("dropping key confirmation: {:?}", e);
+                            None
+                        }
+                        None => None,
+                    },
+                    _ => None,
+                };
+                if let Some(message_type) = reply {
+                    send(esp_now, storage, &src, message_type).await;
+                }
+            }
+            embassy_futures::select::Either3::Second(_) => {
+                if let Some(exchange) = &key_exchange {
+                    if let Some(message_type) = exchange.pending() {
+                        send(esp_now, storage, &exchange.peer(), message_type).await;
+                    }
+                }
+            }
+            embassy_futures::select::Either3::Third(ButtonEvent::ShortPress { .. }) => {
+                if let Some(exchange) = &mut key_exchange {
+                    match exchange.confirm() {
+                        Ok(confirm) => {
+                            let message_type = MessageType::KeyConfirm(confirm);
+                            send(esp_now, storage, &exchange.peer(), message_type).await;
+                        }
+                        Err(e) => println!("can't confirm pairing yet: {:?}", e),
+                    }
+                }
+            }
+            embassy_futures::select::Either3::Third(_) => {}
+        }
+
+        if let Some(handshake) = pairing.poll(Instant::now().as_millis()) {
+            let message_type = MessageType::Handshake(handshake);
+            send(esp_now, storage, &BROADCAST_ADDRESS, message_type).await;
+        }
+
+        match pairing.state() {
+            InitiatorState::Paired {
+                light_mac,
+                capabilities,
+            } => {
+                let lmk = key_exchange.as_ref().and_then(KeyExchange::lmk);
+                if lmk.is_some() {
+                    keyed_at.get_or_insert(Instant::now());
+                }
+                // Stick around for a bit in case the light didn't hear our last confirmation, it
+                // doesn't hear plaintext once we switch.
+                let keyed = keyed_at
+                    .is_some_and(|t| t.elapsed() >= Duration::from_millis(CONFIRM_TIMEOUT_MS));
+                let timed_out = lmk.is_none()
+                    && paired_at.is_some_and(|t| {
+                        t.elapsed() >= Duration::from_millis(KEY_EXCHANGE_TIMEOUT_MS)
+                    });
+                if keyed || timed_out {
+                    if timed_out {
+                        println!("pairing not confirmed, traffic with the light stays unencrypted");
+                    }
+                    println!("paired with light {:02X?}: {:?}", light_mac, capabilities);
+                    add_unicast_peer(esp_now, light_mac, lmk);
+                    return Some((light_mac, lmk));
+                }
+            }
+            InitiatorState::TimedOut => {
+                println!("no light to pair with");
+                return None;
+            }
+            _ => {}
+        }
+    }
+}
+
 #[esp_hal_embassy::main]
 async fn main(_spawner: Spawner) {
     // generator version: 0.2.2
@@ -41,9 +339,10 @@ async fn main(_spawner: Spawner) {
 
     let timg0 = TimerGroup::new(peripherals.TIMG0);
 
+    let mut rng = Rng::new(peripherals.RNG);
     let esp_wifi_ctrl = &*mk_static!(
         EspWifiController<'static>,
-        init(timg0.timer0, Rng::new(peripherals.RNG)).unwrap()
+        init(timg0.timer0, rng.clone()).unwrap()
     );
 
     let wifi = peripherals.WIFI;
@@ -52,16 +351,20 @@ async fn main(_spawner: Spawner) {
     controller.start().unwrap();
 
     let mut esp_now = interfaces.esp_now;
-    esp_now.set_channel(11).unwrap();
+    esp_now.set_channel(CHANNEL).unwrap();
 
     println!("esp-now version {}", esp_now.version().unwrap());
 
     let c = InputConfig::default().with_pull(Pull::Up);
 
-    let mut async_button = Button::new(Input::new(peripherals.GPIO21, c), ButtonConfig::default());
-    let mut async_button2 = Button::new(Input::new(peripherals.GPIO0, c), ButtonConfig::default());
-    let mut async_button3 = Button::new(Input::new(peripherals.GPIO14, c), ButtonConfig::default());
-    let mut async_button4 = Button::new(Input::new(peripherals.GPIO35, c), ButtonConfig::default());
+    let button1 = Watched::new(Input::new(peripherals.GPIO21, c), ButtonNumber::Button1);
+    let button2 = Watched::new(Input::new(peripherals.GPIO0, c), ButtonNumber::Button2);
+    let button3 = Watched::new(Input::new(peripherals.GPIO14, c), ButtonNumber::Button3);
+    let button4 = Watched::new(Input::new(peripherals.GPIO35, c), ButtonNumber::Button4);
+    let mut async_button = Button::new(button1, ButtonConfig::default());
+    let mut async_button2 = Button::new(button2, ButtonConfig::default());
+    let mut async_button3 = Button::new(button3, ButtonConfig::default());
+    let mut async_button4 = Button::new(button4, ButtonConfig::default());
 
     if !esp_now.peer_exists(&BROADCAST_ADDRESS) {
         esp_now
@@ -75,14 +378,81 @@ async fn main(_spawner: Spawner) {
             .unwrap();
     }
 
+    let mut storage = Storage::load();
+    println!("loaded state: {:?}", storage.state());
+    // Lights may have restarted while this remote was off.
+    storage.skip_counters();
+    for &light_mac in &storage.state().lights {
+        add_unicast_peer(&mut esp_now, light_mac, storage.lmk(light_mac));
+    }
+
+    if storage.state().lights.is_empty() {
+        let paired = pair(&mut esp_now, &mut storage, &mut async_button4, &mut rng).await;
+        if let Some((light_mac, lmk)) = paired {
+            storage.pair(light_mac, lmk);
+        }
+    }
+
+    let mut retransmitter = Retransmitter::<Message, MAX_PENDING>::new(RetryPolicy::default());
+    let mut inventory = Inventory::<MAX_DISCOVERED>::new();
+    let mut links = LinkMonitor::<MAX_DISCOVERED>::new();
+    let mut gestures = GestureDetector::default();
+    let mut holds = HoldRepeater::new(RAMP_DELAY_MS, RAMP_INTERVAL_MS);
+    // Lights keep link statistics on the remote from these, like it does on them.
+    let mut next_heartbeat = Instant::now().as_millis();
+    let mut heartbeat = Heartbeat {
+        seq: 0,
+        uptime_ms: 0,
+        reset_reason: boot_reason(),
+    };
+    send(
+        &mut esp_now,
+        &mut storage,
+        &BROADCAST_ADDRESS,
+        MessageType::Discover,
+    )
+    .await;
+
     loop {
-        let event1 = async_button.update();
-        let event2 = async_button2.update();
-        let event3 = async_button3.update();
-        let event4 = async_button4.update();
+        let buttons = embassy_futures::select::select4(
+            async_button.update(),
+            async_button2.update(),
+            async_button3.update(),
+            async_button4.update(),
+        );
+        let next_due_at = retransmitter
+            .next_due_at()
+            .map_or(next_heartbeat, |at| at.min(next_heartbeat));
+        let periodic_timer = Timer::at(Instant::from_millis(next_due_at));
 
-        let event_data: (ButtonNumber, ButtonEvent) =
-            match embassy_futures::select::select4(event1, event2, event3, event4).await {
+        // Wakes up with the next edge, or `None` once a sequence of taps is over or a held button
+        // is due to repeat.
+        let edges_due_at = [gestures.next_due_at(), holds.next_due_at()]
+            .into_iter()
+            .flatten()
+            .min();
+        let edge = async {
+            match edges_due_at {
+                Some(at) => {
+                    let timer = Timer::at(Instant::from_millis(at));
+                    match embassy_futures::select::select(EDGES.receive(), timer).await {
+                        embassy_futures::select::Either::First(edge) => Some(edge),
+                        embassy_futures::select::Either::Second(_) => None,
+                    }
+                }
+                None => Some(EDGES.receive().await),
+            }
+        };
+
+        let event_data: (ButtonNumber, ButtonEvent) = match embassy_futures::select::select4(
+            buttons,
+            esp_now.receive_async(),
+            periodic_timer,
+            edge,
+        )
+        .await
+        {
+            embassy_futures::select::Either4::First(event) => match event {
                 embassy_futures::select::Either4::First(e) => {
                     println!("button1: {:?}", e);
                     (ButtonNumber::Button1, e)
@@ -99,22 +469,209 @@ async fn main(_spawner: Spawner) {
                     println!("button4: {:?}", e);
                     (ButtonNumber::Button4, e)
                 }
-            };
-
-        let mut tx_bux: [u8; 32] = [0; 32];
-        let message = Message {
-            protocol_version: PROTOCOL_VERSION,
-            message_type: MessageType::ButtonEvent {
-                button_number: event_data.0,
-                event_type: event_data.1.into(),
             },
+            embassy_futures::select::Either4::Second(r) => {
+                let received_at = Instant::now().as_micros();
+                let src = r.info.src_address;
+                let message = open(&KEY, &src, r.data());
+                if !matches!(message, Err(OpenError::Auth(_))) {
+                    links.on_frame(received_at / 1000, src, r.info.rx_control.rssi as i8);
+                }
+                let message = message.ok();
+                match message {
+                    Some(Message {
+                        message_type: MessageType::Ack { counter },
+                        ..
+                    }) => {
+                        retransmitter.on_ack(src, counter);
+                    }
+                    Some(Message {
+                        message_type: MessageType::LightStatus(status),
+                        ..
+                    }) => {
+                        println!("light {:02X?}: {:?}", src, status);
+                    }
+                    Some(Message {
+                        message_type: MessageType::Announce(announce),
+                        ..
+                    }) => {
+                        if inventory.on_announce(src, &announce) {
+                            println!("found light {:02X?}: {:?}", src, announce);
+                        }
+                    }
+                    Some(Message {
+                        message_type: MessageType::ConfigReply(reply),
+                        ..
+                    }) => {
+                        println!("light {:02X?} config: {:?}", src, reply);
+                    }
+                    Some(Message {
+                        message_type: MessageType::SceneReply(reply),
+                        ..
+                    }) => {
+                        println!("light {:02X?} scenes: {:?}", src, reply);
+                    }
+                    Some(Message {
+                        message_type: MessageType::Heartbeat(heartbeat),
+                        ..
+                    }) => {
+                        if links.on_heartbeat(src, &heartbeat) {
+                            println!("light {:02X?} restarted: {:?}", src, heartbeat.reset_reason);
+                            storage.skip_counters();
+                        }
+                    }
+                    Some(Message {
+                        message_type: MessageType::LinkStats { peer, stats },
+                        ..
+                    }) => {
+                        println!("light {:02X?} link to {:02X?}: {:?}", src, peer, stats);
+                    }
+                    // Answered to anyone, like lights answer discovery, as it only tells how well
+                    // the remote hears `peer`.
+                    Some(Message {
+                        message_type: MessageType::LinkStatsRequest { peer },
+                        ..
+                    }) => {
+                        if !esp_now.peer_exists(&src) {
+                            add_unicast_peer(&mut esp_now, src, None);
+                        }
+                        let message_type = MessageType::LinkStats {
+                            peer,
+                            stats: links.stats(received_at / 1000, peer),
+                        };
+                        send(&mut esp_now, &mut storage, &src, message_type).await;
+                    }
+                    // Lights synchronise their clocks to ours.
+                    Some(Message {
+                        message_type: MessageType::TimeRequest(request),
+                        ..
+                    }) if storage.state().lights.contains(&src) => {
+                        let response = request.respond(received_at, Instant::now().as_micros());
+                        let message_type = MessageType::TimeResponse(response);
+                        send(&mut esp_now, &mut storage, &src, message_type).await;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            embassy_futures::select::Either4::Third(_) => {
+                let now = Instant::now().as_millis();
+                if now >= next_heartbeat {
+                    heartbeat.uptime_ms = now;
+                    for light_mac in storage.state().lights.clone() {
+                        let message_type = MessageType::Heartbeat(heartbeat);
+                        send(&mut esp_now, &mut storage, &light_mac, message_type).await;
+                    }
+                    heartbeat.seq = heartbeat.seq.wrapping_add(1);
+                    next_heartbeat = now + HEARTBEAT_INTERVAL_MS;
+                }
+                while let Some(event) = retransmitter.poll(Instant::now().as_millis()) {
+                    match event {
+                        DeliveryEvent::Retransmit { dst, payload, .. } => {
+                            send_message(&mut esp_now, &dst, &payload).await;
+                        }
+                        DeliveryEvent::GaveUp { dst, payload, .. } => {
+                            println!(
+                                "light {:02X?} never acknowledged {:?}",
+                                dst, payload.message_type
+                            );
+                        }
+                    }
+                }
+                continue;
+            }
+            embassy_futures::select::Either4::Fourth(edge) => {
+                let now = Instant::now().as_millis();
+                let (gesture, hold) = match edge {
+                    Some(edge) if edge.pressed => (
+                        gestures.on_press(edge.at_ms, edge.button),
+                        holds.on_press(edge.at_ms, edge.button),
+                    ),
+                    Some(edge) => (
+                        gestures.on_release(edge.at_ms, edge.button),
+                        holds.on_release(edge.button),
+                    ),
+                    None => (gestures.poll(now), None),
+                };
+                let hold = edge.zip(hold).map(|(edge, event)| (edge.button, event));
+                // Buttons held together make a chord rather than ramp. Their holds end as soon as
+                // it forms, so that lights don't ramp while a scene is being saved.
+                let chord = gestures.chord();
+                let mut ended = heapless::Vec::<_, 4>::new();
+                for button in chord.iter() {
+                    if let Some(event_type) = holds.on_release(button) {
+                        ended.push((button, event_type)).ok();
+                    }
+                }
+                // The press or release of this edge, then any repeats that are due.
+                for (button, event_type) in hold
+                    .filter(|(button, _)| !chord.contains(*button))
+                    .into_iter()
+                    .chain(ended)
+                    .chain(core::iter::from_fn(|| holds.poll(now)))
+                {
+                    if button != PAIRING_BUTTON {
+                        let message_type = MessageType::ButtonEvent {
+                            button_number: button,
+                            event_type,
+                        };
+                        let destination = BUTTON_DESTINATIONS[button as usize];
+                        send_to_lights_once(&mut esp_now, &mut storage, destination, message_type)
+                            .await;
+                    }
+                }
+                if let Some(gesture) = gesture {
+                    println!("gesture: {:?}", gesture);
+                    let message_type = scene_message(&gesture).unwrap_or_else(|| gesture.into());
+                    send_to_lights(
+                        &mut esp_now,
+                        &mut storage,
+                        &mut retransmitter,
+                        GESTURE_DESTINATION,
+                        message_type,
+                    )
+                    .await;
+                }
+                continue;
+            }
         };
 
-        postcard::to_slice(&message, &mut tx_bux).unwrap();
+        // Clicks of the buttons of a chord are part of it.
+        if gestures.chord().contains(event_data.0) {
+            continue;
+        }
 
-        esp_now
-            .send_async(&BROADCAST_ADDRESS, &tx_bux)
-            .await
-            .unwrap();
+        if event_data.0 == PAIRING_BUTTON && matches!(event_data.1, ButtonEvent::LongPress) {
+            let paired = pair(&mut esp_now, &mut storage, &mut async_button4, &mut rng).await;
+            // The presses confirming pairing aren't gestures.
+            EDGES.clear();
+            if let Some((light_mac, lmk)) = paired {
+                if !storage.pair(light_mac, lmk) {
+                    println!("already paired with {} lights", MAX_LIGHTS);
+                }
+                send(
+                    &mut esp_now,
+                    &mut storage,
+                    &light_mac,
+                    MessageType::Discover,
+                )
+                .await;
+            }
+            continue;
+        }
+
+        let event_type: ButtonEventType = event_data.1.into();
+        let message_type = MessageType::ButtonEvent {
+            button_number: event_data.0,
+            event_type,
+        };
+        send_to_lights(
+            &mut esp_now,
+            &mut storage,
+            &mut retransmitter,
+            BUTTON_DESTINATIONS[event_data.0 as usize],
+            message_type,
+        )
+        .await;
     }
 }
@@ -0,0 +1,106 @@
+//! Every press and release of the buttons, for a
+//! [`GestureDetector`](spark_messages::GestureDetector).
+//!
+//! `async_button` owns the button pins and only reports clicks. Handing it [`Watched`] pins lets
+//! the remote see the edges as well.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Instant;
+use embedded_hal::digital::{ErrorType, InputPin};
+use embedded_hal_async::digital::Wait;
+use spark_messages::ButtonNumber;
+
+/// A button being pressed or released.
+#[derive(Debug, Copy, Clone)]
+pub struct Edge {
+    pub button: ButtonNumber,
+    pub pressed: bool,
+    pub at_ms: u64,
+}
+
+/// Edges of every [`Watched`] pin, oldest first. Edges are dropped while it is full.
+pub static EDGES: Channel<CriticalSectionRawMutex, Edge, 16> = Channel::new();
+
+/// The pin of an active-low button, reporting to [`EDGES`] whenever its level is seen to change.
+pub struct Watched<P> {
+    pin: P,
+    button: ButtonNumber,
+    pressed: bool,
+}
+
+impl<P: InputPin> Watched<P> {
+    pub fn new(pin: P, button: ButtonNumber) -> Self {
+        Self {
+            pin,
+            button,
+            pressed: false,
+        }
+    }
+
+    fn observe(&mut self, pressed: bool) {
+        if pressed != self.pressed {
+            self.pressed = pressed;
+            let edge = Edge {
+                button: self.button,
+                pressed,
+                at_ms: Instant::now().as_millis(),
+            };
+            EDGES.try_send(edge).ok();
+        }
+    }
+
+    /// After an edge, the level may have bounced back already.
+    fn check(&mut self) -> Result<(), P::Error> {
+        let pressed = self.pin.is_low()?;
+        self.observe(pressed);
+        Ok(())
+    }
+}
+
+impl<P: ErrorType> ErrorType for Watched<P> {
+    type Error = P::Error;
+}
+
+impl<P: InputPin> InputPin for Watched<P> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        let high = self.pin.is_high()?;
+        self.observe(!high);
+        Ok(high)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        let low = self.pin.is_low()?;
+        self.observe(low);
+        Ok(low)
+    }
+}
+
+impl<P: InputPin + Wait> Wait for Watched<P> {
+    async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.wait_for_high().await?;
+        self.observe(false);
+        Ok(())
+    }
+
+    async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.wait_for_low().await?;
+        self.observe(true);
+        Ok(())
+    }
+
+    async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+        self.pin.wait_for_rising_edge().await?;
+        self.check()
+    }
+
+    async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+        self.pin.wait_for_falling_edge().await?;
+        self.check()
+    }
+
+    async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+        self.pin.wait_for_any_edge().await?;
+        self.check()
+    }
+}
@@ -1 +1,4 @@
 #![no_std]
+
+pub mod buttons;
+pub mod storage;
@@ -0,0 +1,124 @@
+//! State the remote keeps across reboots.
+
+use esp_println::println;
+use esp_storage::FlashStorage;
+use serde::{Deserialize, Serialize};
+use spark_messages::{Journal, Lmk, MacAddress, MessageId, MessageIds, SendCounter};
+
+/// Most lights a remote can be paired with.
+pub const MAX_LIGHTS: usize = 8;
+
+/// The `nvs` partition of the default partition table, which nothing else uses.
+const STORAGE_OFFSET: u32 = 0x9000;
+const STORAGE_LEN: u32 = 0x6000;
+
+/// Enough for a [`State`] with every light paired and keyed.
+const STATE_BUF_LEN: usize = 256;
+
+/// Stored ahead of the [`State`], and bumped whenever its layout changes, so that a state stored by
+/// other firmware is never read as this one.
+const STATE_FORMAT: u8 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    pub counter_reservation: u32,
+    pub lights: heapless::Vec<MacAddress, MAX_LIGHTS>,
+    /// Keys traffic with paired lights is encrypted with, see [`spark_messages::KeyExchange`].
+    /// Lights without one get plaintext.
+    pub lmks: heapless::Vec<(MacAddress, Lmk), MAX_LIGHTS>,
+}
+
+pub struct Storage {
+    journal: Journal<FlashStorage>,
+    state: State,
+    counter: SendCounter,
+    ids: MessageIds,
+}
+
+impl Storage {
+    pub fn load() -> Self {
+        let mut journal = Journal::new(FlashStorage::new(), STORAGE_OFFSET, STORAGE_LEN).unwrap();
+        let mut buf = [0; STATE_BUF_LEN];
+        let state = match journal.load::<u8>(&mut buf) {
+            Ok(Some(STATE_FORMAT)) => journal
+                .load::<(u8, State)>(&mut buf)
+                .map(|stored| stored.map(|(_, state)| state)),
+            Ok(Some(format)) => {
+                println!("unknown state format {}, starting over", format);
+                Ok(None)
+            }
+            stored => stored.map(|_| None),
+        };
+        let state = match state {
+            Ok(state) => state.unwrap_or_default(),
+            Err(e) => {
+                println!("failed to load state, starting over: {:?}", e);
+                State::default()
+            }
+        };
+
+        Self {
+            journal,
+            counter: SendCounter::resume(state.counter_reservation),
+            ids: MessageIds::resume(state.counter_reservation),
+            state,
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Counter for the next message this remote sends.
+    pub fn next_counter(&mut self) -> u32 {
+        let (counter, reservation) = self.counter.next_counter();
+        if let Some(reservation) = reservation {
+            self.state.counter_reservation = reservation;
+            self.persist();
+        }
+        counter
+    }
+
+    /// Skip past the counters lights may have reserved, for when they restarted, see
+    /// [`spark_messages::ReceiveWindow`].
+    pub fn skip_counters(&mut self) {
+        if let Some(reservation) = self.counter.skip() {
+            self.state.counter_reservation = reservation;
+            self.persist();
+        }
+    }
+
+    /// ID for the next message this remote sends, shared by all copies of it.
+    pub fn next_id(&mut self) -> MessageId {
+        self.ids.next_id()
+    }
+
+    /// Remember a newly paired light, with the key to encrypt traffic with if the user confirmed
+    /// pairing. Returns `false` if there's no room left for it.
+    pub fn pair(&mut self, mac: MacAddress, lmk: Option<Lmk>) -> bool {
+        if !self.state.lights.contains(&mac) && self.state.lights.push(mac).is_err() {
+            return false;
+        }
+        self.state.lmks.retain(|(light, _)| *light != mac);
+        if let Some(lmk) = lmk {
+            // Every key belongs to a paired light, so there's room.
+            self.state.lmks.push((mac, lmk)).ok();
+        }
+
+        self.persist();
+        true
+    }
+
+    /// The key traffic with the light at `mac` is encrypted with, if any.
+    pub fn lmk(&self, mac: MacAddress) -> Option<Lmk> {
+        let (_, lmk) = self.state.lmks.iter().find(|(light, _)| *light == mac)?;
+        Some(*lmk)
+    }
+
+    fn persist(&mut self) {
+        let mut buf = [0; STATE_BUF_LEN];
+        if let Err(e) = self.journal.store(&(STATE_FORMAT, &self.state), &mut buf) {
+            println!("failed to store state: {:?}", e);
+        }
+    }
+}
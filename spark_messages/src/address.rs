@@ -0,0 +1,65 @@
+//! Addressing messages to some lights only.
+//!
+//! Every light has an [`Address`]: a device ID, unique within an installation, and the groups it
+//! belongs to (a kitchen, a hallway). Messages carry a [`Destination`], and lights only act on the
+//! ones addressed to them. Messages from senders predating addressing are addressed to all lights.
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use crate::{MacAddress, Message};
+
+pub type DeviceId = u16;
+
+/// Bit `n` is set for membership in group `n`.
+pub type GroupMask = u32;
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub enum Destination {
+    #[default]
+    All,
+    Device(DeviceId),
+    /// Every light in any of these groups.
+    Groups(GroupMask),
+}
+
+/// Who a light is, as far as [`Destination`]s are concerned.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct Address {
+    pub id: DeviceId,
+    pub groups: GroupMask,
+}
+
+impl Address {
+    /// The address of a light that hasn't been configured: an ID derived from its MAC address, and
+    /// a member of every group.
+    pub fn unconfigured(mac: MacAddress) -> Self {
+        Self {
+            id: u16::from_be_bytes([mac[4], mac[5]]),
+            groups: GroupMask::MAX,
+        }
+    }
+}
+
+impl Destination {
+    /// Whether a light at `address` should act on messages addressed to `self`.
+    pub fn matches(&self, address: &Address) -> bool {
+        match *self {
+            Destination::All => true,
+            Destination::Device(id) => id == address.id,
+            Destination::Groups(groups) => groups & address.groups != 0,
+        }
+    }
+}
+
+impl Message {
+    /// Address the message to `destination` instead of every light.
+    pub fn with_destination(self, destination: Destination) -> Self {
+        Self {
+            destination,
+            ..self
+        }
+    }
+}
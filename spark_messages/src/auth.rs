@@ -0,0 +1,115 @@
+//! Authentication of frames with a pre-shared key.
+//!
+//! Every frame is followed by a truncated HMAC-SHA256 tag computed with a key shared by all devices
+//! of an installation, over the MAC address of the sender followed by the frame. Covering the
+//! sender keeps a frame captured from one remote from passing as another's, whose replay window
+//! it could otherwise get past. A light relaying a message signs its copy as the origin it names,
+//! so every frame is checked against the sender it counts as coming from, see [`open`]. The tag
+//! sits after the message, where firmware that predates authentication ignores it. Its own frames
+//! carry no tag, and are turned down like any other unauthentic frame.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{DecodeError, MacAddress, Message};
+
+/// Length of the tag appended to every frame.
+pub const TAG_LEN: usize = 8;
+
+pub type Key = [u8; 32];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// The buffer has no room for the tag.
+    BufferFull,
+    /// The frame is too short to hold a tag.
+    Truncated,
+    /// The tag doesn't match the frame, or its sender.
+    BadTag,
+}
+
+/// Why [`open`] turned a frame down.
+#[derive(Debug, PartialEq, Eq)]
+pub enum OpenError {
+    Auth(AuthError),
+    /// The frame is authentic, but doesn't decode.
+    Decode(DecodeError),
+}
+
+/// Parse a hex-encoded key. Meant to be used in a `const` so that bad keys fail the build.
+pub const fn parse_key(hex: &str) -> Key {
+    const fn nibble(c: u8) -> u8 {
+        match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            b'A'..=b'F' => c - b'A' + 10,
+            _ => panic!("key must be hex encoded"),
+        }
+    }
+
+    let hex = hex.as_bytes();
+    assert!(hex.len() == 64, "key must be 32 bytes long");
+
+    let mut key = [0; 32];
+    let mut i = 0;
+    while i < key.len() {
+        key[i] = nibble(hex[2 * i]) << 4 | nibble(hex[2 * i + 1]);
+        i += 1;
+    }
+    key
+}
+
+fn mac(key: &Key, sender: &MacAddress, frame: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(sender);
+    mac.update(frame);
+    mac
+}
+
+/// Append a tag to the `len` byte frame at the start of `buf`, as sent by `sender`. Returns the
+/// signed frame.
+pub fn sign<'a>(
+    key: &Key,
+    sender: &MacAddress,
+    buf: &'a mut [u8],
+    len: usize,
+) -> Result<&'a [u8], AuthError> {
+    let signed_len = len + TAG_LEN;
+    if buf.len() < signed_len {
+        return Err(AuthError::BufferFull);
+    }
+
+    let tag = mac(key, sender, &buf[..len]).finalize().into_bytes();
+    buf[len..signed_len].copy_from_slice(&tag[..TAG_LEN]);
+    Ok(&buf[..signed_len])
+}
+
+/// Check the tag of a frame signed by `sender`. Returns the frame without its tag.
+pub fn verify<'a>(key: &Key, sender: &MacAddress, signed: &'a [u8]) -> Result<&'a [u8], AuthError> {
+    let len = signed
+        .len()
+        .checked_sub(TAG_LEN)
+        .ok_or(AuthError::Truncated)?;
+    let (frame, tag) = signed.split_at(len);
+
+    mac(key, sender, frame)
+        .verify_truncated_left(tag)
+        .map_err(|_| AuthError::BadTag)?;
+    Ok(frame)
+}
+
+/// Check and decode a frame received from `src`. Frames a light relayed are checked against the
+/// sender named as their origin, see [`Message::relayed`], the others against `src`.
+pub fn open(key: &Key, src: &MacAddress, signed: &[u8]) -> Result<Message, OpenError> {
+    let len = signed
+        .len()
+        .checked_sub(TAG_LEN)
+        .ok_or(OpenError::Auth(AuthError::Truncated))?;
+    let message = Message::decode(&signed[..len]);
+    // Frames that don't decode can't name an origin, they can only be authentic if `src` sent them.
+    let sender = message
+        .as_ref()
+        .map_or(*src, |message| message.origin(*src));
+    verify(key, &sender, signed).map_err(OpenError::Auth)?;
+    message.map_err(OpenError::Decode)
+}
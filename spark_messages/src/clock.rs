@@ -0,0 +1,212 @@
+//! Clock synchronisation.
+//!
+//! Every device counts time from its own boot, on its own crystal. For lights to play animations
+//! in step, they render against a shared network time instead: the clock of the remote they
+//! paired with first. Lights periodically send that remote a [`TimeRequest`], NTP-style, and a
+//! [`ClockSync`] turns the answers into an estimate of the remote's clock that compensates for the
+//! time frames spend in flight, and for the clocks drifting apart in between.
+//!
+//! Times are in microseconds since boot.
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+/// How often lights ask for the time once synchronised.
+pub const SYNC_INTERVAL_MS: u64 = 5_000;
+
+/// How many exchanges the estimate is based on.
+const SAMPLES: usize = 16;
+
+/// Drift is only estimated from exchanges at least this far apart, closer ones are too noisy.
+const MIN_DRIFT_SPAN_US: u64 = 10_000_000;
+
+/// Crystals are good for ±50 ppm or so, estimates beyond this are noise.
+const MAX_DRIFT: f64 = 200e-6;
+
+/// An exchange this far off the estimate means the remote rebooted, and its clock started over.
+const RESTART_THRESHOLD_US: i64 = 100_000;
+
+/// Light => remote, asks for the time.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct TimeRequest {
+    /// When the request was sent, by the light's clock.
+    pub origin_us: u64,
+}
+
+/// Remote => light, answers a [`TimeRequest`].
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct TimeResponse {
+    /// Copied from the request.
+    pub origin_us: u64,
+    /// When the request arrived, by the remote's clock.
+    pub receive_us: u64,
+    /// When the response was sent, by the remote's clock.
+    pub transmit_us: u64,
+}
+
+impl TimeRequest {
+    /// Answer a request that arrived at `receive_us`, right before sending the answer at
+    /// `transmit_us`.
+    pub fn respond(&self, receive_us: u64, transmit_us: u64) -> TimeResponse {
+        TimeResponse {
+            origin_us: self.origin_us,
+            receive_us,
+            transmit_us,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Sample {
+    /// When the response arrived, by the local clock.
+    at_us: u64,
+    /// Remote clock minus local clock.
+    offset_us: i64,
+    /// Round trip time, minus the time the remote took to answer.
+    delay_us: i64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Estimate {
+    at_us: u64,
+    offset_us: i64,
+    /// How much faster the remote's clock runs than the local one, e.g. 50e-6 for 50 ppm.
+    drift: f64,
+}
+
+/// Estimates the network time from the answers to [`TimeRequest`]s.
+#[derive(Debug)]
+pub struct ClockSync {
+    pending: Option<u64>,
+    samples: [Option<Sample>; SAMPLES],
+    next: usize,
+    estimate: Option<Estimate>,
+}
+
+impl ClockSync {
+    pub const fn new() -> Self {
+        Self {
+            pending: None,
+            samples: [None; SAMPLES],
+            next: 0,
+            estimate: None,
+        }
+    }
+
+    /// Start an exchange. Only the answer to the latest request is accepted.
+    pub fn request(&mut self, now_us: u64) -> TimeRequest {
+        self.pending = Some(now_us);
+        TimeRequest { origin_us: now_us }
+    }
+
+    /// Handle a response that arrived at `now_us`. Returns `false` if it doesn't answer the
+    /// latest request, or was already handled.
+    pub fn on_response(&mut self, now_us: u64, response: &TimeResponse) -> bool {
+        if self.pending != Some(response.origin_us) || now_us < response.origin_us {
+            return false;
+        }
+        self.pending = None;
+
+        let t1 = response.origin_us as i64;
+        let t2 = response.receive_us as i64;
+        let t3 = response.transmit_us as i64;
+        let t4 = now_us as i64;
+        let sample = Sample {
+            at_us: now_us,
+            offset_us: ((t2 - t1) + (t3 - t4)) / 2,
+            delay_us: (t4 - t1) - (t3 - t2),
+        };
+
+        let expected = self.offset_us(now_us).unwrap_or(sample.offset_us);
+        if (sample.offset_us - expected).abs() > RESTART_THRESHOLD_US {
+            self.samples = [None; SAMPLES];
+        }
+        self.samples[self.next] = Some(sample);
+        self.next = (self.next + 1) % SAMPLES;
+
+        self.estimate = Some(self.fit());
+        true
+    }
+
+    pub fn is_synchronised(&self) -> bool {
+        self.estimate.is_some()
+    }
+
+    /// The network time at local time `now_us`, or the local time until synchronised.
+    pub fn network_time(&self, now_us: u64) -> u64 {
+        match self.estimate {
+            Some(estimate) => {
+                let elapsed = now_us as i64 - estimate.at_us as i64;
+                let drift = (elapsed as f64 * estimate.drift) as i64;
+                (now_us as i64 + estimate.offset_us + drift).max(0) as u64
+            }
+            None => now_us,
+        }
+    }
+
+    /// Remote clock minus local clock at local time `now_us`.
+    pub fn offset_us(&self, now_us: u64) -> Option<i64> {
+        self.estimate
+            .map(|_| self.network_time(now_us) as i64 - now_us as i64)
+    }
+
+    /// How much faster the remote's clock runs than the local one, in parts per million.
+    pub fn drift_ppm(&self) -> Option<f64> {
+        self.estimate.map(|estimate| estimate.drift * 1e6)
+    }
+
+    /// Fit a line through the offsets of the exchanges that spent the least time in flight. The
+    /// offset of an exchange is off by at most half its delay, so those are the ones to trust.
+    fn fit(&self) -> Estimate {
+        let mut delays = [i64::MAX; SAMPLES];
+        for (delay, sample) in delays.iter_mut().zip(&self.samples) {
+            if let Some(sample) = sample {
+                *delay = sample.delay_us;
+            }
+        }
+        delays.sort_unstable();
+        let count = self.samples.iter().flatten().count();
+        let max_delay = delays[(count - 1) / 2];
+        let trusted = || {
+            self.samples
+                .iter()
+                .flatten()
+                .filter(move |s| s.delay_us <= max_delay)
+        };
+
+        let newest = trusted().map(|s| s.at_us).max().unwrap();
+        let oldest = trusted().map(|s| s.at_us).min().unwrap();
+        let n = trusted().count() as f64;
+        let mean_x = trusted()
+            .map(|s| (s.at_us as i64 - newest as i64) as f64)
+            .sum::<f64>()
+            / n;
+        let mean_y = trusted().map(|s| s.offset_us as f64).sum::<f64>() / n;
+
+        let (mut sxy, mut sxx) = (0.0, 0.0);
+        for sample in trusted() {
+            let x = (sample.at_us as i64 - newest as i64) as f64 - mean_x;
+            sxy += x * (sample.offset_us as f64 - mean_y);
+            sxx += x * x;
+        }
+        let drift = if newest - oldest >= MIN_DRIFT_SPAN_US {
+            (sxy / sxx).clamp(-MAX_DRIFT, MAX_DRIFT)
+        } else {
+            0.0
+        };
+
+        Estimate {
+            at_us: newest,
+            offset_us: (mean_y - drift * mean_x) as i64,
+            drift,
+        }
+    }
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,234 @@
+//! Settings lights can be configured with over the air.
+//!
+//! Every setting has a [`ConfigKey`], and the registry in [`ConfigKey::info`] gives its type,
+//! range and default. Remotes read settings with [`MessageType::ConfigGet`] and
+//! [`MessageType::ConfigList`], and change them with [`MessageType::ConfigSet`] unless the
+//! registry has them read-only. Lights check new values against the registry, and answer every
+//! entry asked for or set with a [`MessageType::ConfigReply`].
+//!
+//! [`MessageType::ConfigGet`]: crate::MessageType::ConfigGet
+//! [`MessageType::ConfigList`]: crate::MessageType::ConfigList
+//! [`MessageType::ConfigSet`]: crate::MessageType::ConfigSet
+//! [`MessageType::ConfigReply`]: crate::MessageType::ConfigReply
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use crate::MAX_RELAY_HOPS;
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum ConfigKey {
+    /// How long a button press lights up a light, in milliseconds.
+    AnimationTimeoutMs,
+    /// Brightness of the animation played on button presses.
+    Brightness,
+    /// The Wi-Fi channel ESP-NOW runs on. Read-only, as remotes stay on the channel they paired
+    /// on and a light moving off it would lose them.
+    Channel,
+    /// How many LEDs of each strip to drive.
+    LedCount,
+    /// Relay messages from paired remotes that went through fewer than this many lights, see the
+    /// `relay` module. 0 doesn't relay any.
+    RelayHops,
+}
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum ConfigValue {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+}
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum ConfigType {
+    U8,
+    U16,
+    U32,
+}
+
+/// A setting and its value.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct ConfigEntry {
+    pub key: ConfigKey,
+    pub value: ConfigValue,
+}
+
+/// Why a light refused a [`ConfigEntry`].
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum ConfigError {
+    WrongType {
+        key: ConfigKey,
+        expected: ConfigType,
+    },
+    OutOfRange {
+        key: ConfigKey,
+        min: ConfigValue,
+        max: ConfigValue,
+    },
+    /// The setting can't be changed over the air, see [`ConfigInfo::settable`].
+    ReadOnly { key: ConfigKey },
+}
+
+/// What the registry knows about a setting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConfigInfo {
+    pub key: ConfigKey,
+    pub min: ConfigValue,
+    pub max: ConfigValue,
+    pub default: ConfigValue,
+    /// Whether [`MessageType::ConfigSet`](crate::MessageType::ConfigSet) can change it.
+    pub settable: bool,
+}
+
+impl ConfigKey {
+    /// Every setting, in the order lights list them.
+    pub const ALL: [ConfigKey; 5] = [
+        ConfigKey::AnimationTimeoutMs,
+        ConfigKey::Brightness,
+        ConfigKey::Channel,
+        ConfigKey::LedCount,
+        ConfigKey::RelayHops,
+    ];
+
+    pub const fn info(self) -> ConfigInfo {
+        let (min, max, default) = match self {
+            ConfigKey::AnimationTimeoutMs => (
+                ConfigValue::U32(100),
+                ConfigValue::U32(600_000),
+                ConfigValue::U32(3_000),
+            ),
+            ConfigKey::Brightness => (
+                ConfigValue::U8(1),
+                ConfigValue::U8(255),
+                ConfigValue::U8(25),
+            ),
+            ConfigKey::Channel => (ConfigValue::U8(1), ConfigValue::U8(13), ConfigValue::U8(11)),
+            ConfigKey::LedCount => (
+                ConfigValue::U16(1),
+                ConfigValue::U16(1024),
+                ConfigValue::U16(8),
+            ),
+            ConfigKey::RelayHops => (
+                ConfigValue::U8(0),
+                ConfigValue::U8(MAX_RELAY_HOPS),
+                ConfigValue::U8(0),
+            ),
+        };
+        ConfigInfo {
+            key: self,
+            min,
+            max,
+            default,
+            settable: !matches!(self, ConfigKey::Channel),
+        }
+    }
+
+    /// Check `value` against the type and range of the setting.
+    pub fn validate(self, value: ConfigValue) -> Result<ConfigEntry, ConfigError> {
+        let info = self.info();
+        if value.kind() != info.default.kind() {
+            return Err(ConfigError::WrongType {
+                key: self,
+                expected: info.default.kind(),
+            });
+        }
+        if value.get() < info.min.get() || value.get() > info.max.get() {
+            return Err(ConfigError::OutOfRange {
+                key: self,
+                min: info.min,
+                max: info.max,
+            });
+        }
+        Ok(ConfigEntry { key: self, value })
+    }
+}
+
+impl ConfigValue {
+    pub fn kind(&self) -> ConfigType {
+        match self {
+            ConfigValue::U8(_) => ConfigType::U8,
+            ConfigValue::U16(_) => ConfigType::U16,
+            ConfigValue::U32(_) => ConfigType::U32,
+        }
+    }
+
+    fn get(&self) -> u32 {
+        match *self {
+            ConfigValue::U8(value) => value as u32,
+            ConfigValue::U16(value) => value as u32,
+            ConfigValue::U32(value) => value,
+        }
+    }
+}
+
+/// The value of every setting, as a light keeps them.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub animation_timeout_ms: u32,
+    pub brightness: u8,
+    pub channel: u8,
+    pub led_count: u16,
+    pub relay_hops: u8,
+}
+
+impl Config {
+    pub fn get(&self, key: ConfigKey) -> ConfigEntry {
+        let value = match key {
+            ConfigKey::AnimationTimeoutMs => ConfigValue::U32(self.animation_timeout_ms),
+            ConfigKey::Brightness => ConfigValue::U8(self.brightness),
+            ConfigKey::Channel => ConfigValue::U8(self.channel),
+            ConfigKey::LedCount => ConfigValue::U16(self.led_count),
+            ConfigKey::RelayHops => ConfigValue::U8(self.relay_hops),
+        };
+        ConfigEntry { key, value }
+    }
+
+    /// Change a setting, if it can be changed and the value checks out.
+    pub fn set(&mut self, entry: &ConfigEntry) -> Result<ConfigEntry, ConfigError> {
+        if !entry.key.info().settable {
+            return Err(ConfigError::ReadOnly { key: entry.key });
+        }
+        let entry = entry.key.validate(entry.value)?;
+        self.put(entry);
+        Ok(entry)
+    }
+
+    fn put(&mut self, entry: ConfigEntry) {
+        match (entry.key, entry.value) {
+            (ConfigKey::AnimationTimeoutMs, ConfigValue::U32(value)) => {
+                self.animation_timeout_ms = value
+            }
+            (ConfigKey::Brightness, ConfigValue::U8(value)) => self.brightness = value,
+            (ConfigKey::Channel, ConfigValue::U8(value)) => self.channel = value,
+            (ConfigKey::LedCount, ConfigValue::U16(value)) => self.led_count = value,
+            (ConfigKey::RelayHops, ConfigValue::U8(value)) => self.relay_hops = value,
+            _ => unreachable!("validated"),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let mut config = Self {
+            animation_timeout_ms: 0,
+            brightness: 0,
+            channel: 0,
+            led_count: 0,
+            relay_hops: 0,
+        };
+        for key in ConfigKey::ALL {
+            let value = key.info().default;
+            config.put(key.validate(value).unwrap());
+        }
+        config
+    }
+}
@@ -0,0 +1,147 @@
+//! Dropping messages that arrive more than once.
+//!
+//! Broadcasts can arrive twice, and retransmissions and relays send the same message again, not
+//! always with the same counter. So every [`Message`] carries an ID, which its sender hands out
+//! with [`MessageIds`] and keeps for every copy of the message, whoever it is sent to and however
+//! often. Receivers remember the IDs they saw from each sender for a while in a [`DedupCache`], and
+//! drop messages whose ID they remember. IDs wrap around, which is fine as long as that takes
+//! longer than receivers remember them.
+
+use crate::{MacAddress, Message};
+
+pub type MessageId = u16;
+
+/// The ID of messages from senders predating message IDs, which are never dropped as duplicates.
+pub const NO_MESSAGE_ID: MessageId = 0;
+
+/// How long a receiver remembers an ID, unless configured otherwise. Longer than a sender keeps
+/// retrying, see [`RetryPolicy`](crate::RetryPolicy).
+pub const DEDUP_WINDOW_MS: u64 = 5_000;
+
+impl Message {
+    /// Identify the message as `id`, see [`MessageIds`].
+    pub fn with_id(self, id: MessageId) -> Self {
+        Self { id, ..self }
+    }
+}
+
+/// Hands out message IDs, in order, skipping [`NO_MESSAGE_ID`].
+#[derive(Debug, Clone)]
+pub struct MessageIds {
+    /// How many IDs were handed out, from the start of the ID space. The IDs go around the 65 535
+    /// that aren't [`NO_MESSAGE_ID`], so they don't drift from the count when skipping it.
+    position: u32,
+}
+
+impl MessageIds {
+    /// Start at `first`. Senders should start somewhere else after every boot, so that receivers
+    /// don't drop messages for reusing the IDs of ones sent just before.
+    pub const fn starting_at(first: MessageId) -> Self {
+        Self {
+            position: first.saturating_sub(1) as u32,
+        }
+    }
+
+    /// Resume after a boot, from the reservation [`SendCounter::resume`] was given, at the position
+    /// of the next counter. Every message takes a counter and at most one ID, so the IDs don't
+    /// repeat the ones sent before the boot unless 65 535 went out within a [`DedupCache`] window.
+    ///
+    /// [`SendCounter::resume`]: crate::SendCounter::resume
+    pub const fn resume(reserved: u32) -> Self {
+        Self {
+            position: reserved.wrapping_add(1),
+        }
+    }
+
+    pub fn next_id(&mut self) -> MessageId {
+        let id = (self.position % MessageId::MAX as u32) as MessageId + 1;
+        self.position = self.position.wrapping_add(1);
+        id
+    }
+}
+
+impl Default for MessageIds {
+    fn default() -> Self {
+        Self::starting_at(NO_MESSAGE_ID + 1)
+    }
+}
+
+#[derive(Debug)]
+struct Sender<const N: usize> {
+    mac: MacAddress,
+    last_seen_at: u64,
+    /// IDs and when they were first seen, oldest first.
+    seen: heapless::Deque<(MessageId, u64), N>,
+}
+
+/// The message IDs seen from up to `S` senders over the last while, up to `N` per sender. Times
+/// are in milliseconds.
+///
+/// Once full, the sender heard from least recently is forgotten to make room for a new one, and a
+/// sender's oldest ID for a new one of theirs. A sender sending more than `N` messages within the
+/// window may then get duplicates through.
+#[derive(Debug)]
+pub struct DedupCache<const S: usize, const N: usize> {
+    window_ms: u64,
+    senders: heapless::Vec<Sender<N>, S>,
+}
+
+impl<const S: usize, const N: usize> DedupCache<S, N> {
+    /// Remember IDs for `window_ms` after first seeing them.
+    pub const fn new(window_ms: u64) -> Self {
+        Self {
+            window_ms,
+            senders: heapless::Vec::new(),
+        }
+    }
+
+    /// Record a message `id` from `src` received at `now`. Returns `false` if it is a duplicate,
+    /// which should be dropped.
+    ///
+    /// Only call this for authenticated messages, or forged IDs could get genuine messages dropped.
+    pub fn accept(&mut self, now: u64, src: MacAddress, id: MessageId) -> bool {
+        if id == NO_MESSAGE_ID {
+            return true;
+        }
+
+        let index = match self.senders.iter().position(|sender| sender.mac == src) {
+            Some(index) => index,
+            None => {
+                if self.senders.is_full() {
+                    let oldest = (0..self.senders.len())
+                        .min_by_key(|&i| self.senders[i].last_seen_at)
+                        .unwrap();
+                    self.senders.swap_remove(oldest);
+                }
+                self.senders
+                    .push(Sender {
+                        mac: src,
+                        last_seen_at: now,
+                        seen: heapless::Deque::new(),
+                    })
+                    .ok();
+                self.senders.len() - 1
+            }
+        };
+        let window_ms = self.window_ms;
+        let sender = &mut self.senders[index];
+        sender.last_seen_at = now;
+
+        while sender
+            .seen
+            .front()
+            .is_some_and(|&(_, seen_at)| now.saturating_sub(seen_at) >= window_ms)
+        {
+            sender.seen.pop_front();
+        }
+        if sender.seen.iter().any(|&(seen, _)| seen == id) {
+            return false;
+        }
+
+        if sender.seen.is_full() {
+            sender.seen.pop_front();
+        }
+        sender.seen.push_back((id, now)).ok();
+        true
+    }
+}
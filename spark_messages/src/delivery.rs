@@ -0,0 +1,128 @@
+//! Acknowledged delivery.
+//!
+//! Receivers answer messages that need it with an [`Ack`](crate::MessageType::Ack) carrying the
+//! message's counter, which doubles as its sequence number. Senders keep unacknowledged messages
+//! in a [`Retransmitter`] and send them again, with the same counter, until they are acknowledged
+//! or the [`RetryPolicy`] gives up. Receivers must acknowledge repeated messages again, since
+//! their previous acknowledgement may have been lost, but only act on them once: the replay window
+//! takes care of that.
+
+use crate::MacAddress;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times a message is sent again before giving up.
+    pub max_retries: u8,
+    /// How long to wait for the first acknowledgement. Doubles after every retry.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 4,
+            initial_backoff_ms: 40,
+            max_backoff_ms: 320,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryEvent<T> {
+    /// Send the message numbered `counter` to `dst` again.
+    Retransmit {
+        dst: MacAddress,
+        counter: u32,
+        payload: T,
+    },
+    /// `dst` never acknowledged the message numbered `counter`.
+    GaveUp {
+        dst: MacAddress,
+        counter: u32,
+        payload: T,
+    },
+}
+
+#[derive(Debug)]
+struct Pending<T> {
+    dst: MacAddress,
+    counter: u32,
+    payload: T,
+    retries: u8,
+    backoff_ms: u64,
+    due_at: u64,
+}
+
+/// Messages waiting for an acknowledgement, up to `N` of them.
+pub struct Retransmitter<T, const N: usize> {
+    policy: RetryPolicy,
+    pending: heapless::Vec<Pending<T>, N>,
+}
+
+impl<T: Clone, const N: usize> Retransmitter<T, N> {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            pending: heapless::Vec::new(),
+        }
+    }
+
+    /// Wait for `dst` to acknowledge the message numbered `counter`, which was just sent. Gives the
+    /// payload back if too many messages are waiting already.
+    pub fn sent(&mut self, now: u64, dst: MacAddress, counter: u32, payload: T) -> Result<(), T> {
+        self.pending
+            .push(Pending {
+                dst,
+                counter,
+                payload,
+                retries: 0,
+                backoff_ms: self.policy.initial_backoff_ms,
+                due_at: now + self.policy.initial_backoff_ms,
+            })
+            .map_err(|pending| pending.payload)
+    }
+
+    /// Handle an acknowledgement from `src`. Returns the acknowledged payload, or `None` if the
+    /// acknowledgement is a duplicate or for a message that was given up on.
+    pub fn on_ack(&mut self, src: MacAddress, counter: u32) -> Option<T> {
+        let i = self
+            .pending
+            .iter()
+            .position(|p| p.dst == src && p.counter == counter)?;
+        Some(self.pending.swap_remove(i).payload)
+    }
+
+    /// Returns the next message that is due to be sent again, or given up on.
+    pub fn poll(&mut self, now: u64) -> Option<DeliveryEvent<T>> {
+        let i = self.pending.iter().position(|p| p.due_at <= now)?;
+
+        let pending = &mut self.pending[i];
+        if pending.retries >= self.policy.max_retries {
+            let pending = self.pending.swap_remove(i);
+            return Some(DeliveryEvent::GaveUp {
+                dst: pending.dst,
+                counter: pending.counter,
+                payload: pending.payload,
+            });
+        }
+
+        pending.retries += 1;
+        pending.backoff_ms = (pending.backoff_ms * 2).min(self.policy.max_backoff_ms);
+        pending.due_at = now + pending.backoff_ms;
+        Some(DeliveryEvent::Retransmit {
+            dst: pending.dst,
+            counter: pending.counter,
+            payload: pending.payload.clone(),
+        })
+    }
+
+    /// When [`Retransmitter::poll`] next has something to do, if anything is waiting.
+    pub fn next_due_at(&self) -> Option<u64> {
+        self.pending.iter().map(|p| p.due_at).min()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
@@ -0,0 +1,139 @@
+//! Finding out which lights there are.
+//!
+//! A remote or host tool sends [`MessageType::Discover`], usually broadcast, and every light it is
+//! addressed to answers with an [`Announce`] describing itself. An [`Inventory`] collects the
+//! answers.
+//!
+//! [`MessageType::Discover`]: crate::MessageType::Discover
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    DeviceId, FirmwareVersion, MIN_PROTOCOL_VERSION, MacAddress, PROTOCOL_VERSION, Pattern,
+    max_vec_size,
+};
+
+/// Most strips an [`Announce`] can describe.
+pub const MAX_STRIPS: usize = 8;
+
+/// Light => whoever sent [`MessageType::Discover`](crate::MessageType::Discover).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct Announce {
+    pub id: DeviceId,
+    pub firmware_version: FirmwareVersion,
+    pub protocol_versions: ProtocolVersions,
+    /// How many LEDs each strip has.
+    #[cfg_attr(feature = "std", schemars(with = "Vec<u16>", length(max = MAX_STRIPS)))]
+    pub strips: heapless::Vec<u16, MAX_STRIPS>,
+    pub patterns: PatternSet,
+}
+
+impl MaxSize for Announce {
+    const POSTCARD_MAX_SIZE: usize = DeviceId::POSTCARD_MAX_SIZE
+        + FirmwareVersion::POSTCARD_MAX_SIZE
+        + ProtocolVersions::POSTCARD_MAX_SIZE
+        + max_vec_size::<u16, MAX_STRIPS>()
+        + PatternSet::POSTCARD_MAX_SIZE;
+}
+
+/// The protocol revisions a device decodes.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct ProtocolVersions {
+    pub oldest: u8,
+    pub newest: u8,
+}
+
+impl ProtocolVersions {
+    /// The revisions this crate decodes.
+    pub const SUPPORTED: Self = Self {
+        oldest: MIN_PROTOCOL_VERSION,
+        newest: PROTOCOL_VERSION,
+    };
+
+    pub fn contains(&self, protocol_version: u8) -> bool {
+        (self.oldest..=self.newest).contains(&protocol_version)
+    }
+}
+
+/// A set of [`Pattern`]s. It's a bitmask rather than a list, so that firmware can announce patterns
+/// older firmware doesn't know about without the whole announcement failing to decode.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct PatternSet(u32);
+
+impl PatternSet {
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    pub fn insert(&mut self, pattern: Pattern) {
+        self.0 |= 1 << pattern as u32;
+    }
+
+    pub fn contains(&self, pattern: Pattern) -> bool {
+        self.0 & (1 << pattern as u32) != 0
+    }
+}
+
+impl FromIterator<Pattern> for PatternSet {
+    fn from_iter<I: IntoIterator<Item = Pattern>>(patterns: I) -> Self {
+        let mut set = Self::new();
+        for pattern in patterns {
+            set.insert(pattern);
+        }
+        set
+    }
+}
+
+/// The lights that announced themselves, by MAC address.
+pub struct Inventory<const N: usize> {
+    lights: heapless::Vec<(MacAddress, Announce), N>,
+}
+
+impl<const N: usize> Inventory<N> {
+    pub const fn new() -> Self {
+        Self {
+            lights: heapless::Vec::new(),
+        }
+    }
+
+    /// Record an announcement, replacing any earlier one from the same light. Returns whether the
+    /// light is new. Once full, new lights are left out.
+    pub fn on_announce(&mut self, src: MacAddress, announce: &Announce) -> bool {
+        match self.lights.iter_mut().find(|(mac, _)| *mac == src) {
+            Some((_, known)) => {
+                *known = announce.clone();
+                false
+            }
+            None => self.lights.push((src, announce.clone())).is_ok(),
+        }
+    }
+
+    pub fn get(&self, mac: MacAddress) -> Option<&Announce> {
+        self.lights
+            .iter()
+            .find(|(known, _)| *known == mac)
+            .map(|(_, announce)| announce)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(MacAddress, Announce)> {
+        self.lights.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+}
+
+impl<const N: usize> Default for Inventory<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
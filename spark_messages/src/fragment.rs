@@ -0,0 +1,229 @@
+//! Fragmentation of payloads larger than one frame.
+//!
+//! ESP-NOW frames carry at most [`MAX_FRAME_LEN`] bytes. Bigger payloads, usually the postcard
+//! encoding of a [`MessageType`](crate::MessageType), are split by [`fragment`] into numbered
+//! [`Fragment`]s that each travel in a message of their own, so they are authenticated and replay
+//! protected like any other. A [`Reassembler`] puts them back together, whatever order they arrive
+//! in and however often. Transfers still missing fragments after [`REASSEMBLY_TIMEOUT_MS`] are
+//! dropped, and have to be sent again as a whole. [`Message::reassembled`] turns a payload back
+//! into a message.
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use crate::{DecodeError, MacAddress, Message, MessageType, max_vec_size};
+
+/// Largest frame ESP-NOW sends.
+pub const MAX_FRAME_LEN: usize = 250;
+
+/// Payload bytes per fragment. Leaves room for the message around it and the authentication tag.
+pub const FRAGMENT_LEN: usize = 200;
+
+/// Most fragments a payload can be split into.
+pub const MAX_FRAGMENTS: usize = 32;
+
+/// How long a transfer may take, counted from its first fragment.
+pub const REASSEMBLY_TIMEOUT_MS: u64 = 2_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct Fragment {
+    /// Chosen by the sender, different from that of its recent transfers.
+    pub transfer: u16,
+    pub index: u8,
+    pub count: u8,
+    /// [`FRAGMENT_LEN`] bytes, except in the last fragment.
+    #[cfg_attr(feature = "std", schemars(with = "Vec<u8>", length(max = FRAGMENT_LEN)))]
+    pub data: heapless::Vec<u8, FRAGMENT_LEN>,
+}
+
+impl MaxSize for Fragment {
+    const POSTCARD_MAX_SIZE: usize = u16::POSTCARD_MAX_SIZE
+        + u8::POSTCARD_MAX_SIZE
+        + u8::POSTCARD_MAX_SIZE
+        + max_vec_size::<u8, FRAGMENT_LEN>();
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FragmentError {
+    /// The payload takes more than [`MAX_FRAGMENTS`], or doesn't fit in the reassembler.
+    TooLarge,
+    /// The fragment contradicts itself or earlier fragments of its transfer.
+    Malformed,
+    /// Every slot of the reassembler is taken by another transfer.
+    Busy,
+}
+
+/// Split `payload` into fragments. Even an empty payload takes one.
+pub fn fragment(
+    transfer: u16,
+    payload: &[u8],
+) -> Result<impl Iterator<Item = Fragment> + '_, FragmentError> {
+    let count = payload.len().div_ceil(FRAGMENT_LEN).max(1);
+    if count > MAX_FRAGMENTS {
+        return Err(FragmentError::TooLarge);
+    }
+
+    Ok((0..count).map(move |index| {
+        let start = index * FRAGMENT_LEN;
+        let end = (start + FRAGMENT_LEN).min(payload.len());
+        Fragment {
+            transfer,
+            index: index as u8,
+            count: count as u8,
+            data: heapless::Vec::from_slice(&payload[start..end]).unwrap(),
+        }
+    }))
+}
+
+#[derive(Debug)]
+struct Slot<const N: usize> {
+    in_use: bool,
+    src: MacAddress,
+    transfer: u16,
+    started_at: u64,
+    count: u8,
+    /// Bit `n` is set once fragment `n` arrived.
+    received: u32,
+    /// Payload length, known once the last fragment arrived.
+    len: usize,
+    buf: [u8; N],
+}
+
+impl<const N: usize> Slot<N> {
+    fn is_complete(&self) -> bool {
+        self.received == all_received(self.count)
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        now.saturating_sub(self.started_at) >= REASSEMBLY_TIMEOUT_MS
+    }
+}
+
+/// Reassembles up to `T` transfers at a time, of up to `N` bytes each.
+pub struct Reassembler<const N: usize, const T: usize> {
+    slots: [Slot<N>; T],
+}
+
+impl<const N: usize, const T: usize> Reassembler<N, T> {
+    pub fn new() -> Self {
+        Self {
+            slots: core::array::from_fn(|_| Slot {
+                in_use: false,
+                src: [0; 6],
+                transfer: 0,
+                started_at: 0,
+                count: 0,
+                received: 0,
+                len: 0,
+                buf: [0; N],
+            }),
+        }
+    }
+
+    /// Handle an authenticated fragment from `src`. Returns the payload once its last missing
+    /// fragment arrived, and only then: later duplicates of its fragments are ignored.
+    pub fn on_fragment(
+        &mut self,
+        now: u64,
+        src: MacAddress,
+        fragment: &Fragment,
+    ) -> Result<Option<&[u8]>, FragmentError> {
+        let count = fragment.count as usize;
+        let index = fragment.index as usize;
+        let last = index + 1 == count;
+        if count == 0 || count > MAX_FRAGMENTS || index >= count {
+            return Err(FragmentError::Malformed);
+        }
+        let len_ok = if last {
+            count == 1 || !fragment.data.is_empty()
+        } else {
+            fragment.data.len() == FRAGMENT_LEN
+        };
+        if !len_ok {
+            return Err(FragmentError::Malformed);
+        }
+        let offset = index * FRAGMENT_LEN;
+        if offset + fragment.data.len() > N {
+            return Err(FragmentError::TooLarge);
+        }
+
+        let i = match self.slots.iter().position(|s| {
+            s.in_use && s.src == src && s.transfer == fragment.transfer && !s.is_expired(now)
+        }) {
+            Some(i) => i,
+            None => {
+                let i = self.vacant(now).ok_or(FragmentError::Busy)?;
+                let slot = &mut self.slots[i];
+                slot.in_use = true;
+                slot.src = src;
+                slot.transfer = fragment.transfer;
+                slot.started_at = now;
+                slot.count = fragment.count;
+                slot.received = 0;
+                slot.len = 0;
+                i
+            }
+        };
+
+        let slot = &mut self.slots[i];
+        if slot.count != fragment.count {
+            return Err(FragmentError::Malformed);
+        }
+        if slot.received & (1 << index) != 0 {
+            return Ok(None);
+        }
+
+        slot.buf[offset..offset + fragment.data.len()].copy_from_slice(&fragment.data);
+        slot.received |= 1 << index;
+        if last {
+            slot.len = offset + fragment.data.len();
+        }
+
+        if slot.is_complete() {
+            Ok(Some(&slot.buf[..slot.len]))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// A slot for a new transfer. Complete transfers are remembered so their duplicates can be
+    /// ignored, but only as long as nothing else needs the slot.
+    fn vacant(&self, now: u64) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|s| !s.in_use || s.is_expired(now))
+            .or_else(|| self.slots.iter().position(|s| s.in_use && s.is_complete()))
+    }
+}
+
+impl<const N: usize, const T: usize> Default for Reassembler<N, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Message {
+    /// The message whose encoding `payload` was, reassembled from fragments of which this message
+    /// carried the last to arrive. Everything but the message type is taken from this message, so
+    /// that the reassembled one is addressed and answered like its fragments.
+    ///
+    /// The payload is checked like [`Message::decode`] checks frames, against the revision the
+    /// fragments were stamped with. Senders stamp them with one that knows the payload's message.
+    pub fn reassembled(&self, payload: &[u8]) -> Result<Message, DecodeError> {
+        let protocol_version = self.protocol_version;
+        let (message_type, _) = postcard::take_from_bytes::<MessageType>(payload)
+            .map_err(|e| DecodeError::from_postcard(e, protocol_version))?;
+        if message_type.introduced_in() > protocol_version {
+            return Err(DecodeError::BadVersion { protocol_version });
+        }
+        Ok(Message {
+            message_type,
+            ..self.clone()
+        })
+    }
+}
+
+fn all_received(count: u8) -> u32 {
+    u32::MAX.checked_shr(32 - count as u32).unwrap_or(0)
+}
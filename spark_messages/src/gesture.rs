@@ -0,0 +1,229 @@
+//! Chords and sequences of button presses.
+//!
+//! A remote feeds a [`GestureDetector`] every press and release of its buttons, on top of sending
+//! the clicks of single buttons as [`MessageType::ButtonEvent`]s. Two or more buttons held
+//! together make a chord, reported once they are all released. Single buttons tapped one after the
+//! other within [`SEQUENCE_WINDOW_MS`] of the first make a sequence, reported once the window is
+//! over, [`MAX_SEQUENCE_LEN`] taps were made, or a chord interrupts it.
+//!
+//! Times are in milliseconds.
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use crate::{ButtonNumber, MessageType, max_vec_size};
+
+/// Most taps in a [`ButtonSequence`]. A sequence ends early once it has this many.
+pub const MAX_SEQUENCE_LEN: usize = 8;
+
+/// How long after its first press a sequence ends, unless configured otherwise.
+pub const SEQUENCE_WINDOW_MS: u64 = 1_500;
+
+pub(crate) const BUTTONS: [ButtonNumber; 4] = [
+    ButtonNumber::Button1,
+    ButtonNumber::Button2,
+    ButtonNumber::Button3,
+    ButtonNumber::Button4,
+];
+
+/// A set of buttons, such as a chord.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct ButtonSet(u8);
+
+impl ButtonSet {
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    pub fn insert(&mut self, button: ButtonNumber) {
+        self.0 |= 1 << button as u8;
+    }
+
+    pub fn remove(&mut self, button: ButtonNumber) {
+        self.0 &= !(1 << button as u8);
+    }
+
+    pub fn contains(&self, button: ButtonNumber) -> bool {
+        self.0 & (1 << button as u8) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// The buttons in the set, in button order.
+    pub fn iter(&self) -> impl Iterator<Item = ButtonNumber> + '_ {
+        BUTTONS.into_iter().filter(|&button| self.contains(button))
+    }
+}
+
+impl FromIterator<ButtonNumber> for ButtonSet {
+    fn from_iter<I: IntoIterator<Item = ButtonNumber>>(buttons: I) -> Self {
+        let mut set = Self::new();
+        for button in buttons {
+            set.insert(button);
+        }
+        set
+    }
+}
+
+/// Buttons tapped one after the other, in order.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct ButtonSequence {
+    #[cfg_attr(feature = "std", schemars(with = "Vec<ButtonNumber>", length(max = MAX_SEQUENCE_LEN)))]
+    pub buttons: heapless::Vec<ButtonNumber, MAX_SEQUENCE_LEN>,
+}
+
+impl MaxSize for ButtonSequence {
+    const POSTCARD_MAX_SIZE: usize = max_vec_size::<ButtonNumber, MAX_SEQUENCE_LEN>();
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Gesture {
+    Chord(ButtonSet),
+    Sequence(ButtonSequence),
+}
+
+impl From<Gesture> for MessageType {
+    fn from(gesture: Gesture) -> Self {
+        match gesture {
+            Gesture::Chord(buttons) => MessageType::ButtonChord(buttons),
+            Gesture::Sequence(sequence) => MessageType::ButtonSequence(sequence),
+        }
+    }
+}
+
+/// Detects [`Gesture`]s in the presses and releases of buttons.
+#[derive(Debug)]
+pub struct GestureDetector {
+    window_ms: u64,
+    held: ButtonSet,
+    /// The most buttons held together since none were.
+    chord: ButtonSet,
+    /// When a button was last pressed while none were.
+    pressed_at: u64,
+    sequence: ButtonSequence,
+    sequence_started_at: u64,
+}
+
+impl GestureDetector {
+    /// A detector that ends sequences `window_ms` after their first press.
+    pub const fn new(window_ms: u64) -> Self {
+        Self {
+            window_ms,
+            held: ButtonSet::new(),
+            chord: ButtonSet::new(),
+            pressed_at: 0,
+            sequence: ButtonSequence {
+                buttons: heapless::Vec::new(),
+            },
+            sequence_started_at: 0,
+        }
+    }
+
+    /// Record `button` being pressed. Returns the sequence this press ends, if any.
+    pub fn on_press(&mut self, now: u64, button: ButtonNumber) -> Option<Gesture> {
+        if self.held.contains(button) {
+            return None;
+        }
+
+        let mut ended = None;
+        if self.held.is_empty() {
+            if self.sequence_over(now) {
+                ended = self.take_sequence();
+            }
+            self.pressed_at = now;
+            self.chord = ButtonSet::new();
+        }
+
+        self.held.insert(button);
+        if self.held.len() > self.chord.len() {
+            self.chord = self.held;
+        }
+        // A chord, so not a tap.
+        if self.held.len() == 2 {
+            ended = ended.or_else(|| self.take_sequence());
+        }
+        ended
+    }
+
+    /// Record `button` being released. Returns the chord or sequence this release ends, if any.
+    pub fn on_release(&mut self, now: u64, button: ButtonNumber) -> Option<Gesture> {
+        if !self.held.contains(button) {
+            return None;
+        }
+        self.held.remove(button);
+        if !self.held.is_empty() {
+            return None;
+        }
+
+        if self.chord.len() >= 2 {
+            return Some(Gesture::Chord(self.chord));
+        }
+
+        if self.sequence.buttons.is_empty() {
+            self.sequence_started_at = self.pressed_at;
+        }
+        self.sequence.buttons.push(button).ok();
+        if self.sequence.buttons.is_full() || self.sequence_over(now) {
+            self.take_sequence()
+        } else {
+            None
+        }
+    }
+
+    /// The buttons of the chord being held, or of the last one until a button is pressed alone.
+    /// Empty otherwise, so that remotes can hold back what else the buttons do.
+    pub fn chord(&self) -> ButtonSet {
+        if self.chord.len() >= 2 {
+            self.chord
+        } else {
+            ButtonSet::new()
+        }
+    }
+
+    /// Returns the sequence that ended by `now`, if any. Call at
+    /// [`next_due_at`](Self::next_due_at).
+    pub fn poll(&mut self, now: u64) -> Option<Gesture> {
+        if self.held.is_empty() && self.sequence_over(now) {
+            self.take_sequence()
+        } else {
+            None
+        }
+    }
+
+    /// When the sequence being tapped ends, if one is.
+    pub fn next_due_at(&self) -> Option<u64> {
+        if self.sequence.buttons.is_empty() {
+            None
+        } else {
+            Some(self.sequence_started_at + self.window_ms)
+        }
+    }
+
+    fn sequence_over(&self, now: u64) -> bool {
+        self.next_due_at().is_some_and(|due| now >= due)
+    }
+
+    /// A single tap is only a click, not a sequence.
+    fn take_sequence(&mut self) -> Option<Gesture> {
+        let sequence = core::mem::take(&mut self.sequence);
+        if sequence.buttons.len() >= 2 {
+            Some(Gesture::Sequence(sequence))
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for GestureDetector {
+    fn default() -> Self {
+        Self::new(SEQUENCE_WINDOW_MS)
+    }
+}
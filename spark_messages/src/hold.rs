@@ -0,0 +1,151 @@
+//! Holding buttons down for continuous control, such as dimming.
+//!
+//! A remote feeds a [`HoldRepeater`] every press and release of its buttons, and it reports them
+//! as [`ButtonEventType::Pressed`] and [`ButtonEventType::Released`]. While a button stays down, it
+//! reports a [`ButtonEventType::HoldRepeat`] every so often, starting a while after the press so
+//! that clicks don't count as holds. A light turns the repeats of a button into how far to move
+//! with a [`Ramp`].
+//!
+//! Repeats carry how long the button has been held rather than counting steps, so that a light
+//! missing some of them still moves as far as it should. Times are in milliseconds.
+
+use crate::gesture::BUTTONS;
+use crate::{ButtonEventType, ButtonNumber};
+
+/// How long a button is held before it starts repeating, unless configured otherwise.
+pub const HOLD_DELAY_MS: u64 = 500;
+
+/// How often a held button repeats, unless configured otherwise.
+pub const HOLD_REPEAT_MS: u64 = 100;
+
+#[derive(Debug, Copy, Clone)]
+struct Held {
+    pressed_at: u64,
+    next_repeat_at: u64,
+}
+
+/// Turns the presses and releases of buttons into events, repeating while they are held.
+#[derive(Debug)]
+pub struct HoldRepeater {
+    delay_ms: u64,
+    interval_ms: u64,
+    /// In button order.
+    held: [Option<Held>; 4],
+}
+
+impl HoldRepeater {
+    /// Start repeating `delay_ms` after a press, then every `interval_ms`.
+    pub const fn new(delay_ms: u64, interval_ms: u64) -> Self {
+        Self {
+            delay_ms,
+            interval_ms,
+            held: [None; 4],
+        }
+    }
+
+    /// `button` went down at `now`. `None` if it was down already.
+    pub fn on_press(&mut self, now: u64, button: ButtonNumber) -> Option<ButtonEventType> {
+        let held = &mut self.held[button as usize];
+        if held.is_some() {
+            return None;
+        }
+        *held = Some(Held {
+            pressed_at: now,
+            next_repeat_at: now + self.delay_ms,
+        });
+        Some(ButtonEventType::Pressed)
+    }
+
+    /// `button` went up. `None` if it wasn't down.
+    pub fn on_release(&mut self, button: ButtonNumber) -> Option<ButtonEventType> {
+        self.held[button as usize]
+            .take()
+            .map(|_| ButtonEventType::Released)
+    }
+
+    /// The next repeat due by `now`, if any. Call until `None`. A button repeats once however
+    /// late this is called, the repeats it missed are skipped.
+    pub fn poll(&mut self, now: u64) -> Option<(ButtonNumber, ButtonEventType)> {
+        let interval_ms = self.interval_ms.max(1);
+        let (button, held) = BUTTONS
+            .into_iter()
+            .zip(&mut self.held)
+            .filter_map(|(button, held)| Some((button, held.as_mut()?)))
+            .find(|(_, held)| held.next_repeat_at <= now)?;
+
+        let late = now - held.next_repeat_at;
+        held.next_repeat_at += (late / interval_ms + 1) * interval_ms;
+        let elapsed_ms = (now - held.pressed_at).min(u32::MAX as u64) as u32;
+        Some((button, ButtonEventType::HoldRepeat { elapsed_ms }))
+    }
+
+    /// When [`HoldRepeater::poll`] has something to report next, if ever.
+    pub fn next_due_at(&self) -> Option<u64> {
+        self.held
+            .iter()
+            .flatten()
+            .map(|held| held.next_repeat_at)
+            .min()
+    }
+}
+
+impl Default for HoldRepeater {
+    fn default() -> Self {
+        Self::new(HOLD_DELAY_MS, HOLD_REPEAT_MS)
+    }
+}
+
+/// Turns the events of one button into steps to move by, at a steady rate while it is held.
+#[derive(Debug, Copy, Clone)]
+pub struct Ramp {
+    steps_per_s: u32,
+    /// How long the button had been held at the first repeat of the current hold, and at the
+    /// latest one.
+    hold: Option<(u32, u32)>,
+    /// Steps taken since the first repeat.
+    taken: u32,
+}
+
+impl Ramp {
+    pub const fn new(steps_per_s: u32) -> Self {
+        Self {
+            steps_per_s,
+            hold: None,
+            taken: 0,
+        }
+    }
+
+    /// How many steps to move by after `event`. A hold moves from its first repeat, so that the
+    /// delay before repeating doesn't make it jump. Any other event ends the hold.
+    pub fn on_event(&mut self, event: &ButtonEventType) -> u32 {
+        let elapsed_ms = match *event {
+            ButtonEventType::HoldRepeat { elapsed_ms } => elapsed_ms,
+            _ => {
+                self.hold = None;
+                return 0;
+            }
+        };
+
+        match self.hold {
+            Some((first, latest)) if elapsed_ms >= latest => {
+                let total = (elapsed_ms - first) as u64 * self.steps_per_s as u64 / 1000;
+                let total = total.min(u32::MAX as u64) as u32;
+                let steps = total - self.taken;
+                self.hold = Some((first, elapsed_ms));
+                self.taken = total;
+                steps
+            }
+            // The first repeat, or one of a new hold whose release got lost.
+            _ => {
+                self.hold = Some((elapsed_ms, elapsed_ms));
+                self.taken = 0;
+                0
+            }
+        }
+    }
+
+    /// Whether the button is being held, as far as the events go.
+    pub fn is_holding(&self) -> bool {
+        self.hold.is_some()
+    }
+}
@@ -0,0 +1,216 @@
+//! Persistent storage of small state snapshots in NOR flash.
+//!
+//! A region of flash is split in two halves, each used as an append-only log of postcard-encoded
+//! snapshots. Storing a snapshot appends a record to the active half, and only once that half is
+//! full is the other one erased and written to. Flash only survives a limited number of erase
+//! cycles, so this is what lets firmware persist state that changes on every message, like replay
+//! windows. Loading returns the newest intact record, so a power cut in the middle of a write loses
+//! that one update and nothing else.
+
+use crc::{CRC_32_ISO_HDLC, Crc};
+use embedded_storage::nor_flash::NorFlash;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+const MAGIC: u16 = 0x5350;
+/// Magic, payload length, sequence number and CRC of the sequence number and payload.
+const HEADER_LEN: usize = 12;
+/// Records are padded to this, which must be a multiple of the flash's read and write sizes.
+const ALIGN: usize = 4;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+#[derive(Debug)]
+pub enum JournalError<E> {
+    Flash(E),
+    Encode(postcard::Error),
+    Decode(postcard::Error),
+    /// The snapshot doesn't fit in the buffer or in half of the journal.
+    TooLarge,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Record {
+    half: u32,
+    offset: u32,
+    len: usize,
+    seq: u32,
+}
+
+pub struct Journal<F> {
+    flash: F,
+    offset: u32,
+    half_len: u32,
+    latest: Option<Record>,
+    active: u32,
+    /// Where the next record goes in the active half, or `None` if it can't take any more.
+    next: Option<u32>,
+}
+
+impl<F: NorFlash> Journal<F> {
+    /// Use the `len` bytes of `flash` starting at `offset`, which must both be aligned to twice the
+    /// erase size, and find the latest snapshot in there.
+    pub fn new(flash: F, offset: u32, len: u32) -> Result<Self, F::Error> {
+        let erase_size = F::ERASE_SIZE as u32;
+        assert_eq!(offset % erase_size, 0);
+        assert_eq!(len % (2 * erase_size), 0);
+        assert_eq!(ALIGN % F::READ_SIZE, 0);
+        assert_eq!(ALIGN % F::WRITE_SIZE, 0);
+
+        let mut journal = Self {
+            flash,
+            offset,
+            half_len: len / 2,
+            latest: None,
+            active: 0,
+            next: None,
+        };
+
+        let (latest0, next0) = journal.scan(0)?;
+        let (latest1, next1) = journal.scan(1)?;
+        (journal.latest, journal.active, journal.next) = match (latest0, latest1) {
+            (Some(r0), Some(r1)) if r1.seq.wrapping_sub(r0.seq) as i32 > 0 => (latest1, 1, next1),
+            (None, Some(_)) => (latest1, 1, next1),
+            _ => (latest0, 0, next0),
+        };
+        Ok(journal)
+    }
+
+    /// Load the latest snapshot, using `buf` as scratch space.
+    pub fn load<T: DeserializeOwned>(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Result<Option<T>, JournalError<F::Error>> {
+        let Some(record) = self.latest else {
+            return Ok(None);
+        };
+
+        let padded = buf
+            .get_mut(..align(record.len))
+            .ok_or(JournalError::TooLarge)?;
+        self.flash
+            .read(
+                self.address(record.half, record.offset) + HEADER_LEN as u32,
+                padded,
+            )
+            .map_err(JournalError::Flash)?;
+        postcard::from_bytes(&padded[..record.len])
+            .map(Some)
+            .map_err(JournalError::Decode)
+    }
+
+    /// Store a new snapshot, using `buf` as scratch space.
+    pub fn store<T: Serialize>(
+        &mut self,
+        value: &T,
+        buf: &mut [u8],
+    ) -> Result<(), JournalError<F::Error>> {
+        let buf_len = buf.len();
+        let len = postcard::to_slice(value, buf.get_mut(HEADER_LEN..).unwrap_or_default())
+            .map_err(|e| match e {
+                postcard::Error::SerializeBufferFull => JournalError::TooLarge,
+                e => JournalError::Encode(e),
+            })?
+            .len();
+        let record_len = align(HEADER_LEN + len);
+        if record_len > buf_len || record_len > self.half_len as usize {
+            return Err(JournalError::TooLarge);
+        }
+
+        let seq = self.latest.map_or(0, |r| r.seq.wrapping_add(1));
+        let mut digest = CRC.digest();
+        digest.update(&seq.to_le_bytes());
+        digest.update(&buf[HEADER_LEN..HEADER_LEN + len]);
+
+        buf[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[2..4].copy_from_slice(&(len as u16).to_le_bytes());
+        buf[4..8].copy_from_slice(&seq.to_le_bytes());
+        buf[8..12].copy_from_slice(&digest.finalize().to_le_bytes());
+        buf[HEADER_LEN + len..record_len].fill(0xFF);
+
+        let offset = match self.next {
+            Some(next) if next + record_len as u32 <= self.half_len => next,
+            _ => {
+                // Only erase the other half, so the latest snapshot survives until replaced.
+                let other = 1 - self.active;
+                let start = self.address(other, 0);
+                self.flash
+                    .erase(start, start + self.half_len)
+                    .map_err(JournalError::Flash)?;
+                self.active = other;
+                0
+            }
+        };
+
+        self.next = None;
+        self.flash
+            .write(self.address(self.active, offset), &buf[..record_len])
+            .map_err(JournalError::Flash)?;
+        self.next = Some(offset + record_len as u32);
+        self.latest = Some(Record {
+            half: self.active,
+            offset,
+            len,
+            seq,
+        });
+        Ok(())
+    }
+
+    fn address(&self, half: u32, offset: u32) -> u32 {
+        self.offset + half * self.half_len + offset
+    }
+
+    /// Returns the last intact record in `half`, and where the next one can go.
+    fn scan(&mut self, half: u32) -> Result<(Option<Record>, Option<u32>), F::Error> {
+        let mut latest = None;
+        let mut offset = 0;
+
+        while offset + HEADER_LEN as u32 <= self.half_len {
+            let mut header = [0u8; HEADER_LEN];
+            self.flash.read(self.address(half, offset), &mut header)?;
+            if header.iter().all(|&b| b == 0xFF) {
+                return Ok((latest, Some(offset)));
+            }
+
+            let magic = u16::from_le_bytes([header[0], header[1]]);
+            let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+            let seq = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            let crc = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+            let record_len = align(HEADER_LEN + len) as u32;
+            if magic != MAGIC || offset + record_len > self.half_len {
+                break;
+            }
+
+            let mut digest = CRC.digest();
+            digest.update(&seq.to_le_bytes());
+            let mut chunk = [0u8; 16];
+            let mut read = 0;
+            while read < len {
+                let n = (len - read).min(chunk.len());
+                let chunk = &mut chunk[..align(n)];
+                let address = self.address(half, offset) + (HEADER_LEN + read) as u32;
+                self.flash.read(address, chunk)?;
+                digest.update(&chunk[..n]);
+                read += n;
+            }
+            if digest.finalize() != crc {
+                // Torn write. Whatever comes next can't be trusted, or written over.
+                break;
+            }
+
+            latest = Some(Record {
+                half,
+                offset,
+                len,
+                seq,
+            });
+            offset += record_len;
+        }
+
+        Ok((latest, None))
+    }
+}
+
+fn align(len: usize) -> usize {
+    len.next_multiple_of(ALIGN)
+}
@@ -0,0 +1,27 @@
+//! JSON for host tools such as the web dashboard, with the `std` feature.
+//!
+//! Messages are written the way serde derives it: structs as objects of their fields, enum
+//! variants by name, with their fields in an object keyed by the variant. Revisions only ever
+//! append variants and fields, so JSON written for one revision stays valid for the next. Fields
+//! appended to [`Message`] take their defaults when left out, as they do in frames from older
+//! senders. [`json_schema`] describes all of it, and
+//! `schema/message.schema.json` is the schema of the current revision.
+
+use schemars::Schema;
+
+use crate::Message;
+
+impl Message {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("messages serialize to JSON")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+}
+
+/// The JSON Schema of [`Message`], and with it every message type.
+pub fn json_schema() -> Schema {
+    schemars::schema_for!(Message)
+}
@@ -0,0 +1,232 @@
+//! Deriving the key ESP-NOW encrypts a remote's and a light's unicast traffic with.
+//!
+//! Once the [pairing handshake](crate::PairingInitiator) is done, both sides send each other a
+//! [`KeyShare`] holding an X25519 public key, and derive the same local master key ([`Lmk`]) from
+//! the shared secret. The user then confirms pairing with a button press on both devices, so that
+//! neither ends up with a key shared with some other device that happened to be pairing. Each side
+//! sends a [`KeyConfirm`] once confirmed, proving it derived the same key, and only uses the key
+//! once it has received a valid confirmation as well. A lost or garbled share fails the exchange
+//! then, rather than leaving two devices with keys that don't match. Afterwards both sides
+//! register each other as encrypted peers with the key.
+//!
+//! The key and the confirmations are derived with HKDF-SHA256 and HMAC-SHA256 and bound to both
+//! MAC addresses and public keys, see [`KeyExchange::on_share`].
+//!
+//! Like the pairing state machines, [`KeyExchange`] does no I/O and keeps no time. Callers feed it
+//! received messages and the button press, and send [`KeyExchange::pending`] on every retry. Each
+//! side keeps resending its share and its confirmation until the peer's arrives, and answers
+//! repeats of the peer's with its own, as they mean the peer missed it. First copies go
+//! unanswered, so that answers don't bounce back and forth.
+
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::{MacAddress, MessageType};
+
+/// ESP-NOW's local master key, as registered with a peer.
+pub type Lmk = [u8; 16];
+
+/// How long either side waits for the user to confirm pairing on both devices, before giving up
+/// and leaving the link unencrypted.
+pub const KEY_EXCHANGE_TIMEOUT_MS: u64 = 30_000;
+
+/// Either side's public key, sent to the other after the pairing handshake.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct KeyShare {
+    pub public_key: [u8; 32],
+}
+
+/// Sent by either side once the user confirmed pairing on it. `tag` proves the sender derived the
+/// same key.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct KeyConfirm {
+    pub tag: [u8; 32],
+}
+
+/// Which side of the pairing a [`KeyExchange`] is on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+    Remote,
+    Light,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KeyExchangeError {
+    /// The message came from another device than the one being paired with.
+    WrongPeer,
+    /// The peer's public key is one of the few that make for a predictable shared secret.
+    WeakKey,
+    /// The peer sent another public key than before.
+    KeyChanged,
+    /// The user confirmed before the peer's public key arrived.
+    NoPeerKey,
+    /// The peer derived another key, so someone else took part in the exchange.
+    BadConfirmation,
+}
+
+/// What both sides derive from the shared secret.
+struct Keys {
+    peer_public_key: [u8; 32],
+    lmk: Lmk,
+    confirm_key: [u8; 32],
+}
+
+/// One side of the key exchange with `peer`.
+pub struct KeyExchange {
+    role: Role,
+    mac: MacAddress,
+    peer: MacAddress,
+    secret: StaticSecret,
+    keys: Option<Keys>,
+    confirmed: bool,
+    peer_confirmed: bool,
+}
+
+impl KeyExchange {
+    /// Start an exchange with `peer`, from the device at `mac`. `secret` must be 32 fresh random
+    /// bytes, from the hardware random number generator.
+    pub fn new(role: Role, mac: MacAddress, peer: MacAddress, secret: [u8; 32]) -> Self {
+        Self {
+            role,
+            mac,
+            peer,
+            secret: StaticSecret::from(secret),
+            keys: None,
+            confirmed: false,
+            peer_confirmed: false,
+        }
+    }
+
+    pub fn peer(&self) -> MacAddress {
+        self.peer
+    }
+
+    /// The key share to send the peer.
+    pub fn share(&self) -> KeyShare {
+        KeyShare {
+            public_key: PublicKey::from(&self.secret).to_bytes(),
+        }
+    }
+
+    /// Handle the peer's key share, sent by `src`. Returns our own to answer a repeat with.
+    ///
+    /// The key is derived with HKDF-SHA256 from the X25519 shared secret, salted with the
+    /// remote's MAC address then the light's, with the remote's public key then the light's as
+    /// info. The first 16 bytes of output are the [`Lmk`], the next 32 key the confirmations.
+    pub fn on_share(
+        &mut self,
+        src: MacAddress,
+        share: &KeyShare,
+    ) -> Result<Option<KeyShare>, KeyExchangeError> {
+        if src != self.peer {
+            return Err(KeyExchangeError::WrongPeer);
+        }
+        match &self.keys {
+            Some(keys) if keys.peer_public_key != share.public_key => {
+                return Err(KeyExchangeError::KeyChanged);
+            }
+            Some(_) => return Ok(Some(self.share())),
+            None => {}
+        }
+
+        let shared = self
+            .secret
+            .diffie_hellman(&PublicKey::from(share.public_key));
+        if !shared.was_contributory() {
+            return Err(KeyExchangeError::WeakKey);
+        }
+
+        let own_public_key = self.share().public_key;
+        let (macs, public_keys) = match self.role {
+            Role::Remote => ([self.mac, self.peer], [own_public_key, share.public_key]),
+            Role::Light => ([self.peer, self.mac], [share.public_key, own_public_key]),
+        };
+        let mut okm = [0; 48];
+        Hkdf::<Sha256>::new(Some(macs.as_flattened()), shared.as_bytes())
+            .expand(public_keys.as_flattened(), &mut okm)
+            .expect("48 bytes is a valid length for HKDF-SHA256");
+        let (lmk, confirm_key) = okm.split_at(16);
+        self.keys = Some(Keys {
+            peer_public_key: share.public_key,
+            lmk: lmk.try_into().unwrap(),
+            confirm_key: confirm_key.try_into().unwrap(),
+        });
+        Ok(None)
+    }
+
+    /// The user confirmed pairing on this device. Returns the confirmation to send the peer.
+    pub fn confirm(&mut self) -> Result<KeyConfirm, KeyExchangeError> {
+        let keys = self.keys.as_ref().ok_or(KeyExchangeError::NoPeerKey)?;
+        self.confirmed = true;
+        Ok(key_confirm(keys, self.role))
+    }
+
+    /// Handle the peer's confirmation, sent by `src`. Returns our own to answer a repeat with, if
+    /// the user confirmed already.
+    pub fn on_confirm(
+        &mut self,
+        src: MacAddress,
+        confirm: &KeyConfirm,
+    ) -> Result<Option<KeyConfirm>, KeyExchangeError> {
+        if src != self.peer {
+            return Err(KeyExchangeError::WrongPeer);
+        }
+        let keys = self.keys.as_ref().ok_or(KeyExchangeError::NoPeerKey)?;
+        let peer_role = match self.role {
+            Role::Remote => Role::Light,
+            Role::Light => Role::Remote,
+        };
+        confirmation_mac(keys, peer_role)
+            .verify_slice(&confirm.tag)
+            .map_err(|_| KeyExchangeError::BadConfirmation)?;
+
+        let repeat = core::mem::replace(&mut self.peer_confirmed, true);
+        if self.confirmed && repeat {
+            self.confirm().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// What to send the peer again on every retry: our key share until theirs arrives, then our
+    /// confirmation until theirs does.
+    pub fn pending(&self) -> Option<MessageType> {
+        match &self.keys {
+            None => Some(MessageType::KeyShare(self.share())),
+            Some(keys) if self.confirmed && !self.peer_confirmed => {
+                Some(MessageType::KeyConfirm(key_confirm(keys, self.role)))
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// The derived key, once both sides confirmed.
+    pub fn lmk(&self) -> Option<Lmk> {
+        let keys = self.keys.as_ref()?;
+        (self.confirmed && self.peer_confirmed).then_some(keys.lmk)
+    }
+}
+
+/// The confirmation `role` sends.
+fn key_confirm(keys: &Keys, role: Role) -> KeyConfirm {
+    KeyConfirm {
+        tag: confirmation_mac(keys, role).finalize().into_bytes().into(),
+    }
+}
+
+/// HMAC-SHA256 of the role's name, keyed with the confirmation key.
+fn confirmation_mac(keys: &Keys, role: Role) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(&keys.confirm_key).expect("HMAC accepts any key length");
+    mac.update(match role {
+        Role::Remote => b"remote",
+        Role::Light => b"light",
+    });
+    mac
+}
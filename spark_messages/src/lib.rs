@@ -1,51 +1,278 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 
 use async_button::ButtonEvent;
+use postcard::experimental::max_size::MaxSize;
 use serde::{Serialize, Deserialize};
 
-// /// Remote (master) => light (slave)
-// #[derive(Serialize, Deserialize, Debug)]
-// pub struct Handshake {
-//     pub remote_mac: [u8; 6],
-// }
-//
-// /// Light (slave) => remote (master)
-// #[derive(Serialize, Deserialize, Debug)]
-// pub struct HandshakeResponse {
-//     pub light_mac: [u8; 6],
-// }
-//
-// #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Copy, Clone)]
-// pub enum Button {
-//     Button0,
-//     Button1,
-//     Button2,
-//     Button3,
-// }
-//
-// #[derive(Serialize, Deserialize, Debug)]
-// pub struct ButtonSequence {
-//     pub buttons: smallvec::SmallVec<[Button; 5]>,
-// }
-
-pub const PROTOCOL_VERSION: u8 = 0;
-
-#[derive(Serialize, Deserialize, Debug)]
+mod address;
+mod auth;
+mod clock;
+mod config;
+mod dedup;
+mod delivery;
+mod discovery;
+mod fragment;
+mod gesture;
+mod hold;
+#[cfg(feature = "std")]
+mod json;
+mod journal;
+mod key_exchange;
+mod link;
+mod ota;
+mod pairing;
+mod relay;
+mod replay;
+mod scene;
+mod serial;
+mod stream;
+mod text;
+mod version;
+
+pub use address::{Address, Destination, DeviceId, GroupMask};
+pub use auth::{AuthError, Key, OpenError, TAG_LEN, open, parse_key, sign, verify};
+pub use clock::{ClockSync, SYNC_INTERVAL_MS, TimeRequest, TimeResponse};
+pub use config::{
+    Config, ConfigEntry, ConfigError, ConfigInfo, ConfigKey, ConfigType, ConfigValue,
+};
+pub use dedup::{DEDUP_WINDOW_MS, DedupCache, MessageId, MessageIds, NO_MESSAGE_ID};
+pub use delivery::{DeliveryEvent, Retransmitter, RetryPolicy};
+pub use discovery::{Announce, Inventory, MAX_STRIPS, PatternSet, ProtocolVersions};
+pub use fragment::{
+    FRAGMENT_LEN, Fragment, FragmentError, MAX_FRAGMENTS, MAX_FRAME_LEN, REASSEMBLY_TIMEOUT_MS,
+    Reassembler, fragment,
+};
+pub use gesture::{
+    ButtonSequence, ButtonSet, Gesture, GestureDetector, MAX_SEQUENCE_LEN, SEQUENCE_WINDOW_MS,
+};
+#[cfg(feature = "std")]
+pub use json::json_schema;
+pub use hold::{HOLD_DELAY_MS, HOLD_REPEAT_MS, HoldRepeater, Ramp};
+pub use journal::{Journal, JournalError};
+pub use key_exchange::{
+    KEY_EXCHANGE_TIMEOUT_MS, KeyConfirm, KeyExchange, KeyExchangeError, KeyShare, Lmk, Role,
+};
+pub use link::{HEARTBEAT_INTERVAL_MS, Heartbeat, LinkMonitor, LinkStats, ResetReason};
+pub use ota::{OTA_CHUNK_LEN, OtaBegin, OtaChunk, OtaError, OtaReceiver, OtaState, OtaStatus};
+pub use pairing::{
+    CONFIRM_TIMEOUT_MS, HANDSHAKE_RETRY_MS, HANDSHAKE_TIMEOUT_MS, InitiatorState,
+    PAIRING_WINDOW_MS, PairingInitiator, PairingResponder, ResponderState,
+};
+pub use relay::MAX_RELAY_HOPS;
+pub use replay::{
+    COUNTER_RESERVATION, REPLAY_WINDOW_SIZE, ReceiveWindow, ReplayWindow, SendCounter,
+};
+pub use scene::{MAX_SCENES, Scene, SceneBook, SceneError, SceneSet, SceneStore};
+pub use serial::{FrameError, FrameReader, MAX_SERIAL_FRAME_LEN, encode_frame};
+pub use stream::{MAX_FRAME_PIXELS, PixelBuffer, PixelFrame, PixelFrameError, STREAM_TIMEOUT_MS};
+pub use text::{DEFAULT_ANIMATION, ParseError};
+pub use version::{DecodeError, EncodeError, MAX_ENCODED_LEN};
+
+use version::max_vec_size;
+
+pub type MacAddress = [u8; 6];
+
+/// Remote (master) => light (slave), broadcast while the remote is looking for a light to pair
+/// with.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct Handshake {
+    pub remote_mac: MacAddress,
+}
+
+/// Light (slave) => remote (master), unicast answer to a [`Handshake`].
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct HandshakeResponse {
+    pub light_mac: MacAddress,
+    pub capabilities: Capabilities,
+}
+
+/// Remote (master) => light (slave), unicast. Completes pairing.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct HandshakeConfirm {
+    pub light_mac: MacAddress,
+}
+
+/// What a light can drive, reported while pairing.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct Capabilities {
+    pub strips: u8,
+    pub leds_per_strip: u16,
+}
+
+/// What a light shows, set with [`MessageType::SetAnimation`].
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct SetAnimation {
+    pub pattern: Pattern,
+    /// The color the pattern starts from.
+    pub color: Color,
+    /// How fast the pattern moves, in steps per second. A full cycle of hues, fading or blinking
+    /// takes 256 steps.
+    pub speed: u8,
+    pub brightness: u8,
+    /// How long to play the animation before going dark. `None` latches it until the next one.
+    pub duration_ms: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum Pattern {
+    Off,
+    /// The base color, unchanging.
+    Solid,
+    /// Every LED cycles through the hues, starting from the base color.
+    HueSweep,
+    /// The base color fading in and out.
+    Breathe,
+    /// The base color switching on and off.
+    Blink,
+    /// The hues spread out along the strip, moving.
+    Rainbow,
+}
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub enum Color {
+    Hsv { hue: u8, sat: u8, val: u8 },
+    Rgb { r: u8, g: u8, b: u8 },
+}
+
+/// Light (slave) => remote (master), answers [`MessageType::StatusRequest`] and is sent to every
+/// paired remote whenever what the light shows changes. Changes in quick succession may go out
+/// as one status, the latest.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct LightStatus {
+    pub mode: LightMode,
+    /// The pattern being played, [`Pattern::Off`] while the light is off.
+    pub pattern: Pattern,
+    pub brightness: u8,
+    pub uptime_ms: u64,
+    pub firmware_version: FirmwareVersion,
+    /// The last thing that went wrong since boot, if anything did.
+    pub last_error: Option<ErrorCode>,
+}
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum LightMode {
+    Off,
+    /// Playing an animation until its duration is up.
+    Timed,
+    /// Playing an animation until told otherwise.
+    Latched,
+    /// Showing pixels streamed with [`MessageType::PixelFrame`].
+    Streaming,
+}
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// A frame failed to send.
+    SendFailed,
+    /// A frame didn't carry a valid tag.
+    Unauthenticated,
+    /// An authenticated frame didn't decode.
+    Malformed,
+    /// A message came from an unpaired device, or was a replay.
+    Rejected,
+    /// A fragment couldn't be reassembled.
+    Fragment,
+}
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl FirmwareVersion {
+    /// Parse a `major.minor.patch` version, ignoring any pre-release or build suffix. Meant to be
+    /// used on `env!("CARGO_PKG_VERSION")` in a `const`.
+    pub const fn parse(version: &str) -> Self {
+        let version = version.as_bytes();
+        let mut parts = [0u8; 3];
+        let mut part = 0;
+        let mut i = 0;
+        while i < version.len() {
+            match version[i] {
+                c @ b'0'..=b'9' => parts[part] = parts[part] * 10 + (c - b'0'),
+                b'.' if part < 2 => part += 1,
+                b'-' | b'+' => break,
+                _ => panic!("version must be major.minor.patch"),
+            }
+            i += 1;
+        }
+
+        Self {
+            major: parts[0],
+            minor: parts[1],
+            patch: parts[2],
+        }
+    }
+}
+
+/// Newest protocol revision this crate understands. See the `version` module for the rules that
+/// keep revisions compatible with each other.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Oldest protocol revision this crate decodes. Revision 0 frames aren't signed, so they decode
+/// but don't pass [`open`].
+pub const MIN_PROTOCOL_VERSION: u8 = 0;
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 pub struct Message {
     pub protocol_version: u8,
-    pub message_type: MessageType
+    pub message_type: MessageType,
+    /// Numbers the sender's messages, see [`SendCounter`]. 0 for senders predating revision 1.
+    #[serde(default)]
+    pub counter: u32,
+    /// Which lights should act on the message. All of them for senders predating addressing.
+    #[serde(default)]
+    pub destination: Destination,
+    /// Tells copies of the message apart from other messages, see [`DedupCache`].
+    /// [`NO_MESSAGE_ID`] for senders predating message IDs.
+    #[serde(default)]
+    pub id: MessageId,
+    /// How many lights relayed the message, see [`Message::relayed`]. 0 if it came straight from
+    /// its sender.
+    #[serde(default)]
+    pub hops: u8,
+    /// The sender the message came from, if a light relayed it. See [`Message::origin`].
+    #[serde(default)]
+    pub origin: Option<MacAddress>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, MaxSize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 #[non_exhaustive]
 pub enum ButtonEventType {
     ShortPress {
         count: usize,
     },
     LongPress,
+    /// The button went down. Sent as well as, not instead of, the clicks it ends up making.
+    Pressed,
+    Released,
+    /// The button is still down, `elapsed_ms` after it went down. Repeats while the button is
+    /// held, see [`HoldRepeater`].
+    HoldRepeat {
+        elapsed_ms: u32,
+    },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 pub enum ButtonNumber {
     Button1,
     Button2,
@@ -53,13 +280,80 @@ pub enum ButtonNumber {
     Button4,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, MaxSize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
 #[non_exhaustive]
 pub enum MessageType {
     ButtonEvent {
         button_number: ButtonNumber,
         event_type: ButtonEventType,
-    }
+    },
+    Handshake(Handshake),
+    HandshakeResponse(HandshakeResponse),
+    HandshakeConfirm(HandshakeConfirm),
+    /// Acknowledges the message numbered `counter`, see [`Retransmitter`].
+    Ack {
+        counter: u32,
+    },
+    /// Part of a payload too large for one frame, see [`Reassembler`].
+    Fragment(Fragment),
+    SetAnimation(SetAnimation),
+    /// Asks a light for its [`LightStatus`].
+    StatusRequest,
+    LightStatus(LightStatus),
+    /// Changes the [`Address`] of the lights it is addressed to.
+    SetAddress(Address),
+    TimeRequest(TimeRequest),
+    TimeResponse(TimeResponse),
+    /// Starts or resumes a firmware update, see [`OtaReceiver`].
+    OtaBegin(OtaBegin),
+    OtaChunk(OtaChunk),
+    OtaStatus(OtaStatus),
+    /// Asks the light to check the image it received and boot it.
+    OtaCommit,
+    /// Asks a light for the value of a setting, see [`Config`].
+    ConfigGet(ConfigKey),
+    ConfigSet(ConfigEntry),
+    /// Asks a light for the value of every setting, which it answers one by one.
+    ConfigList,
+    /// Answers [`MessageType::ConfigGet`], [`MessageType::ConfigSet`] and
+    /// [`MessageType::ConfigList`].
+    ConfigReply(Result<ConfigEntry, ConfigError>),
+    /// Asks the lights it is addressed to to [`Announce`] themselves.
+    Discover,
+    Announce(Announce),
+    Heartbeat(Heartbeat),
+    /// Asks for the [`LinkStats`] the receiver keeps on `peer`.
+    LinkStatsRequest {
+        peer: MacAddress,
+    },
+    /// Answers [`MessageType::LinkStatsRequest`], `None` if the receiver never heard from `peer`.
+    LinkStats {
+        peer: MacAddress,
+        stats: Option<LinkStats>,
+    },
+    /// Buttons held together, see [`GestureDetector`].
+    ButtonChord(ButtonSet),
+    /// Buttons tapped one after the other, see [`GestureDetector`].
+    ButtonSequence(ButtonSequence),
+    /// Saves a [`Scene`] on the lights it is addressed to.
+    SceneStore(SceneStore),
+    /// Plays the scene saved in `slot`.
+    SceneRecall {
+        slot: u8,
+    },
+    /// Asks a light which slots hold a scene.
+    SceneList,
+    /// Answers [`MessageType::SceneStore`], [`MessageType::SceneRecall`] and
+    /// [`MessageType::SceneList`] with the slots holding a scene.
+    SceneReply(Result<SceneSet, SceneError>),
+    /// Either side's public key, once paired. See [`KeyExchange`].
+    KeyShare(KeyShare),
+    /// Either side's proof of the key derived from the [`MessageType::KeyShare`]s, once the user
+    /// confirmed pairing on it.
+    KeyConfirm(KeyConfirm),
+    /// Pixels for a light to show while streaming, see [`PixelBuffer`].
+    PixelFrame(PixelFrame),
 }
 
 impl From<ButtonEvent> for ButtonEventType {
@@ -0,0 +1,181 @@
+//! Link quality between devices.
+//!
+//! Lights and remotes send every device they are paired with a [`Heartbeat`] every
+//! [`HEARTBEAT_INTERVAL_MS`], so that a device that stops responding can be told apart from one
+//! that is out of range or keeps crashing, and so that both ends know how lossy the link is. A
+//! [`LinkMonitor`] keeps statistics on every peer it hears from: signal strength and last-seen
+//! time from any frame, and losses and restarts from heartbeats. Devices answer
+//! [`MessageType::LinkStatsRequest`](crate::MessageType::LinkStatsRequest) with the [`LinkStats`]
+//! they keep on a peer.
+//!
+//! Times are in milliseconds.
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use crate::MacAddress;
+
+pub const HEARTBEAT_INTERVAL_MS: u64 = 10_000;
+
+/// The latest frame weighs `1 / RSSI_SMOOTHING` in the average signal strength.
+const RSSI_SMOOTHING: i32 = 8;
+
+/// Light => every paired remote and remote => every paired light, periodically.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct Heartbeat {
+    /// Numbers heartbeats from 0 after every boot, so that gaps reveal lost ones.
+    pub seq: u32,
+    pub uptime_ms: u64,
+    /// Why the sender last booted.
+    pub reset_reason: ResetReason,
+}
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum ResetReason {
+    PowerOn,
+    /// Firmware asked for it, e.g. after an update. Panics end up here too.
+    Software,
+    Watchdog,
+    Brownout,
+    DeepSleep,
+    Other,
+}
+
+/// What a device knows about its link to a peer.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct LinkStats {
+    /// Signal strength of the latest frame, in dBm.
+    pub rssi: i8,
+    /// Signal strength averaged over recent frames, in dBm.
+    pub rssi_avg: i8,
+    pub since_last_seen_ms: u64,
+    pub heartbeats: u32,
+    /// Heartbeats that never arrived, going by gaps in their sequence numbers.
+    pub heartbeats_lost: u32,
+    /// How often the peer rebooted while being monitored.
+    pub restarts: u32,
+    pub last_heartbeat: Option<Heartbeat>,
+}
+
+impl LinkStats {
+    /// Share of heartbeats lost, from 0 to 1.
+    pub fn loss_rate(&self) -> f32 {
+        let expected = self.heartbeats + self.heartbeats_lost;
+        if expected == 0 {
+            0.0
+        } else {
+            self.heartbeats_lost as f32 / expected as f32
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Peer {
+    rssi: i8,
+    /// Scaled by [`RSSI_SMOOTHING`].
+    rssi_avg: i32,
+    last_seen_ms: u64,
+    heartbeats: u32,
+    heartbeats_lost: u32,
+    restarts: u32,
+    last_heartbeat: Option<Heartbeat>,
+}
+
+/// Keeps [`LinkStats`] on up to `N` peers. Once full, the peer heard from the longest ago is
+/// forgotten.
+pub struct LinkMonitor<const N: usize> {
+    peers: heapless::Vec<(MacAddress, Peer), N>,
+}
+
+impl<const N: usize> LinkMonitor<N> {
+    pub const fn new() -> Self {
+        Self {
+            peers: heapless::Vec::new(),
+        }
+    }
+
+    /// Record a frame from `src`, received with signal strength `rssi`. Only authenticated frames
+    /// should count.
+    pub fn on_frame(&mut self, now: u64, src: MacAddress, rssi: i8) {
+        match self.peers.iter_mut().find(|(mac, _)| *mac == src) {
+            Some((_, peer)) => {
+                peer.rssi = rssi;
+                peer.rssi_avg +=
+                    div_round(rssi as i32 * RSSI_SMOOTHING - peer.rssi_avg, RSSI_SMOOTHING);
+                peer.last_seen_ms = now;
+            }
+            None => {
+                if self.peers.is_full() {
+                    let oldest = (0..self.peers.len())
+                        .min_by_key(|&i| self.peers[i].1.last_seen_ms)
+                        .unwrap();
+                    self.peers.swap_remove(oldest);
+                }
+                let peer = Peer {
+                    rssi,
+                    rssi_avg: rssi as i32 * RSSI_SMOOTHING,
+                    last_seen_ms: now,
+                    heartbeats: 0,
+                    heartbeats_lost: 0,
+                    restarts: 0,
+                    last_heartbeat: None,
+                };
+                self.peers.push((src, peer)).ok();
+            }
+        }
+    }
+
+    /// Record a heartbeat from `src`, after [`on_frame`](Self::on_frame). Returns whether the peer
+    /// restarted since its last one.
+    pub fn on_heartbeat(&mut self, src: MacAddress, heartbeat: &Heartbeat) -> bool {
+        let Some((_, peer)) = self.peers.iter_mut().find(|(mac, _)| *mac == src) else {
+            return false;
+        };
+
+        let restarted = match peer.last_heartbeat {
+            // Counting from 0 again.
+            Some(last) if heartbeat.uptime_ms < last.uptime_ms => {
+                peer.restarts += 1;
+                peer.heartbeats_lost += heartbeat.seq;
+                true
+            }
+            Some(last) if heartbeat.seq <= last.seq => return false,
+            Some(last) => {
+                peer.heartbeats_lost += heartbeat.seq - last.seq - 1;
+                false
+            }
+            None => false,
+        };
+        peer.heartbeats += 1;
+        peer.last_heartbeat = Some(*heartbeat);
+        restarted
+    }
+
+    pub fn stats(&self, now: u64, mac: MacAddress) -> Option<LinkStats> {
+        let (_, peer) = self.peers.iter().find(|(known, _)| *known == mac)?;
+        Some(LinkStats {
+            rssi: peer.rssi,
+            rssi_avg: div_round(peer.rssi_avg, RSSI_SMOOTHING) as i8,
+            since_last_seen_ms: now.saturating_sub(peer.last_seen_ms),
+            heartbeats: peer.heartbeats,
+            heartbeats_lost: peer.heartbeats_lost,
+            restarts: peer.restarts,
+            last_heartbeat: peer.last_heartbeat,
+        })
+    }
+}
+
+/// Rounds to the nearest, so that the average doesn't get stuck short of a steady signal.
+fn div_round(n: i32, d: i32) -> i32 {
+    (n + n.signum() * d / 2) / d
+}
+
+impl<const N: usize> Default for LinkMonitor<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
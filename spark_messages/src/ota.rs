@@ -0,0 +1,264 @@
+//! Firmware updates over ESP-NOW.
+//!
+//! An update is a session: [`OtaBegin`] announces the size and SHA-256 of the image, [`OtaChunk`]s
+//! carry it in order, and [`MessageType::OtaCommit`] asks the light to check it and boot it. The
+//! light answers a begin, a commit, the last chunk and any chunk that isn't the next one it
+//! expects with an [`OtaStatus`] telling how much of the image it has. The sender carries on from
+//! there, so a lost chunk costs a rewind rather than the whole transfer. Sending the same
+//! [`OtaBegin`] again, e.g. after losing the link for a while, resumes the session.
+//!
+//! [`OtaReceiver`] writes the image to flash as it arrives, and hashes it back out of flash before
+//! committing, so that what gets booted is what was sent.
+//!
+//! [`MessageType::OtaCommit`]: crate::MessageType::OtaCommit
+
+use embedded_storage::nor_flash::NorFlash;
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::max_vec_size;
+
+/// Most image bytes in an [`OtaChunk`]. Leaves room in a frame for the rest of the message, and
+/// is a multiple of the flash write size.
+pub const OTA_CHUNK_LEN: usize = 192;
+
+/// How much is read from flash at a time while hashing the image.
+const READ_LEN: usize = 64;
+
+/// Sender => light, starts or resumes an update.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct OtaBegin {
+    pub size: u32,
+    pub sha256: [u8; 32],
+}
+
+/// Sender => light, the image bytes starting at `offset`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct OtaChunk {
+    pub offset: u32,
+    #[cfg_attr(feature = "std", schemars(with = "Vec<u8>", length(max = OTA_CHUNK_LEN)))]
+    pub data: heapless::Vec<u8, OTA_CHUNK_LEN>,
+}
+
+impl MaxSize for OtaChunk {
+    const POSTCARD_MAX_SIZE: usize = u32::POSTCARD_MAX_SIZE + max_vec_size::<u8, OTA_CHUNK_LEN>();
+}
+
+/// Light => sender, how far an update got.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct OtaStatus {
+    pub state: OtaState,
+    /// How many bytes of the image the light has, i.e. the offset of the next chunk to send.
+    pub received: u32,
+    /// Why the last begin, chunk or commit failed.
+    pub error: Option<OtaError>,
+}
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum OtaState {
+    /// No update in progress.
+    Idle,
+    Receiving,
+    /// The whole image arrived, waiting for the commit.
+    Complete,
+    /// The image checked out, and the light is about to boot it.
+    Committed,
+}
+
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum OtaError {
+    /// A chunk or commit arrived without a session to go with it.
+    NoSession,
+    /// The image doesn't fit in the update partition.
+    TooLarge,
+    /// Writing to or reading from flash failed.
+    Flash,
+    /// The commit arrived before the whole image.
+    Incomplete,
+    /// The image in flash doesn't hash to what [`OtaBegin`] announced. The session starts over.
+    HashMismatch,
+}
+
+#[derive(Debug)]
+struct Session {
+    begin: OtaBegin,
+    received: u32,
+    /// How much of the region has been erased for this session, from its start.
+    erased: u32,
+    committed: bool,
+}
+
+/// Receives an image into a region of flash.
+pub struct OtaReceiver<F> {
+    flash: F,
+    offset: u32,
+    len: u32,
+    session: Option<Session>,
+    error: Option<OtaError>,
+}
+
+impl<F: NorFlash> OtaReceiver<F> {
+    /// Receive into the `len` bytes of `flash` starting at `offset`, which must both be aligned to
+    /// the erase size.
+    pub fn new(flash: F, offset: u32, len: u32) -> Self {
+        let erase_size = F::ERASE_SIZE as u32;
+        assert_eq!(offset % erase_size, 0);
+        assert_eq!(len % erase_size, 0);
+        assert_eq!(OTA_CHUNK_LEN % F::WRITE_SIZE, 0);
+        assert_eq!(READ_LEN % F::READ_SIZE, 0);
+
+        Self {
+            flash,
+            offset,
+            len,
+            session: None,
+            error: None,
+        }
+    }
+
+    pub fn status(&self) -> OtaStatus {
+        let (state, received) = match &self.session {
+            None => (OtaState::Idle, 0),
+            Some(session) if session.committed => (OtaState::Committed, session.received),
+            Some(session) if session.received == session.begin.size => {
+                (OtaState::Complete, session.received)
+            }
+            Some(session) => (OtaState::Receiving, session.received),
+        };
+        OtaStatus {
+            state,
+            received,
+            error: self.error,
+        }
+    }
+
+    /// Start an update, or resume it if it's the one in progress.
+    pub fn on_begin(&mut self, begin: &OtaBegin) -> OtaStatus {
+        self.error = None;
+        match &self.session {
+            Some(session) if session.begin == *begin => {}
+            _ if begin.size > self.len => {
+                self.session = None;
+                self.error = Some(OtaError::TooLarge);
+            }
+            _ => {
+                self.session = Some(Session {
+                    begin: *begin,
+                    received: 0,
+                    erased: 0,
+                    committed: false,
+                })
+            }
+        }
+        self.status()
+    }
+
+    /// Write a chunk if it's the next one. Returns the status to answer with, if any.
+    pub fn on_chunk(&mut self, chunk: &OtaChunk) -> Option<OtaStatus> {
+        self.error = None;
+        let Some(session) = &mut self.session else {
+            self.error = Some(OtaError::NoSession);
+            return Some(self.status());
+        };
+
+        let len = chunk.data.len() as u32;
+        let end = chunk.offset.saturating_add(len);
+        let last = end == session.begin.size;
+        // Only the last chunk may leave the next write unaligned.
+        let aligned = last || end.next_multiple_of(F::WRITE_SIZE as u32) == end;
+        if session.committed
+            || chunk.offset != session.received
+            || len == 0
+            || end > session.begin.size
+            || !aligned
+        {
+            return Some(self.status());
+        }
+
+        if let Err(error) = Self::write(&mut self.flash, self.offset, session, &chunk.data) {
+            self.error = Some(error);
+            return Some(self.status());
+        }
+        session.received = end;
+
+        last.then(|| self.status())
+    }
+
+    /// Check the image in flash against the hash it was announced with.
+    pub fn on_commit(&mut self) -> OtaStatus {
+        let Some(session) = &mut self.session else {
+            self.error = Some(OtaError::NoSession);
+            return self.status();
+        };
+        if session.received != session.begin.size {
+            self.error = Some(OtaError::Incomplete);
+            return self.status();
+        }
+
+        let mut hasher = Sha256::new();
+        let mut buf = [0u8; READ_LEN];
+        let mut read = 0;
+        while read < session.begin.size {
+            let n = ((session.begin.size - read) as usize).min(READ_LEN);
+            let buf = &mut buf[..n.next_multiple_of(F::READ_SIZE)];
+            if self.flash.read(self.offset + read, buf).is_err() {
+                self.error = Some(OtaError::Flash);
+                return self.status();
+            }
+            hasher.update(&buf[..n]);
+            read += n as u32;
+        }
+
+        if hasher.finalize().as_slice() == session.begin.sha256 {
+            session.committed = true;
+            self.error = None;
+        } else {
+            session.received = 0;
+            session.erased = 0;
+            self.error = Some(OtaError::HashMismatch);
+        }
+        self.status()
+    }
+
+    /// Whether the image checked out and can be booted.
+    pub fn is_committed(&self) -> bool {
+        self.session.as_ref().is_some_and(|s| s.committed)
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    fn write(
+        flash: &mut F,
+        offset: u32,
+        session: &mut Session,
+        data: &[u8],
+    ) -> Result<(), OtaError> {
+        let start = session.received;
+        let end = start + data.len() as u32;
+        if end > session.erased {
+            let erase_end = end.next_multiple_of(F::ERASE_SIZE as u32);
+            flash
+                .erase(offset + session.erased, offset + erase_end)
+                .map_err(|_| OtaError::Flash)?;
+            session.erased = erase_end;
+        }
+
+        // Pad the last chunk to the write size. Erased flash reads as 0xFF, so that's a no-op.
+        let mut buf = [0xFF; OTA_CHUNK_LEN];
+        buf[..data.len()].copy_from_slice(data);
+        let padded = &buf[..data.len().next_multiple_of(F::WRITE_SIZE)];
+        flash
+            .write(offset + start, padded)
+            .map_err(|_| OtaError::Flash)
+    }
+}
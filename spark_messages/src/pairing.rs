@@ -0,0 +1,260 @@
+//! Pairing handshake between a remote and a light.
+//!
+//! 1. The remote broadcasts a [`Handshake`] until a light answers or it gives up.
+//! 2. A light in pairing mode answers the first remote it hears with a unicast
+//!    [`HandshakeResponse`], repeating it until confirmed. Other remotes are ignored meanwhile.
+//! 3. The remote answers with a unicast [`HandshakeConfirm`] and both sides switch to unicast.
+//!
+//! Neither state machine does any I/O or keeps time itself: callers feed them received messages
+//! and call `poll` regularly with the current time in milliseconds, and send whatever they return.
+
+use crate::{Capabilities, Handshake, HandshakeConfirm, HandshakeResponse, MacAddress};
+
+/// How often unanswered handshakes and unconfirmed responses are sent again.
+pub const HANDSHAKE_RETRY_MS: u64 = 250;
+/// How long a remote looks for a light before giving up.
+pub const HANDSHAKE_TIMEOUT_MS: u64 = 10_000;
+/// How long a light stays in pairing mode.
+pub const PAIRING_WINDOW_MS: u64 = 30_000;
+/// How long a light waits for the remote it answered before listening to other remotes again.
+pub const CONFIRM_TIMEOUT_MS: u64 = 2_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InitiatorState {
+    Idle,
+    Searching {
+        started_at: u64,
+        sent_at: u64,
+    },
+    Paired {
+        light_mac: MacAddress,
+        capabilities: Capabilities,
+    },
+    TimedOut,
+}
+
+/// Remote side of the handshake.
+pub struct PairingInitiator {
+    remote_mac: MacAddress,
+    state: InitiatorState,
+}
+
+impl PairingInitiator {
+    pub fn new(remote_mac: MacAddress) -> Self {
+        Self {
+            remote_mac,
+            state: InitiatorState::Idle,
+        }
+    }
+
+    pub fn state(&self) -> InitiatorState {
+        self.state
+    }
+
+    /// Start looking for a light. Returns the handshake to broadcast.
+    pub fn start(&mut self, now: u64) -> Handshake {
+        self.state = InitiatorState::Searching {
+            started_at: now,
+            sent_at: now,
+        };
+        self.handshake()
+    }
+
+    /// Returns the handshake to broadcast again if no light has answered in a while.
+    pub fn poll(&mut self, now: u64) -> Option<Handshake> {
+        let InitiatorState::Searching {
+            started_at,
+            sent_at,
+        } = self.state
+        else {
+            return None;
+        };
+
+        if now.saturating_sub(started_at) >= HANDSHAKE_TIMEOUT_MS {
+            self.state = InitiatorState::TimedOut;
+            None
+        } else if now.saturating_sub(sent_at) >= HANDSHAKE_RETRY_MS {
+            self.state = InitiatorState::Searching {
+                started_at,
+                sent_at: now,
+            };
+            Some(self.handshake())
+        } else {
+            None
+        }
+    }
+
+    /// Handle a response sent by `src`. Returns the confirmation to unicast back to it.
+    ///
+    /// The first light to answer wins. Its repeated responses are confirmed again, since they mean
+    /// the previous confirmation got lost.
+    pub fn on_response(
+        &mut self,
+        src: MacAddress,
+        response: &HandshakeResponse,
+    ) -> Option<HandshakeConfirm> {
+        if src != response.light_mac {
+            return None;
+        }
+
+        match self.state {
+            InitiatorState::Searching { .. } => {
+                self.state = InitiatorState::Paired {
+                    light_mac: src,
+                    capabilities: response.capabilities,
+                };
+            }
+            InitiatorState::Paired { light_mac, .. } if light_mac == src => {}
+            _ => return None,
+        }
+
+        Some(HandshakeConfirm { light_mac: src })
+    }
+
+    fn handshake(&self) -> Handshake {
+        Handshake {
+            remote_mac: self.remote_mac,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResponderState {
+    Idle,
+    Listening {
+        window_ends_at: u64,
+    },
+    Answering {
+        remote_mac: MacAddress,
+        window_ends_at: u64,
+        answered_at: u64,
+        sent_at: u64,
+    },
+}
+
+/// Light side of the handshake.
+pub struct PairingResponder {
+    light_mac: MacAddress,
+    capabilities: Capabilities,
+    state: ResponderState,
+}
+
+impl PairingResponder {
+    pub fn new(light_mac: MacAddress, capabilities: Capabilities) -> Self {
+        Self {
+            light_mac,
+            capabilities,
+            state: ResponderState::Idle,
+        }
+    }
+
+    pub fn state(&self) -> ResponderState {
+        self.state
+    }
+
+    /// Enter pairing mode for [`PAIRING_WINDOW_MS`].
+    pub fn start(&mut self, now: u64) {
+        self.state = ResponderState::Listening {
+            window_ends_at: now + PAIRING_WINDOW_MS,
+        };
+    }
+
+    /// Handle a handshake sent by `src`. Returns the response to unicast back to it.
+    pub fn on_handshake(
+        &mut self,
+        now: u64,
+        src: MacAddress,
+        handshake: &Handshake,
+    ) -> Option<HandshakeResponse> {
+        if src != handshake.remote_mac {
+            return None;
+        }
+
+        match self.state {
+            ResponderState::Listening { window_ends_at } if now < window_ends_at => {
+                self.state = ResponderState::Answering {
+                    remote_mac: src,
+                    window_ends_at,
+                    answered_at: now,
+                    sent_at: now,
+                };
+            }
+            ResponderState::Answering {
+                remote_mac,
+                window_ends_at,
+                answered_at,
+                ..
+            } if remote_mac == src => {
+                self.state = ResponderState::Answering {
+                    remote_mac,
+                    window_ends_at,
+                    answered_at,
+                    sent_at: now,
+                };
+            }
+            _ => return None,
+        }
+
+        Some(self.response())
+    }
+
+    /// Handle a confirmation sent by `src`. Returns the newly paired remote, after which pairing
+    /// mode is over.
+    pub fn on_confirm(
+        &mut self,
+        src: MacAddress,
+        confirm: &HandshakeConfirm,
+    ) -> Option<MacAddress> {
+        match self.state {
+            ResponderState::Answering { remote_mac, .. }
+                if remote_mac == src && confirm.light_mac == self.light_mac =>
+            {
+                self.state = ResponderState::Idle;
+                Some(remote_mac)
+            }
+            _ => None,
+        }
+    }
+
+    /// Handle timeouts. Returns a response to unicast again, along with its destination, if the
+    /// remote hasn't confirmed the last one in a while.
+    pub fn poll(&mut self, now: u64) -> Option<(MacAddress, HandshakeResponse)> {
+        match self.state {
+            ResponderState::Listening { window_ends_at } if now >= window_ends_at => {
+                self.state = ResponderState::Idle;
+                None
+            }
+            ResponderState::Answering {
+                window_ends_at,
+                answered_at,
+                ..
+            } if now.saturating_sub(answered_at) >= CONFIRM_TIMEOUT_MS => {
+                // Give other remotes a chance for the rest of the window.
+                self.state = ResponderState::Listening { window_ends_at };
+                self.poll(now)
+            }
+            ResponderState::Answering {
+                remote_mac,
+                window_ends_at,
+                answered_at,
+                sent_at,
+            } if now.saturating_sub(sent_at) >= HANDSHAKE_RETRY_MS => {
+                self.state = ResponderState::Answering {
+                    remote_mac,
+                    window_ends_at,
+                    answered_at,
+                    sent_at: now,
+                };
+                Some((remote_mac, self.response()))
+            }
+            _ => None,
+        }
+    }
+
+    fn response(&self) -> HandshakeResponse {
+        HandshakeResponse {
+            light_mac: self.light_mac,
+            capabilities: self.capabilities,
+        }
+    }
+}
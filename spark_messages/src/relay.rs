@@ -0,0 +1,47 @@
+//! Relaying: lights passing messages on, so that remotes reach lights beyond their radio range.
+//!
+//! A light with [`ConfigKey::RelayHops`] set rebroadcasts the messages it accepts from paired
+//! remotes, whoever they are addressed to. The copy it sends, from [`Message::relayed`], names the
+//! remote in [`Message::origin`] and counts the lights it went through in [`Message::hops`]. It is
+//! signed as the remote, see [`open`](crate::open), and receivers treat it as coming from the
+//! remote, dropping every copy of a message but the first with a [`DedupCache`](crate::DedupCache)
+//! keyed by origin. Each light relays a message at most once then, so floods die out, and `hops`
+//! limits how far they spread.
+//!
+//! Replies and acknowledgements aren't relayed. Lights only reached through relays still act on
+//! messages, but remotes don't hear back from them.
+//!
+//! Frames a remote encrypted with the link key it shares with the light, see
+//! [`KeyExchange`](crate::KeyExchange), are relayed like any other. The copy goes out as a
+//! broadcast authenticated with the installation key only, so the link key keeps messages private
+//! over the first hop alone.
+//!
+//! [`ConfigKey::RelayHops`]: crate::ConfigKey::RelayHops
+
+use crate::{MacAddress, Message, NO_MESSAGE_ID};
+
+/// Most hops a light can be configured to relay messages over.
+pub const MAX_RELAY_HOPS: u8 = 7;
+
+impl Message {
+    /// The sender the message started from, given it was received from `src`.
+    pub fn origin(&self, src: MacAddress) -> MacAddress {
+        self.origin.unwrap_or(src)
+    }
+
+    /// The copy of a message received from `src` for a light to rebroadcast, if the message went
+    /// through fewer than `max_hops` lights so far.
+    ///
+    /// Messages without an ID are never relayed, as receivers couldn't tell the copies apart from
+    /// new messages.
+    pub fn relayed(&self, src: MacAddress, max_hops: u8) -> Option<Message> {
+        if self.id == NO_MESSAGE_ID || self.hops >= max_hops {
+            return None;
+        }
+        Some(Message {
+            hops: self.hops + 1,
+            origin: Some(self.origin(src)),
+            ..self.clone()
+        })
+    }
+}
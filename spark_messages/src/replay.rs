@@ -0,0 +1,174 @@
+//! Replay protection.
+//!
+//! Senders number their messages with a [`SendCounter`] and receivers keep a [`ReplayWindow`]
+//! per sender, so that a captured frame is only ever accepted once. Counters are compared with
+//! serial number arithmetic, which makes them wrap around instead of running out.
+//!
+//! Both sides survive reboots by persisting a reservation of counters ahead of the ones they use or
+//! saw, so that they don't have to on every message. Senders resume counting after theirs, and
+//! receivers resume with a [`ReceiveWindow`] rejecting everything up to theirs. Senders
+//! [skip](SendCounter::skip) a reservation's worth of counters when they boot, and whenever they
+//! notice a receiver restarted, to get past what it may have reserved.
+
+use serde::{Deserialize, Serialize};
+
+/// How far behind the newest counter a message may arrive and still be accepted.
+pub const REPLAY_WINDOW_SIZE: u32 = 32;
+
+/// How many counters a sender reserves at a time, i.e. how often it has to persist its state.
+pub const COUNTER_RESERVATION: u32 = 256;
+
+/// Counters already seen from one sender.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReplayWindow {
+    newest: u32,
+    /// Bit `n` is set if counter `newest - n` has been seen.
+    seen: u32,
+}
+
+impl ReplayWindow {
+    /// A window that only accepts counters after `counter`.
+    pub fn starting_at(counter: u32) -> Self {
+        Self {
+            newest: counter,
+            seen: u32::MAX,
+        }
+    }
+
+    /// Whether a message numbered `counter` would be accepted.
+    pub fn check(&self, counter: u32) -> bool {
+        let ahead = counter.wrapping_sub(self.newest) as i32;
+        if ahead > 0 {
+            return true;
+        }
+
+        let behind = ahead.unsigned_abs();
+        behind < REPLAY_WINDOW_SIZE && self.seen & (1 << behind) == 0
+    }
+
+    /// Record a message numbered `counter`. Returns `false` if it is a replay, or too old to tell.
+    ///
+    /// Only call this for authenticated messages, or forged counters could push the window ahead
+    /// of the genuine sender.
+    pub fn accept(&mut self, counter: u32) -> bool {
+        if !self.check(counter) {
+            return false;
+        }
+
+        let ahead = counter.wrapping_sub(self.newest) as i32;
+        if ahead > 0 {
+            let ahead = ahead.unsigned_abs();
+            self.seen = if ahead < REPLAY_WINDOW_SIZE {
+                self.seen << ahead | 1
+            } else {
+                1
+            };
+            self.newest = counter;
+        } else {
+            self.seen |= 1 << ahead.unsigned_abs();
+        }
+        true
+    }
+
+    pub fn newest(&self) -> u32 {
+        self.newest
+    }
+}
+
+/// A [`ReplayWindow`] that only has to be persisted every [`COUNTER_RESERVATION`] counters. It
+/// persists as its reservation.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(from = "u32", into = "u32")]
+pub struct ReceiveWindow {
+    window: ReplayWindow,
+    reserved: u32,
+}
+
+impl ReceiveWindow {
+    /// Resume after a boot. `reserved` is the last reservation returned by
+    /// [`ReceiveWindow::accept`] that was persisted, or the counter pairing completed with.
+    pub fn resume(reserved: u32) -> Self {
+        Self {
+            window: ReplayWindow::starting_at(reserved),
+            reserved,
+        }
+    }
+
+    /// Record a message numbered `counter`, see [`ReplayWindow::accept`]. If a new reservation is
+    /// returned along with `true`, it must be persisted before acting on the message.
+    pub fn accept(&mut self, counter: u32) -> (bool, Option<u32>) {
+        if !self.window.accept(counter) {
+            return (false, None);
+        }
+
+        if counter.wrapping_sub(self.reserved) as i32 > 0 {
+            self.reserved = counter.wrapping_add(COUNTER_RESERVATION - 1);
+            (true, Some(self.reserved))
+        } else {
+            (true, None)
+        }
+    }
+
+    /// Record a message numbered `counter` without reserving, for messages too frequent to
+    /// persist for. They may be accepted once more after a reboot.
+    pub fn accept_unreserved(&mut self, counter: u32) -> bool {
+        self.window.accept(counter)
+    }
+
+    pub fn newest(&self) -> u32 {
+        self.window.newest()
+    }
+}
+
+impl From<u32> for ReceiveWindow {
+    fn from(reserved: u32) -> Self {
+        Self::resume(reserved)
+    }
+}
+
+impl From<ReceiveWindow> for u32 {
+    fn from(window: ReceiveWindow) -> Self {
+        window.reserved
+    }
+}
+
+/// Numbers outgoing messages.
+#[derive(Debug)]
+pub struct SendCounter {
+    last: u32,
+    reserved: u32,
+}
+
+impl SendCounter {
+    /// Resume counting after a boot. `reserved` is the last reservation returned by
+    /// [`SendCounter::next_counter`] that was persisted, or 0 on first boot.
+    pub fn resume(reserved: u32) -> Self {
+        Self {
+            last: reserved,
+            reserved,
+        }
+    }
+
+    /// Returns the counter for the next message. If a new reservation is returned along with it,
+    /// it must be persisted before the message is sent.
+    pub fn next_counter(&mut self) -> (u32, Option<u32>) {
+        self.last = self.last.wrapping_add(1);
+        (self.last, self.reserve())
+    }
+
+    /// Skip [`COUNTER_RESERVATION`] counters, past anything a [`ReceiveWindow`] may have reserved
+    /// before it restarted. Returns a new reservation to persist, if any.
+    pub fn skip(&mut self) -> Option<u32> {
+        self.last = self.last.wrapping_add(COUNTER_RESERVATION);
+        self.reserve()
+    }
+
+    fn reserve(&mut self) -> Option<u32> {
+        if self.last.wrapping_sub(self.reserved) as i32 > 0 {
+            self.reserved = self.last.wrapping_add(COUNTER_RESERVATION - 1);
+            Some(self.reserved)
+        } else {
+            None
+        }
+    }
+}
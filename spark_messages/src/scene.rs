@@ -0,0 +1,158 @@
+//! Scenes: light states saved on a light in numbered slots, to be brought back with one message.
+//!
+//! [`MessageType::SceneStore`] saves a [`Scene`] in a slot, either one spelled out or whatever the
+//! light is showing. [`MessageType::SceneRecall`] plays the scene in a slot, and
+//! [`MessageType::SceneList`] asks which slots hold one. Lights keep their scenes in a
+//! [`SceneBook`], and answer all three with a [`MessageType::SceneReply`].
+//!
+//! [`MessageType::SceneStore`]: crate::MessageType::SceneStore
+//! [`MessageType::SceneRecall`]: crate::MessageType::SceneRecall
+//! [`MessageType::SceneList`]: crate::MessageType::SceneList
+//! [`MessageType::SceneReply`]: crate::MessageType::SceneReply
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use crate::{MAX_STRIPS, SetAnimation, max_vec_size};
+
+/// Slots a light has, numbered from 0.
+pub const MAX_SCENES: usize = 8;
+
+const _: () = assert!(MAX_SCENES <= 8, "a SceneSet must hold every slot");
+
+/// What a light shows: an animation, dimmed strip by strip.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct Scene {
+    pub animation: SetAnimation,
+    /// How bright each strip is relative to the animation, in strip order. 255 is as bright as the
+    /// animation, 0 dark. Strips past the end are as bright as the animation.
+    #[cfg_attr(feature = "std", schemars(with = "Vec<u8>", length(max = MAX_STRIPS)))]
+    pub strip_levels: heapless::Vec<u8, MAX_STRIPS>,
+}
+
+impl MaxSize for Scene {
+    const POSTCARD_MAX_SIZE: usize =
+        SetAnimation::POSTCARD_MAX_SIZE + max_vec_size::<u8, MAX_STRIPS>();
+}
+
+impl Scene {
+    /// `animation` on every strip alike.
+    pub fn new(animation: SetAnimation) -> Self {
+        Self {
+            animation,
+            strip_levels: heapless::Vec::new(),
+        }
+    }
+
+    /// How bright `strip` is relative to the animation.
+    pub fn strip_level(&self, strip: usize) -> u8 {
+        self.strip_levels.get(strip).copied().unwrap_or(u8::MAX)
+    }
+}
+
+/// Remote => light, saves a scene in `slot`, replacing whatever was there.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct SceneStore {
+    pub slot: u8,
+    /// The scene to save, `None` for what the light is showing.
+    pub scene: Option<Scene>,
+}
+
+/// A set of slots. It's a bitmask so that a reply lists every slot in one byte.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct SceneSet(u8);
+
+impl SceneSet {
+    pub const fn new() -> Self {
+        Self(0)
+    }
+
+    /// Add `slot`, which must be below [`MAX_SCENES`].
+    pub fn insert(&mut self, slot: u8) {
+        assert!((slot as usize) < MAX_SCENES);
+        self.0 |= 1 << slot;
+    }
+
+    pub fn contains(&self, slot: u8) -> bool {
+        (slot as usize) < MAX_SCENES && self.0 & (1 << slot) != 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// The slots in the set, in order.
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..MAX_SCENES as u8).filter(|&slot| self.contains(slot))
+    }
+}
+
+impl FromIterator<u8> for SceneSet {
+    fn from_iter<I: IntoIterator<Item = u8>>(slots: I) -> Self {
+        let mut set = Self::new();
+        for slot in slots {
+            set.insert(slot);
+        }
+        set
+    }
+}
+
+/// Why a light couldn't store or recall a scene.
+#[derive(Serialize, Deserialize, MaxSize, Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+#[non_exhaustive]
+pub enum SceneError {
+    /// The light has no slot numbered `slot`.
+    NoSuchSlot { slot: u8 },
+    /// Nothing is stored in `slot`.
+    Empty { slot: u8 },
+    /// Asked to store what the light is showing, but it hasn't shown anything since it booted.
+    NothingShowing,
+}
+
+/// The scenes a light keeps, one per slot.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct SceneBook {
+    slots: [Option<Scene>; MAX_SCENES],
+}
+
+impl SceneBook {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { None }; MAX_SCENES],
+        }
+    }
+
+    /// Save `scene` in `slot`. Returns the slots holding a scene from now on.
+    pub fn store(&mut self, slot: u8, scene: Scene) -> Result<SceneSet, SceneError> {
+        let stored = self
+            .slots
+            .get_mut(slot as usize)
+            .ok_or(SceneError::NoSuchSlot { slot })?;
+        *stored = Some(scene);
+        Ok(self.slots())
+    }
+
+    /// The scene saved in `slot`.
+    pub fn recall(&self, slot: u8) -> Result<&Scene, SceneError> {
+        self.slots
+            .get(slot as usize)
+            .ok_or(SceneError::NoSuchSlot { slot })?
+            .as_ref()
+            .ok_or(SceneError::Empty { slot })
+    }
+
+    /// The slots holding a scene.
+    pub fn slots(&self) -> SceneSet {
+        (0..MAX_SCENES as u8)
+            .filter(|&slot| self.slots[slot as usize].is_some())
+            .collect()
+    }
+}
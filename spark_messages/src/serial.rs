@@ -0,0 +1,113 @@
+//! Framing messages for byte streams, such as a USB-serial port to a PC.
+//!
+//! A serial frame is the [`Message::encode`]d message followed by the CRC-32 of the encoding,
+//! COBS-encoded so that it contains no zero bytes, between two zero bytes. A reader joining
+//! mid-stream, losing bytes, or receiving log output in between frames gets back in step at the
+//! next zero. Serial frames carry no authentication tag, the CRC only catches corruption.
+
+use crc::{CRC_32_ISO_HDLC, Crc};
+
+use crate::{DecodeError, EncodeError, MAX_ENCODED_LEN, Message};
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+const CRC_LEN: usize = 4;
+
+/// Longest frame before COBS encoding.
+const MAX_UNSTUFFED_LEN: usize = MAX_ENCODED_LEN + CRC_LEN;
+
+/// Longest serial frame any message encodes to, delimiters included.
+pub const MAX_SERIAL_FRAME_LEN: usize = cobs::max_encoding_length(MAX_UNSTUFFED_LEN) + 2;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// More bytes than any frame has came without a delimiter.
+    Overflow,
+    /// The frame isn't valid COBS, or is too short to hold a CRC.
+    Malformed,
+    /// The CRC doesn't match the frame, which got corrupted.
+    BadCrc,
+    /// The frame is intact, but the message in it didn't decode.
+    Decode(DecodeError),
+}
+
+/// Encode `message` as a serial frame into the start of `buf`. Returns the frame.
+pub fn encode_frame<'a>(message: &Message, buf: &'a mut [u8]) -> Result<&'a [u8], EncodeError> {
+    let mut unstuffed = [0; MAX_UNSTUFFED_LEN];
+    let len = message.encode(&mut unstuffed)?.len();
+    let crc = CRC.checksum(&unstuffed[..len]);
+    unstuffed[len..len + CRC_LEN].copy_from_slice(&crc.to_le_bytes());
+
+    let (delimiter, rest) = buf.split_first_mut().ok_or(EncodeError::BufferFull)?;
+    *delimiter = 0;
+    let stuffed_len =
+        cobs::try_encode(&unstuffed[..len + CRC_LEN], rest).map_err(|_| EncodeError::BufferFull)?;
+    *rest.get_mut(stuffed_len).ok_or(EncodeError::BufferFull)? = 0;
+    Ok(&buf[..stuffed_len + 2])
+}
+
+/// Reassembles messages from a byte stream of serial frames, however the stream is split up.
+pub struct FrameReader {
+    buf: [u8; MAX_SERIAL_FRAME_LEN],
+    /// Bytes since the last delimiter, including those that didn't fit in `buf`.
+    len: usize,
+}
+
+impl FrameReader {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_SERIAL_FRAME_LEN],
+            len: 0,
+        }
+    }
+
+    /// Take the next byte of the stream. Returns the message, or the error, of the frame it ends.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Message, FrameError>> {
+        if byte != 0 {
+            if let Some(slot) = self.buf.get_mut(self.len) {
+                *slot = byte;
+            }
+            self.len = self.len.saturating_add(1);
+            return None;
+        }
+
+        // Nothing between two delimiters, such as between two frames.
+        let len = core::mem::replace(&mut self.len, 0);
+        if len == 0 {
+            return None;
+        }
+        if len > self.buf.len() {
+            return Some(Err(FrameError::Overflow));
+        }
+        Some(self.decode(len))
+    }
+
+    /// Take the next bytes of the stream. Returns the messages, or errors, of the frames they end.
+    pub fn feed<'a>(
+        &'a mut self,
+        bytes: &'a [u8],
+    ) -> impl Iterator<Item = Result<Message, FrameError>> + 'a {
+        bytes.iter().filter_map(|&byte| self.push(byte))
+    }
+
+    fn decode(&self, len: usize) -> Result<Message, FrameError> {
+        let mut unstuffed = [0; MAX_UNSTUFFED_LEN];
+        let unstuffed_len =
+            cobs::decode(&self.buf[..len], &mut unstuffed).map_err(|_| FrameError::Malformed)?;
+        let body_len = unstuffed_len
+            .checked_sub(CRC_LEN)
+            .ok_or(FrameError::Malformed)?;
+
+        let (body, crc) = unstuffed[..unstuffed_len].split_at(body_len);
+        if CRC.checksum(body).to_le_bytes() != crc {
+            return Err(FrameError::BadCrc);
+        }
+        Message::decode(body).map_err(FrameError::Decode)
+    }
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
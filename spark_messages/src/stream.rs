@@ -0,0 +1,142 @@
+//! Streaming pixels: a host driving every LED of a light live, for shows and debugging.
+//!
+//! A [`PixelFrame`] writes a run of pixels to one strip. Without a frame number the pixels show
+//! right away. With one they are staged, and the whole frame shows at once when a [`PixelFrame`]
+//! of that frame without pixels commits it, so that frames spread over several messages never
+//! show half written. A frame left uncommitted is dropped once a later one starts, and messages
+//! of frames older than the one being staged or the last committed arrive too late to matter.
+//!
+//! A light streams while frames keep coming, and goes back to what it was showing
+//! [`STREAM_TIMEOUT_MS`] after the last one. [`PixelBuffer`] keeps track of all of it, and forgets
+//! frame numbers after the timeout, so that hosts can start counting over.
+
+use postcard::experimental::max_size::MaxSize;
+use serde::{Deserialize, Serialize};
+
+use crate::max_vec_size;
+
+/// Most pixels in a [`PixelFrame`]. Leaves room in a frame for the rest of the message.
+pub const MAX_FRAME_PIXELS: usize = 60;
+
+/// How long a light keeps showing streamed pixels after the last [`PixelFrame`].
+pub const STREAM_TIMEOUT_MS: u64 = 2_000;
+
+/// Host => light, red, green and blue of the pixels of strip `strip` from `offset` on.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(schemars::JsonSchema))]
+pub struct PixelFrame {
+    pub strip: u8,
+    pub offset: u16,
+    #[cfg_attr(feature = "std", schemars(with = "Vec<[u8; 3]>", length(max = MAX_FRAME_PIXELS)))]
+    pub pixels: heapless::Vec<[u8; 3], MAX_FRAME_PIXELS>,
+    /// The frame the pixels belong to, `None` to show them right away.
+    pub frame: Option<u32>,
+}
+
+impl MaxSize for PixelFrame {
+    const POSTCARD_MAX_SIZE: usize = u8::POSTCARD_MAX_SIZE
+        + u16::POSTCARD_MAX_SIZE
+        + max_vec_size::<[u8; 3], MAX_FRAME_PIXELS>()
+        + Option::<u32>::POSTCARD_MAX_SIZE;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PixelFrameError {
+    /// The light has fewer strips.
+    NoSuchStrip { strips: u8 },
+    /// The pixels run past the end of the strip.
+    OutOfRange { leds: u16 },
+    /// The frame is older than the one being staged, or the last committed.
+    Late,
+}
+
+/// What a light streaming pixels shows, for `S` strips of `N` LEDs each. Times are in
+/// milliseconds.
+#[derive(Debug, Clone)]
+pub struct PixelBuffer<const S: usize, const N: usize> {
+    shown: [[[u8; 3]; N]; S],
+    staged: [[[u8; 3]; N]; S],
+    /// The frame being staged, or the last one committed once `staging` is false.
+    frame: Option<u32>,
+    staging: bool,
+    last_frame_at: Option<u64>,
+}
+
+impl<const S: usize, const N: usize> PixelBuffer<S, N> {
+    /// Every pixel dark.
+    pub const fn new() -> Self {
+        Self {
+            shown: [[[0; 3]; N]; S],
+            staged: [[[0; 3]; N]; S],
+            frame: None,
+            staging: false,
+            last_frame_at: None,
+        }
+    }
+
+    /// Handle a [`PixelFrame`] received at `now`. Returns whether what shows changed.
+    pub fn on_frame(&mut self, now: u64, frame: &PixelFrame) -> Result<bool, PixelFrameError> {
+        if !self.is_streaming(now) {
+            *self = Self::new();
+        }
+        let strip = frame.strip as usize;
+        if strip >= S {
+            return Err(PixelFrameError::NoSuchStrip { strips: S as u8 });
+        }
+        let start = frame.offset as usize;
+        let end = start + frame.pixels.len();
+        if end > N {
+            return Err(PixelFrameError::OutOfRange { leds: N as u16 });
+        }
+
+        let Some(number) = frame.frame else {
+            self.last_frame_at = Some(now);
+            self.shown[strip][start..end].copy_from_slice(&frame.pixels);
+            if self.staging {
+                self.staged[strip][start..end].copy_from_slice(&frame.pixels);
+            }
+            return Ok(true);
+        };
+        match self.frame {
+            // Frame numbers wrap around.
+            Some(latest) if (number.wrapping_sub(latest) as i32) < 0 => {
+                return Err(PixelFrameError::Late);
+            }
+            Some(latest) if latest == number && !self.staging => {
+                return Err(PixelFrameError::Late);
+            }
+            Some(latest) if latest == number => {}
+            _ => {
+                self.staged = self.shown;
+                self.frame = Some(number);
+                self.staging = true;
+            }
+        }
+
+        self.last_frame_at = Some(now);
+        if frame.pixels.is_empty() {
+            self.shown = self.staged;
+            self.staging = false;
+            return Ok(true);
+        }
+        self.staged[strip][start..end].copy_from_slice(&frame.pixels);
+        Ok(false)
+    }
+
+    /// Whether a frame arrived within the last [`STREAM_TIMEOUT_MS`].
+    pub fn is_streaming(&self, now: u64) -> bool {
+        self.last_frame_at
+            .is_some_and(|at| now.saturating_sub(at) < STREAM_TIMEOUT_MS)
+    }
+
+    /// The pixels of every strip, as they show.
+    pub fn shown(&self) -> &[[[u8; 3]; N]; S] {
+        &self.shown
+    }
+}
+
+impl<const S: usize, const N: usize> Default for PixelBuffer<S, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,1119 @@
+//! A line-oriented text syntax for messages, for serial consoles, logs and host tools.
+//!
+//! A line is a command, its arguments, then `key=value` options, separated by spaces:
+//!
+//! ```text
+//! button 2 short 3
+//! anim rainbow color=rgb:255,128,0 speed=40 duration=5000
+//! config-set brightness 40 to=groups:0x5 counter=12
+//! ```
+//!
+//! Every command takes the options `to`, the [`Destination`] (`all`, `device:ID` or
+//! `groups:MASK`), `counter`, `id`, `hops` and `from`, the origin of a relayed message, which
+//! default to `all`, 0, 0, 0 and none. Numbers are decimal, or
+//! hexadecimal after `0x`. MAC addresses are written `aa:bb:cc:dd:ee:ff`, bytes as hexadecimal, and
+//! lists separated by commas. Times are in milliseconds, except for clock synchronisation.
+//!
+//! | Command | Message |
+//! |---|---|
+//! | `button 1..4 short COUNT`, `button 1..4 long`, `button 1..4 pressed`, `button 1..4 released`, `button 1..4 hold ELAPSED_MS` | [`MessageType::ButtonEvent`] |
+//! | `handshake MAC` | [`MessageType::Handshake`] |
+//! | `handshake-response MAC strips=N leds=N` | [`MessageType::HandshakeResponse`] |
+//! | `handshake-confirm MAC` | [`MessageType::HandshakeConfirm`] |
+//! | `ack COUNTER` | [`MessageType::Ack`] |
+//! | `fragment transfer=N index=N count=N data=BYTES` | [`MessageType::Fragment`] |
+//! | `anim PATTERN [color=hsv:H,S,V\|rgb:R,G,B] [speed=N] [brightness=N] [duration=MS]` | [`MessageType::SetAnimation`] |
+//! | `status` | [`MessageType::StatusRequest`] |
+//! | `light-status MODE pattern=PATTERN brightness=N uptime=MS firmware=X.Y.Z [error=ERROR]` | [`MessageType::LightStatus`] |
+//! | `set-address id=N groups=MASK` | [`MessageType::SetAddress`] |
+//! | `time-request origin=US` | [`MessageType::TimeRequest`] |
+//! | `time-response origin=US receive=US transmit=US` | [`MessageType::TimeResponse`] |
+//! | `ota-begin size=N sha256=BYTES` | [`MessageType::OtaBegin`] |
+//! | `ota-chunk offset=N data=BYTES` | [`MessageType::OtaChunk`] |
+//! | `ota-status STATE received=N [error=ERROR]` | [`MessageType::OtaStatus`] |
+//! | `ota-commit` | [`MessageType::OtaCommit`] |
+//! | `config-get KEY` | [`MessageType::ConfigGet`] |
+//! | `config-set KEY VALUE` | [`MessageType::ConfigSet`] |
+//! | `config-list` | [`MessageType::ConfigList`] |
+//! | `config-reply KEY VALUE`, `config-reply KEY wrong-type expected=TYPE`, `config-reply KEY out-of-range min=VALUE max=VALUE`, `config-reply KEY read-only` | [`MessageType::ConfigReply`] |
+//! | `discover` | [`MessageType::Discover`] |
+//! | `announce id=N firmware=X.Y.Z protocols=OLDEST-NEWEST strips=N,.. patterns=PATTERN,..` | [`MessageType::Announce`] |
+//! | `heartbeat seq=N uptime=MS reset=REASON` | [`MessageType::Heartbeat`] |
+//! | `link-stats-request MAC` | [`MessageType::LinkStatsRequest`] |
+//! | `link-stats MAC [rssi=DBM rssi-avg=DBM since=MS heartbeats=N lost=N restarts=N [last-seq=N last-uptime=MS last-reset=REASON]]` | [`MessageType::LinkStats`] |
+//! | `chord BUTTON,..` | [`MessageType::ButtonChord`] |
+//! | `sequence BUTTON,..` | [`MessageType::ButtonSequence`] |
+//! | `scene-store SLOT`, `scene-store SLOT PATTERN [color=..] [speed=N] [brightness=N] [duration=MS] [levels=BYTES]` | [`MessageType::SceneStore`] |
+//! | `scene-recall SLOT` | [`MessageType::SceneRecall`] |
+//! | `scene-list` | [`MessageType::SceneList`] |
+//! | `scene-reply [SLOT,..]`, `scene-reply no-such-slot SLOT`, `scene-reply empty SLOT`, `scene-reply nothing-showing` | [`MessageType::SceneReply`] |
+//! | `key-share BYTES` | [`MessageType::KeyShare`] |
+//! | `key-confirm BYTES` | [`MessageType::KeyConfirm`] |
+//! | `pixels STRIP offset=N [data=BYTES] [frame=N]` | [`MessageType::PixelFrame`] |
+//!
+//! Config values take the type of their key, a `u8:`, `u16:` or `u32:` prefix gives them another.
+//! `anim` defaults to [`DEFAULT_ANIMATION`]'s color, speed and brightness, and latches without a
+//! duration, and so does the animation of a scene. `scene-store SLOT` saves what the light is
+//! showing. The data of `pixels` is red, green and blue of each pixel in turn, and without it a
+//! frame is committed.
+//!
+//! Messages format to the same syntax, every field written out, so a message parses back from its
+//! text as long as it is of protocol revision [`PROTOCOL_VERSION`](crate::PROTOCOL_VERSION).
+//! Patterns of an [`Announce`] that this crate doesn't know about are left out.
+
+use core::fmt::{self, Display, Formatter, Write};
+use core::str::FromStr;
+
+use crate::{
+    Address, Announce, ButtonEventType, ButtonNumber, ButtonSequence, ButtonSet, Capabilities,
+    Color, ConfigEntry, ConfigError, ConfigKey, ConfigType, ConfigValue, Destination, ErrorCode,
+    FirmwareVersion, Fragment, Handshake, HandshakeConfirm, HandshakeResponse, Heartbeat,
+    KeyConfirm, KeyShare, LightMode, LightStatus, LinkStats, MAX_SCENES, MacAddress, Message,
+    MessageType, NO_MESSAGE_ID, OtaBegin, OtaChunk, OtaError, OtaState, OtaStatus, Pattern,
+    PatternSet, PixelFrame, ProtocolVersions, ResetReason, Scene, SceneError, SceneSet, SceneStore,
+    SetAnimation, TimeRequest, TimeResponse,
+};
+
+/// What `anim` plays when the line leaves options out.
+pub const DEFAULT_ANIMATION: SetAnimation = SetAnimation {
+    pattern: Pattern::Solid,
+    color: Color::Hsv {
+        hue: 0,
+        sat: 255,
+        val: 255,
+    },
+    speed: 64,
+    brightness: 25,
+    duration_ms: None,
+};
+
+/// Most words on a line.
+const MAX_WORDS: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The line is blank.
+    Empty,
+    UnknownCommand,
+    /// The named argument or option is missing.
+    Missing(&'static str),
+    /// The named argument or option doesn't parse, or is out of range.
+    Invalid(&'static str),
+    /// The line has more arguments than the command takes, an option it doesn't take, or an option
+    /// twice.
+    Unexpected,
+    /// The line has more words than any command.
+    TooLong,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => f.write_str("empty line"),
+            ParseError::UnknownCommand => f.write_str("unknown command"),
+            ParseError::Missing(name) => write!(f, "missing {name}"),
+            ParseError::Invalid(name) => write!(f, "invalid {name}"),
+            ParseError::Unexpected => f.write_str("unexpected argument or option"),
+            ParseError::TooLong => f.write_str("too many words"),
+        }
+    }
+}
+
+impl FromStr for Message {
+    type Err = ParseError;
+
+    fn from_str(line: &str) -> Result<Self, ParseError> {
+        let mut line = Line::new(line)?;
+        let command = line.word("command").map_err(|_| ParseError::Empty)?;
+        let message_type = parse_command(command, &mut line)?;
+        let destination = line.option("to")?.unwrap_or_default();
+        let counter = line.option("counter")?.unwrap_or(0);
+        let id = line.option("id")?.unwrap_or(NO_MESSAGE_ID);
+        let hops = line.option("hops")?.unwrap_or(0);
+        let origin = line.option("from")?;
+        line.finish()?;
+        Ok(Message {
+            hops,
+            origin,
+            ..Message::new(message_type, counter)
+                .with_destination(destination)
+                .with_id(id)
+        })
+    }
+}
+
+impl Display for Message {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message_type)?;
+        if self.destination != Destination::All {
+            write!(f, " to={}", Text(&self.destination))?;
+        }
+        if self.counter != 0 {
+            write!(f, " counter={}", self.counter)?;
+        }
+        if self.id != NO_MESSAGE_ID {
+            write!(f, " id={}", self.id)?;
+        }
+        if self.hops != 0 {
+            write!(f, " hops={}", self.hops)?;
+        }
+        if let Some(origin) = &self.origin {
+            write!(f, " from={}", Text(origin))?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_command(command: &str, line: &mut Line<'_>) -> Result<MessageType, ParseError> {
+    Ok(match command {
+        "button" => {
+            let button_number = line.argument("button")?;
+            let event_type = match line.word("event")? {
+                "short" => ButtonEventType::ShortPress {
+                    count: line.argument("count")?,
+                },
+                "long" => ButtonEventType::LongPress,
+                "pressed" => ButtonEventType::Pressed,
+                "released" => ButtonEventType::Released,
+                "hold" => ButtonEventType::HoldRepeat {
+                    elapsed_ms: line.argument("elapsed")?,
+                },
+                _ => return Err(ParseError::Invalid("event")),
+            };
+            MessageType::ButtonEvent {
+                button_number,
+                event_type,
+            }
+        }
+        "handshake" => MessageType::Handshake(Handshake {
+            remote_mac: line.argument("mac")?,
+        }),
+        "handshake-response" => MessageType::HandshakeResponse(HandshakeResponse {
+            light_mac: line.argument("mac")?,
+            capabilities: Capabilities {
+                strips: line.required("strips")?,
+                leds_per_strip: line.required("leds")?,
+            },
+        }),
+        "handshake-confirm" => MessageType::HandshakeConfirm(HandshakeConfirm {
+            light_mac: line.argument("mac")?,
+        }),
+        "ack" => MessageType::Ack {
+            counter: line.argument("counter")?,
+        },
+        "fragment" => MessageType::Fragment(Fragment {
+            transfer: line.required("transfer")?,
+            index: line.required("index")?,
+            count: line.required("count")?,
+            data: line.required("data")?,
+        }),
+        "anim" => {
+            let pattern = line.argument("pattern")?;
+            MessageType::SetAnimation(parse_animation(pattern, line)?)
+        }
+        "status" => MessageType::StatusRequest,
+        "light-status" => MessageType::LightStatus(LightStatus {
+            mode: line.argument("mode")?,
+            pattern: line.required("pattern")?,
+            brightness: line.required("brightness")?,
+            uptime_ms: line.required("uptime")?,
+            firmware_version: line.required("firmware")?,
+            last_error: line.option("error")?,
+        }),
+        "set-address" => MessageType::SetAddress(Address {
+            id: line.required("id")?,
+            groups: line.required("groups")?,
+        }),
+        "time-request" => MessageType::TimeRequest(TimeRequest {
+            origin_us: line.required("origin")?,
+        }),
+        "time-response" => MessageType::TimeResponse(TimeResponse {
+            origin_us: line.required("origin")?,
+            receive_us: line.required("receive")?,
+            transmit_us: line.required("transmit")?,
+        }),
+        "ota-begin" => MessageType::OtaBegin(OtaBegin {
+            size: line.required("size")?,
+            sha256: line.required("sha256")?,
+        }),
+        "ota-chunk" => MessageType::OtaChunk(OtaChunk {
+            offset: line.required("offset")?,
+            data: line.required("data")?,
+        }),
+        "ota-status" => MessageType::OtaStatus(OtaStatus {
+            state: line.argument("state")?,
+            received: line.required("received")?,
+            error: line.option("error")?,
+        }),
+        "ota-commit" => MessageType::OtaCommit,
+        "config-get" => MessageType::ConfigGet(line.argument("key")?),
+        "config-set" => {
+            let key = line.argument("key")?;
+            let value =
+                parse_config_value(key, line.word("value")?).ok_or(ParseError::Invalid("value"))?;
+            MessageType::ConfigSet(ConfigEntry { key, value })
+        }
+        "config-list" => MessageType::ConfigList,
+        "config-reply" => {
+            let key = line.argument("key")?;
+            MessageType::ConfigReply(match line.word("value")? {
+                "wrong-type" => Err(ConfigError::WrongType {
+                    key,
+                    expected: line.required("expected")?,
+                }),
+                "out-of-range" => Err(ConfigError::OutOfRange {
+                    key,
+                    min: line.required_with("min", |min| parse_config_value(key, min))?,
+                    max: line.required_with("max", |max| parse_config_value(key, max))?,
+                }),
+                "read-only" => Err(ConfigError::ReadOnly { key }),
+                value => Ok(ConfigEntry {
+                    key,
+                    value: parse_config_value(key, value).ok_or(ParseError::Invalid("value"))?,
+                }),
+            })
+        }
+        "discover" => MessageType::Discover,
+        "announce" => MessageType::Announce(Announce {
+            id: line.required("id")?,
+            firmware_version: line.required("firmware")?,
+            protocol_versions: line.required("protocols")?,
+            strips: line.required("strips")?,
+            patterns: line.required("patterns")?,
+        }),
+        "heartbeat" => MessageType::Heartbeat(Heartbeat {
+            seq: line.required("seq")?,
+            uptime_ms: line.required("uptime")?,
+            reset_reason: line.required("reset")?,
+        }),
+        "link-stats-request" => MessageType::LinkStatsRequest {
+            peer: line.argument("mac")?,
+        },
+        "link-stats" => {
+            let peer = line.argument("mac")?;
+            let stats = match line.option("rssi")? {
+                None => None,
+                Some(rssi) => Some(LinkStats {
+                    rssi,
+                    rssi_avg: line.required("rssi-avg")?,
+                    since_last_seen_ms: line.required("since")?,
+                    heartbeats: line.required("heartbeats")?,
+                    heartbeats_lost: line.required("lost")?,
+                    restarts: line.required("restarts")?,
+                    last_heartbeat: match line.option("last-seq")? {
+                        None => None,
+                        Some(seq) => Some(Heartbeat {
+                            seq,
+                            uptime_ms: line.required("last-uptime")?,
+                            reset_reason: line.required("last-reset")?,
+                        }),
+                    },
+                }),
+            };
+            MessageType::LinkStats { peer, stats }
+        }
+        "chord" => MessageType::ButtonChord(line.optional_argument("buttons")?.unwrap_or_default()),
+        "sequence" => MessageType::ButtonSequence(ButtonSequence {
+            buttons: line.optional_argument("buttons")?.unwrap_or_default(),
+        }),
+        "scene-store" => {
+            let slot = line.argument("slot")?;
+            let scene = match line.optional_argument("pattern")? {
+                None => None,
+                Some(pattern) => Some(Scene {
+                    animation: parse_animation(pattern, line)?,
+                    strip_levels: line.option("levels")?.unwrap_or_default(),
+                }),
+            };
+            MessageType::SceneStore(SceneStore { slot, scene })
+        }
+        "scene-recall" => MessageType::SceneRecall {
+            slot: line.argument("slot")?,
+        },
+        "scene-list" => MessageType::SceneList,
+        "scene-reply" => MessageType::SceneReply(match line.word("slots") {
+            Err(_) => Ok(SceneSet::new()),
+            Ok("no-such-slot") => Err(SceneError::NoSuchSlot {
+                slot: line.argument("slot")?,
+            }),
+            Ok("empty") => Err(SceneError::Empty {
+                slot: line.argument("slot")?,
+            }),
+            Ok("nothing-showing") => Err(SceneError::NothingShowing),
+            Ok(slots) => Ok(SceneSet::parse(slots).ok_or(ParseError::Invalid("slots"))?),
+        }),
+        "key-share" => MessageType::KeyShare(KeyShare {
+            public_key: line.argument("key")?,
+        }),
+        "key-confirm" => MessageType::KeyConfirm(KeyConfirm {
+            tag: line.argument("tag")?,
+        }),
+        "pixels" => MessageType::PixelFrame(PixelFrame {
+            strip: line.argument("strip")?,
+            offset: line.required("offset")?,
+            pixels: line.option("data")?.unwrap_or_default(),
+            frame: line.option("frame")?,
+        }),
+        _ => return Err(ParseError::UnknownCommand),
+    })
+}
+
+impl Display for MessageType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageType::ButtonEvent {
+                button_number,
+                event_type,
+            } => {
+                write!(f, "button {}", Text(button_number))?;
+                match event_type {
+                    ButtonEventType::ShortPress { count } => write!(f, " short {count}"),
+                    ButtonEventType::LongPress => f.write_str(" long"),
+                    ButtonEventType::Pressed => f.write_str(" pressed"),
+                    ButtonEventType::Released => f.write_str(" released"),
+                    ButtonEventType::HoldRepeat { elapsed_ms } => write!(f, " hold {elapsed_ms}"),
+                }
+            }
+            MessageType::Handshake(handshake) => {
+                write!(f, "handshake {}", Text(&handshake.remote_mac))
+            }
+            MessageType::HandshakeResponse(response) => write!(
+                f,
+                "handshake-response {} strips={} leds={}",
+                Text(&response.light_mac),
+                response.capabilities.strips,
+                response.capabilities.leds_per_strip
+            ),
+            MessageType::HandshakeConfirm(confirm) => {
+                write!(f, "handshake-confirm {}", Text(&confirm.light_mac))
+            }
+            MessageType::Ack { counter } => write!(f, "ack {counter}"),
+            MessageType::Fragment(fragment) => write!(
+                f,
+                "fragment transfer={} index={} count={} data={}",
+                fragment.transfer,
+                fragment.index,
+                fragment.count,
+                Text(&fragment.data)
+            ),
+            MessageType::SetAnimation(animation) => write!(f, "anim {}", AnimationText(animation)),
+            MessageType::StatusRequest => f.write_str("status"),
+            MessageType::LightStatus(status) => {
+                write!(
+                    f,
+                    "light-status {} pattern={} brightness={} uptime={} firmware={}",
+                    Text(&status.mode),
+                    Text(&status.pattern),
+                    status.brightness,
+                    status.uptime_ms,
+                    Text(&status.firmware_version)
+                )?;
+                if let Some(error) = &status.last_error {
+                    write!(f, " error={}", Text(error))?;
+                }
+                Ok(())
+            }
+            MessageType::SetAddress(address) => write!(
+                f,
+                "set-address id={} groups={:#x}",
+                address.id, address.groups
+            ),
+            MessageType::TimeRequest(request) => {
+                write!(f, "time-request origin={}", request.origin_us)
+            }
+            MessageType::TimeResponse(response) => write!(
+                f,
+                "time-response origin={} receive={} transmit={}",
+                response.origin_us, response.receive_us, response.transmit_us
+            ),
+            MessageType::OtaBegin(begin) => write!(
+                f,
+                "ota-begin size={} sha256={}",
+                begin.size,
+                Text(&begin.sha256)
+            ),
+            MessageType::OtaChunk(chunk) => write!(
+                f,
+                "ota-chunk offset={} data={}",
+                chunk.offset,
+                Text(&chunk.data)
+            ),
+            MessageType::OtaStatus(status) => {
+                write!(
+                    f,
+                    "ota-status {} received={}",
+                    Text(&status.state),
+                    status.received
+                )?;
+                if let Some(error) = &status.error {
+                    write!(f, " error={}", Text(error))?;
+                }
+                Ok(())
+            }
+            MessageType::OtaCommit => f.write_str("ota-commit"),
+            MessageType::ConfigGet(key) => write!(f, "config-get {}", Text(key)),
+            MessageType::ConfigSet(entry) => write!(
+                f,
+                "config-set {} {}",
+                Text(&entry.key),
+                ConfigText(entry.key, entry.value)
+            ),
+            MessageType::ConfigList => f.write_str("config-list"),
+            MessageType::ConfigReply(Ok(entry)) => write!(
+                f,
+                "config-reply {} {}",
+                Text(&entry.key),
+                ConfigText(entry.key, entry.value)
+            ),
+            MessageType::ConfigReply(Err(ConfigError::WrongType { key, expected })) => write!(
+                f,
+                "config-reply {} wrong-type expected={}",
+                Text(key),
+                Text(expected)
+            ),
+            MessageType::ConfigReply(Err(ConfigError::OutOfRange { key, min, max })) => write!(
+                f,
+                "config-reply {} out-of-range min={} max={}",
+                Text(key),
+                ConfigText(*key, *min),
+                ConfigText(*key, *max)
+            ),
+            MessageType::ConfigReply(Err(ConfigError::ReadOnly { key })) => {
+                write!(f, "config-reply {} read-only", Text(key))
+            }
+            MessageType::Discover => f.write_str("discover"),
+            MessageType::Announce(announce) => write!(
+                f,
+                "announce id={} firmware={} protocols={} strips={} patterns={}",
+                announce.id,
+                Text(&announce.firmware_version),
+                Text(&announce.protocol_versions),
+                Text(&announce.strips),
+                Text(&announce.patterns)
+            ),
+            MessageType::Heartbeat(heartbeat) => write!(
+                f,
+                "heartbeat seq={} uptime={} reset={}",
+                heartbeat.seq,
+                heartbeat.uptime_ms,
+                Text(&heartbeat.reset_reason)
+            ),
+            MessageType::LinkStatsRequest { peer } => {
+                write!(f, "link-stats-request {}", Text(peer))
+            }
+            MessageType::LinkStats { peer, stats } => {
+                write!(f, "link-stats {}", Text(peer))?;
+                let Some(stats) = stats else {
+                    return Ok(());
+                };
+                write!(
+                    f,
+                    " rssi={} rssi-avg={} since={} heartbeats={} lost={} restarts={}",
+                    stats.rssi,
+                    stats.rssi_avg,
+                    stats.since_last_seen_ms,
+                    stats.heartbeats,
+                    stats.heartbeats_lost,
+                    stats.restarts
+                )?;
+                if let Some(heartbeat) = &stats.last_heartbeat {
+                    write!(
+                        f,
+                        " last-seq={} last-uptime={} last-reset={}",
+                        heartbeat.seq,
+                        heartbeat.uptime_ms,
+                        Text(&heartbeat.reset_reason)
+                    )?;
+                }
+                Ok(())
+            }
+            MessageType::ButtonChord(buttons) => {
+                f.write_str("chord")?;
+                if !buttons.is_empty() {
+                    write!(f, " {}", Text(buttons))?;
+                }
+                Ok(())
+            }
+            MessageType::ButtonSequence(sequence) => {
+                f.write_str("sequence")?;
+                if !sequence.buttons.is_empty() {
+                    write!(f, " {}", Text(&sequence.buttons))?;
+                }
+                Ok(())
+            }
+            MessageType::SceneStore(store) => {
+                write!(f, "scene-store {}", store.slot)?;
+                let Some(scene) = &store.scene else {
+                    return Ok(());
+                };
+                write!(f, " {}", AnimationText(&scene.animation))?;
+                if !scene.strip_levels.is_empty() {
+                    write!(f, " levels={}", Text(&scene.strip_levels))?;
+                }
+                Ok(())
+            }
+            MessageType::SceneRecall { slot } => write!(f, "scene-recall {slot}"),
+            MessageType::SceneList => f.write_str("scene-list"),
+            MessageType::SceneReply(Ok(slots)) => {
+                f.write_str("scene-reply")?;
+                if !slots.is_empty() {
+                    write!(f, " {}", Text(slots))?;
+                }
+                Ok(())
+            }
+            MessageType::SceneReply(Err(SceneError::NoSuchSlot { slot })) => {
+                write!(f, "scene-reply no-such-slot {slot}")
+            }
+            MessageType::SceneReply(Err(SceneError::Empty { slot })) => {
+                write!(f, "scene-reply empty {slot}")
+            }
+            MessageType::SceneReply(Err(SceneError::NothingShowing)) => {
+                f.write_str("scene-reply nothing-showing")
+            }
+            MessageType::KeyShare(share) => write!(f, "key-share {}", Text(&share.public_key)),
+            MessageType::KeyConfirm(confirm) => write!(f, "key-confirm {}", Text(&confirm.tag)),
+            MessageType::PixelFrame(frame) => {
+                write!(f, "pixels {} offset={}", frame.strip, frame.offset)?;
+                if !frame.pixels.is_empty() {
+                    write!(f, " data={}", Text(&frame.pixels))?;
+                }
+                if let Some(number) = frame.frame {
+                    write!(f, " frame={number}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The words of a line, handed out to whoever parses them. Arguments are the words that aren't
+/// options, in order.
+struct Line<'a> {
+    words: heapless::Vec<&'a str, MAX_WORDS>,
+    used: [bool; MAX_WORDS],
+}
+
+impl<'a> Line<'a> {
+    fn new(line: &'a str) -> Result<Self, ParseError> {
+        let mut words = heapless::Vec::new();
+        for word in line.split_ascii_whitespace() {
+            words.push(word).map_err(|_| ParseError::TooLong)?;
+        }
+        Ok(Self {
+            words,
+            used: [false; MAX_WORDS],
+        })
+    }
+
+    /// Mark the first unused word `matches` accepts as used, returning what it made of it.
+    fn take(&mut self, matches: impl Fn(&'a str) -> Option<&'a str>) -> Option<&'a str> {
+        let (word, used) = self
+            .words
+            .iter()
+            .zip(&mut self.used)
+            .filter(|(_, used)| !**used)
+            .find_map(|(word, used)| matches(word).map(|word| (word, used)))?;
+        *used = true;
+        Some(word)
+    }
+
+    /// The next argument, as is.
+    fn word(&mut self, name: &'static str) -> Result<&'a str, ParseError> {
+        self.take(|word| (!word.contains('=')).then_some(word))
+            .ok_or(ParseError::Missing(name))
+    }
+
+    fn optional_argument<T: Value>(&mut self, name: &'static str) -> Result<Option<T>, ParseError> {
+        match self.word(name) {
+            Ok(word) => T::parse(word).map(Some).ok_or(ParseError::Invalid(name)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn argument<T: Value>(&mut self, name: &'static str) -> Result<T, ParseError> {
+        self.optional_argument(name)?
+            .ok_or(ParseError::Missing(name))
+    }
+
+    fn option_with<T>(
+        &mut self,
+        key: &'static str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<Option<T>, ParseError> {
+        let Some(value) = self.take(|word| word.strip_prefix(key)?.strip_prefix('=')) else {
+            return Ok(None);
+        };
+        parse(value).map(Some).ok_or(ParseError::Invalid(key))
+    }
+
+    fn option<T: Value>(&mut self, key: &'static str) -> Result<Option<T>, ParseError> {
+        self.option_with(key, T::parse)
+    }
+
+    fn required_with<T>(
+        &mut self,
+        key: &'static str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<T, ParseError> {
+        self.option_with(key, parse)?
+            .ok_or(ParseError::Missing(key))
+    }
+
+    fn required<T: Value>(&mut self, key: &'static str) -> Result<T, ParseError> {
+        self.required_with(key, T::parse)
+    }
+
+    /// Check that every word was used.
+    fn finish(&self) -> Result<(), ParseError> {
+        match self.used[..self.words.len()].iter().all(|&used| used) {
+            true => Ok(()),
+            false => Err(ParseError::Unexpected),
+        }
+    }
+}
+
+/// A value written as a single word.
+trait Value: Sized {
+    fn parse(word: &str) -> Option<Self>;
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result;
+}
+
+/// Formats a [`Value`].
+struct Text<'a, T>(&'a T);
+
+impl<T: Value> Display for Text<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.write(f)
+    }
+}
+
+macro_rules! numbers {
+    ($($type:ty),*) => {$(
+        impl Value for $type {
+            fn parse(word: &str) -> Option<Self> {
+                match word.strip_prefix("0x") {
+                    Some(hex) => <$type>::from_str_radix(hex, 16).ok(),
+                    None => word.parse().ok(),
+                }
+            }
+
+            fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                write!(f, "{self}")
+            }
+        }
+    )*};
+}
+
+numbers!(u8, u16, u32, u64, usize, i8);
+
+/// An enum without fields, written as the name of its variant.
+trait Named: Copy + 'static {
+    fn name(&self) -> &'static str;
+
+    fn from_name(name: &str) -> Option<Self>;
+}
+
+impl<T: Named> Value for T {
+    fn parse(word: &str) -> Option<Self> {
+        Self::from_name(word)
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+macro_rules! names {
+    ($($type:ident { $($variant:ident => $name:literal,)* })*) => {$(
+        impl Named for $type {
+            fn name(&self) -> &'static str {
+                match self {
+                    $($type::$variant => $name,)*
+                }
+            }
+
+            fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some($type::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    )*};
+}
+
+names! {
+    ButtonNumber {
+        Button1 => "1",
+        Button2 => "2",
+        Button3 => "3",
+        Button4 => "4",
+    }
+    Pattern {
+        Off => "off",
+        Solid => "solid",
+        HueSweep => "hue-sweep",
+        Breathe => "breathe",
+        Blink => "blink",
+        Rainbow => "rainbow",
+    }
+    LightMode {
+        Off => "off",
+        Timed => "timed",
+        Latched => "latched",
+        Streaming => "streaming",
+    }
+    ErrorCode {
+        SendFailed => "send-failed",
+        Unauthenticated => "unauthenticated",
+        Malformed => "malformed",
+        Rejected => "rejected",
+        Fragment => "fragment",
+    }
+    OtaState {
+        Idle => "idle",
+        Receiving => "receiving",
+        Complete => "complete",
+        Committed => "committed",
+    }
+    OtaError {
+        NoSession => "no-session",
+        TooLarge => "too-large",
+        Flash => "flash",
+        Incomplete => "incomplete",
+        HashMismatch => "hash-mismatch",
+    }
+    ConfigKey {
+        AnimationTimeoutMs => "animation-timeout",
+        Brightness => "brightness",
+        Channel => "channel",
+        LedCount => "led-count",
+        RelayHops => "relay-hops",
+    }
+    ConfigType {
+        U8 => "u8",
+        U16 => "u16",
+        U32 => "u32",
+    }
+    ResetReason {
+        PowerOn => "power-on",
+        Software => "software",
+        Watchdog => "watchdog",
+        Brownout => "brownout",
+        DeepSleep => "deep-sleep",
+        Other => "other",
+    }
+}
+
+/// Parse the comma separated items of `word`, none if it is empty.
+fn parse_list<'a, T: Value + 'a>(word: &'a str) -> impl Iterator<Item = Option<T>> + 'a {
+    word.split(',')
+        .filter(move |_| !word.is_empty())
+        .map(T::parse)
+}
+
+fn write_list<'a, T: Value + 'a>(
+    f: &mut Formatter<'_>,
+    items: impl IntoIterator<Item = &'a T>,
+) -> fmt::Result {
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            f.write_char(',')?;
+        }
+        item.write(f)?;
+    }
+    Ok(())
+}
+
+fn parse_hex(word: &str) -> impl Iterator<Item = Option<u8>> + '_ {
+    word.as_bytes().chunks(2).map(|pair| {
+        // The last chunk of an odd number of digits is one digit short.
+        let pair = core::str::from_utf8(pair)
+            .ok()
+            .filter(|pair| pair.len() == 2)?;
+        u8::from_str_radix(pair, 16).ok()
+    })
+}
+
+fn write_hex(f: &mut Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+}
+
+impl Value for Destination {
+    fn parse(word: &str) -> Option<Self> {
+        if word == "all" {
+            return Some(Destination::All);
+        }
+        let (kind, value) = word.split_once(':')?;
+        match kind {
+            "device" => Value::parse(value).map(Destination::Device),
+            "groups" => Value::parse(value).map(Destination::Groups),
+            _ => None,
+        }
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Destination::All => f.write_str("all"),
+            Destination::Device(id) => write!(f, "device:{id}"),
+            Destination::Groups(groups) => write!(f, "groups:{groups:#x}"),
+        }
+    }
+}
+
+impl Value for MacAddress {
+    fn parse(word: &str) -> Option<Self> {
+        let mut mac = [0; 6];
+        let mut parts = word.split(':');
+        for byte in &mut mac {
+            *byte = u8::from_str_radix(parts.next().filter(|part| part.len() == 2)?, 16).ok()?;
+        }
+        parts.next().is_none().then_some(mac)
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_char(':')?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Value for [u8; 32] {
+    fn parse(word: &str) -> Option<Self> {
+        let mut bytes = [0; 32];
+        let mut hex = parse_hex(word);
+        for byte in &mut bytes {
+            *byte = hex.next()??;
+        }
+        hex.next().is_none().then_some(bytes)
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_hex(f, self)
+    }
+}
+
+impl<const N: usize> Value for heapless::Vec<u8, N> {
+    fn parse(word: &str) -> Option<Self> {
+        let mut bytes = heapless::Vec::new();
+        for byte in parse_hex(word) {
+            bytes.push(byte?).ok()?;
+        }
+        Some(bytes)
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_hex(f, self)
+    }
+}
+
+impl<const N: usize> Value for heapless::Vec<[u8; 3], N> {
+    fn parse(word: &str) -> Option<Self> {
+        let mut pixels = heapless::Vec::new();
+        let mut hex = parse_hex(word).peekable();
+        while hex.peek().is_some() {
+            pixels
+                .push([hex.next()??, hex.next()??, hex.next()??])
+                .ok()?;
+        }
+        Some(pixels)
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_hex(f, self.as_flattened())
+    }
+}
+
+impl<const N: usize> Value for heapless::Vec<u16, N> {
+    fn parse(word: &str) -> Option<Self> {
+        let mut items = heapless::Vec::new();
+        for item in parse_list(word) {
+            items.push(item?).ok()?;
+        }
+        Some(items)
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_list(f, self)
+    }
+}
+
+impl<const N: usize> Value for heapless::Vec<ButtonNumber, N> {
+    fn parse(word: &str) -> Option<Self> {
+        let mut items = heapless::Vec::new();
+        for item in parse_list(word) {
+            items.push(item?).ok()?;
+        }
+        Some(items)
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_list(f, self)
+    }
+}
+
+impl Value for ButtonSet {
+    fn parse(word: &str) -> Option<Self> {
+        parse_list(word).collect()
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_list(f, &self.iter().collect::<heapless::Vec<_, 4>>())
+    }
+}
+
+impl Value for PatternSet {
+    fn parse(word: &str) -> Option<Self> {
+        parse_list(word).collect()
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        const ALL: [Pattern; 6] = [
+            Pattern::Off,
+            Pattern::Solid,
+            Pattern::HueSweep,
+            Pattern::Breathe,
+            Pattern::Blink,
+            Pattern::Rainbow,
+        ];
+        write_list(f, ALL.iter().filter(|pattern| self.contains(**pattern)))
+    }
+}
+
+impl Value for SceneSet {
+    fn parse(word: &str) -> Option<Self> {
+        parse_list(word)
+            .map(|slot: Option<u8>| slot.filter(|&slot| (slot as usize) < MAX_SCENES))
+            .collect()
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_list(f, &self.iter().collect::<heapless::Vec<_, MAX_SCENES>>())
+    }
+}
+
+impl Value for Color {
+    fn parse(word: &str) -> Option<Self> {
+        let (kind, values) = word.split_once(':')?;
+        let mut values = values.split(',').map(u8::parse);
+        let (a, b, c) = (values.next()??, values.next()??, values.next()??);
+        if values.next().is_some() {
+            return None;
+        }
+        match kind {
+            "hsv" => Some(Color::Hsv {
+                hue: a,
+                sat: b,
+                val: c,
+            }),
+            "rgb" => Some(Color::Rgb { r: a, g: b, b: c }),
+            _ => None,
+        }
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Color::Hsv { hue, sat, val } => write!(f, "hsv:{hue},{sat},{val}"),
+            Color::Rgb { r, g, b } => write!(f, "rgb:{r},{g},{b}"),
+        }
+    }
+}
+
+impl Value for FirmwareVersion {
+    fn parse(word: &str) -> Option<Self> {
+        let mut parts = word.split('.').map(u8::parse);
+        let version = FirmwareVersion {
+            major: parts.next()??,
+            minor: parts.next()??,
+            patch: parts.next()??,
+        };
+        parts.next().is_none().then_some(version)
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl Value for ProtocolVersions {
+    fn parse(word: &str) -> Option<Self> {
+        let (oldest, newest) = word.split_once('-')?;
+        Some(ProtocolVersions {
+            oldest: u8::parse(oldest)?,
+            newest: u8::parse(newest)?,
+        })
+    }
+
+    fn write(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.oldest, self.newest)
+    }
+}
+
+/// The options of an animation playing `pattern`, defaulting to [`DEFAULT_ANIMATION`]'s.
+fn parse_animation(pattern: Pattern, line: &mut Line<'_>) -> Result<SetAnimation, ParseError> {
+    Ok(SetAnimation {
+        pattern,
+        color: line.option("color")?.unwrap_or(DEFAULT_ANIMATION.color),
+        speed: line.option("speed")?.unwrap_or(DEFAULT_ANIMATION.speed),
+        brightness: line
+            .option("brightness")?
+            .unwrap_or(DEFAULT_ANIMATION.brightness),
+        duration_ms: line.option("duration")?,
+    })
+}
+
+/// Formats an animation as its pattern and options.
+struct AnimationText<'a>(&'a SetAnimation);
+
+impl Display for AnimationText<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let animation = self.0;
+        write!(
+            f,
+            "{} color={} speed={} brightness={}",
+            Text(&animation.pattern),
+            Text(&animation.color),
+            animation.speed,
+            animation.brightness
+        )?;
+        if let Some(duration_ms) = animation.duration_ms {
+            write!(f, " duration={duration_ms}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a value for `key`, of the key's type unless it says otherwise.
+fn parse_config_value(key: ConfigKey, word: &str) -> Option<ConfigValue> {
+    let (kind, value) = match word.split_once(':') {
+        Some((kind, value)) => (ConfigType::from_name(kind)?, value),
+        None => (key.info().default.kind(), word),
+    };
+    match kind {
+        ConfigType::U8 => Value::parse(value).map(ConfigValue::U8),
+        ConfigType::U16 => Value::parse(value).map(ConfigValue::U16),
+        ConfigType::U32 => Value::parse(value).map(ConfigValue::U32),
+    }
+}
+
+/// Formats a value for a key, with its type if it isn't the key's.
+struct ConfigText(ConfigKey, ConfigValue);
+
+impl Display for ConfigText {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let ConfigText(key, value) = *self;
+        if value.kind() != key.info().default.kind() {
+            write!(f, "{}:", Text(&value.kind()))?;
+        }
+        match value {
+            ConfigValue::U8(value) => write!(f, "{value}"),
+            ConfigValue::U16(value) => write!(f, "{value}"),
+            ConfigValue::U32(value) => write!(f, "{value}"),
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! Encoding frames, and decoding them across protocol revisions.
+//!
+//! A frame is the protocol version byte followed by the postcard encoding of a [`MessageType`] and
+//! then of the fields later revisions appended to [`Message`]. No frame is longer than
+//! [`MAX_ENCODED_LEN`]. Lights and remotes are flashed independently, so revisions have to stay
+//! compatible with each other. That relies on three rules:
+//!
+//! * `MessageType` and the enums nested inside it only ever gain variants at the end, so the
+//!   discriminants of existing variants never change.
+//! * A frame is stamped with the oldest revision that knows every variant used in it (see
+//!   [`MessageType::introduced_in`]), not with the sender's own revision. Older firmware that
+//!   insists on an exact version match keeps accepting the messages it understands.
+//! * New fields are only ever appended to `Message`. Readers default the ones missing from frames
+//!   sent by older revisions, and ignore anything following the ones they know.
+//!
+//! Decoding isn't accepting, though. Revision 0 frames still decode, but revision 0 firmware
+//! neither signs nor numbers its frames, so they fail [`open`](crate::open) and its messages are no
+//! longer accepted on the air. Revision 0 firmware does still accept what newer firmware sends it.
+
+use postcard::experimental::max_size::MaxSize;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    ButtonEventType, ConfigError, ConfigKey, ConfigValue, Destination, ErrorCode, LightMode,
+    MAX_FRAME_LEN, Message, MessageType, NO_MESSAGE_ID, OtaError, OtaState, Pattern, ResetReason,
+    SceneError, TAG_LEN,
+};
+
+/// Longest frame any message encodes to, before the authentication tag.
+pub const MAX_ENCODED_LEN: usize = Message::POSTCARD_MAX_SIZE;
+
+const _: () = assert!(
+    MAX_ENCODED_LEN + TAG_LEN <= MAX_FRAME_LEN,
+    "every message must fit in a signed frame"
+);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    /// The buffer is too small for the message. [`MAX_ENCODED_LEN`] bytes are always enough.
+    BufferFull,
+    /// Postcard failed otherwise.
+    Malformed(postcard::Error),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The frame ends in the middle of the message, or before the version byte.
+    Truncated,
+    /// The frame is stamped with an older revision than the one that introduced its message.
+    BadVersion { protocol_version: u8 },
+    /// The frame uses a message, or a variant nested in one, that this crate doesn't know about.
+    /// Likely from a newer revision.
+    UnknownType { protocol_version: u8 },
+    /// The frame is not a valid encoding of any known message.
+    Malformed(postcard::Error),
+}
+
+impl DecodeError {
+    pub(crate) fn from_postcard(error: postcard::Error, protocol_version: u8) -> Self {
+        match error {
+            postcard::Error::DeserializeUnexpectedEnd => DecodeError::Truncated,
+            // Serde's error for a variant index out of range.
+            postcard::Error::SerdeDeCustom => DecodeError::UnknownType { protocol_version },
+            e => DecodeError::Malformed(e),
+        }
+    }
+}
+
+/// Largest encoding of a `heapless::Vec`, which postcard only derives [`MaxSize`] of for older
+/// heapless versions.
+pub(crate) const fn max_vec_size<T: MaxSize, const N: usize>() -> usize {
+    let mut len_size = 1;
+    while N >> (7 * len_size) != 0 {
+        len_size += 1;
+    }
+    len_size + N * T::POSTCARD_MAX_SIZE
+}
+
+impl Message {
+    /// Wrap `message_type` in a message stamped with the oldest revision able to decode it.
+    pub fn new(message_type: MessageType, counter: u32) -> Self {
+        Self {
+            protocol_version: message_type.introduced_in(),
+            message_type,
+            counter,
+            destination: Destination::All,
+            id: NO_MESSAGE_ID,
+            hops: 0,
+            origin: None,
+        }
+    }
+
+    /// Encode the message into the start of `buf`. Returns the frame, without any padding.
+    pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Result<&'a [u8], EncodeError> {
+        match postcard::to_slice(self, buf) {
+            Ok(frame) => Ok(frame),
+            Err(postcard::Error::SerializeBufferFull) => Err(EncodeError::BufferFull),
+            Err(e) => Err(EncodeError::Malformed(e)),
+        }
+    }
+
+    /// Decode a frame produced by any protocol revision, older or newer than this one.
+    ///
+    /// Trailing bytes are ignored, both the zero padding some senders add and fields appended by
+    /// newer revisions.
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (&protocol_version, body) = bytes.split_first().ok_or(DecodeError::Truncated)?;
+        let malformed = |e| DecodeError::from_postcard(e, protocol_version);
+
+        let (message_type, rest) =
+            postcard::take_from_bytes::<MessageType>(body).map_err(malformed)?;
+        if message_type.introduced_in() > protocol_version {
+            return Err(DecodeError::BadVersion { protocol_version });
+        }
+        let (counter, rest) = take_appended(rest).map_err(malformed)?;
+        let (destination, rest) = take_appended(rest).map_err(malformed)?;
+        let (id, rest) = take_appended(rest).map_err(malformed)?;
+        let (hops, rest) = take_appended(rest).map_err(malformed)?;
+        let (origin, _) = take_appended(rest).map_err(malformed)?;
+
+        Ok(Self {
+            protocol_version,
+            message_type,
+            counter,
+            destination,
+            id,
+            hops,
+            origin,
+        })
+    }
+}
+
+/// Decode a field appended to `Message`, or default it if the sender predates it.
+fn take_appended<T: DeserializeOwned + Default>(
+    bytes: &[u8],
+) -> Result<(T, &[u8]), postcard::Error> {
+    if bytes.is_empty() {
+        Ok((T::default(), bytes))
+    } else {
+        postcard::take_from_bytes(bytes)
+    }
+}
+
+impl MessageType {
+    /// The protocol revision that introduced this message, including any nested variants.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            MessageType::ButtonEvent { event_type, .. } => event_type.introduced_in(),
+            MessageType::Handshake(_)
+            | MessageType::HandshakeResponse(_)
+            | MessageType::HandshakeConfirm(_)
+            | MessageType::Ack { .. }
+            | MessageType::Fragment(_) => 1,
+            MessageType::SetAnimation(animation) => animation.pattern.introduced_in(),
+            MessageType::StatusRequest
+            | MessageType::SetAddress(_)
+            | MessageType::TimeRequest(_)
+            | MessageType::TimeResponse(_) => 1,
+            MessageType::LightStatus(status) => status
+                .mode
+                .introduced_in()
+                .max(status.pattern.introduced_in())
+                .max(status.last_error.map_or(1, |e| e.introduced_in())),
+            MessageType::OtaBegin(_) | MessageType::OtaChunk(_) | MessageType::OtaCommit => 1,
+            MessageType::OtaStatus(status) => status
+                .state
+                .introduced_in()
+                .max(status.error.map_or(1, |e| e.introduced_in())),
+            MessageType::ConfigGet(key) => key.introduced_in(),
+            MessageType::ConfigSet(entry) | MessageType::ConfigReply(Ok(entry)) => {
+                entry.key.introduced_in().max(entry.value.introduced_in())
+            }
+            MessageType::ConfigList => 1,
+            MessageType::ConfigReply(Err(error)) => error.introduced_in(),
+            MessageType::Discover | MessageType::Announce(_) => 1,
+            MessageType::Heartbeat(heartbeat) => heartbeat.reset_reason.introduced_in(),
+            MessageType::LinkStatsRequest { .. } => 1,
+            MessageType::LinkStats { stats, .. } => stats
+                .and_then(|stats| stats.last_heartbeat)
+                .map_or(1, |heartbeat| heartbeat.reset_reason.introduced_in()),
+            MessageType::ButtonChord(_) | MessageType::ButtonSequence(_) => 1,
+            MessageType::SceneStore(store) => store
+                .scene
+                .as_ref()
+                .map_or(1, |scene| scene.animation.pattern.introduced_in()),
+            MessageType::SceneRecall { .. } | MessageType::SceneList => 1,
+            MessageType::SceneReply(Ok(_)) => 1,
+            MessageType::SceneReply(Err(error)) => error.introduced_in(),
+            MessageType::KeyShare(_) | MessageType::KeyConfirm(_) => 1,
+            MessageType::PixelFrame(_) => 1,
+        }
+    }
+}
+
+impl ButtonEventType {
+    /// The protocol revision that introduced this event type.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            ButtonEventType::ShortPress { .. } | ButtonEventType::LongPress => 0,
+            ButtonEventType::Pressed
+            | ButtonEventType::Released
+            | ButtonEventType::HoldRepeat { .. } => 1,
+        }
+    }
+}
+
+impl Pattern {
+    /// The protocol revision that introduced this pattern.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            Pattern::Off
+            | Pattern::Solid
+            | Pattern::HueSweep
+            | Pattern::Breathe
+            | Pattern::Blink
+            | Pattern::Rainbow => 1,
+        }
+    }
+}
+
+impl LightMode {
+    /// The protocol revision that introduced this mode.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            LightMode::Off | LightMode::Timed | LightMode::Latched | LightMode::Streaming => 1,
+        }
+    }
+}
+
+impl ErrorCode {
+    /// The protocol revision that introduced this error code.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            ErrorCode::SendFailed
+            | ErrorCode::Unauthenticated
+            | ErrorCode::Malformed
+            | ErrorCode::Rejected
+            | ErrorCode::Fragment => 1,
+        }
+    }
+}
+
+impl OtaState {
+    /// The protocol revision that introduced this state.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            OtaState::Idle | OtaState::Receiving | OtaState::Complete | OtaState::Committed => 1,
+        }
+    }
+}
+
+impl OtaError {
+    /// The protocol revision that introduced this error.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            OtaError::NoSession
+            | OtaError::TooLarge
+            | OtaError::Flash
+            | OtaError::Incomplete
+            | OtaError::HashMismatch => 1,
+        }
+    }
+}
+
+impl ConfigKey {
+    /// The protocol revision that introduced this setting.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            ConfigKey::AnimationTimeoutMs
+            | ConfigKey::Brightness
+            | ConfigKey::Channel
+            | ConfigKey::LedCount
+            | ConfigKey::RelayHops => 1,
+        }
+    }
+}
+
+impl ConfigValue {
+    /// The protocol revision that introduced this type of value.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            ConfigValue::U8(_) | ConfigValue::U16(_) | ConfigValue::U32(_) => 1,
+        }
+    }
+}
+
+impl ConfigError {
+    /// The protocol revision that introduced this error, and the setting it is about.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            ConfigError::WrongType { key, .. }
+            | ConfigError::OutOfRange { key, .. }
+            | ConfigError::ReadOnly { key } => key.introduced_in(),
+        }
+    }
+}
+
+impl ResetReason {
+    /// The protocol revision that introduced this reset reason.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            ResetReason::PowerOn
+            | ResetReason::Software
+            | ResetReason::Watchdog
+            | ResetReason::Brownout
+            | ResetReason::DeepSleep
+            | ResetReason::Other => 1,
+        }
+    }
+}
+
+impl SceneError {
+    /// The protocol revision that introduced this error.
+    pub fn introduced_in(&self) -> u8 {
+        match self {
+            SceneError::NoSuchSlot { .. }
+            | SceneError::Empty { .. }
+            | SceneError::NothingShowing => 1,
+        }
+    }
+}
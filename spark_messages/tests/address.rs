@@ -0,0 +1,94 @@
+use spark_messages::{Address, ButtonEventType, ButtonNumber, Destination, Message, MessageType};
+
+const KITCHEN: u32 = 1 << 0;
+const HALLWAY: u32 = 1 << 1;
+const PORCH: u32 = 1 << 2;
+
+const KITCHEN_LIGHT: Address = Address {
+    id: 1,
+    groups: KITCHEN,
+};
+const HALLWAY_LIGHT: Address = Address {
+    id: 2,
+    groups: HALLWAY | PORCH,
+};
+
+fn button_press() -> Message {
+    Message::new(
+        MessageType::ButtonEvent {
+            button_number: ButtonNumber::Button1,
+            event_type: ButtonEventType::ShortPress { count: 1 },
+        },
+        42,
+    )
+}
+
+#[test]
+fn all_reaches_every_light() {
+    assert!(Destination::All.matches(&KITCHEN_LIGHT));
+    assert!(Destination::All.matches(&HALLWAY_LIGHT));
+    assert!(Destination::All.matches(&Address { id: 0, groups: 0 }));
+}
+
+#[test]
+fn device_reaches_one_light() {
+    assert!(Destination::Device(1).matches(&KITCHEN_LIGHT));
+    assert!(!Destination::Device(1).matches(&HALLWAY_LIGHT));
+    assert!(!Destination::Device(3).matches(&KITCHEN_LIGHT));
+}
+
+#[test]
+fn groups_reach_their_members() {
+    assert!(Destination::Groups(KITCHEN).matches(&KITCHEN_LIGHT));
+    assert!(!Destination::Groups(KITCHEN).matches(&HALLWAY_LIGHT));
+
+    // Any group in common is enough.
+    assert!(Destination::Groups(PORCH).matches(&HALLWAY_LIGHT));
+    assert!(Destination::Groups(KITCHEN | PORCH).matches(&KITCHEN_LIGHT));
+    assert!(Destination::Groups(KITCHEN | PORCH).matches(&HALLWAY_LIGHT));
+
+    assert!(!Destination::Groups(0).matches(&KITCHEN_LIGHT));
+    assert!(!Destination::Groups(KITCHEN).matches(&Address { id: 1, groups: 0 }));
+}
+
+#[test]
+fn unconfigured_lights_are_in_every_group() {
+    let address = Address::unconfigured([0x24, 0x0A, 0xC4, 0x00, 0x12, 0x34]);
+    assert_eq!(address.id, 0x1234);
+    assert!(Destination::Groups(1 << 31).matches(&address));
+    assert!(Destination::Device(0x1234).matches(&address));
+}
+
+#[test]
+fn defaults_to_all() {
+    assert_eq!(button_press().destination, Destination::All);
+
+    // Frames from senders predating addressing end with the counter, or with zero padding.
+    let mut buf = [0u8; 32];
+    let len = postcard::to_slice(&button_press(), &mut buf).unwrap().len();
+    let without_destination = &buf[..len - 1];
+    assert_eq!(
+        Message::decode(without_destination)
+            .unwrap()
+            .destination,
+        Destination::All
+    );
+    assert_eq!(
+        Message::decode(&buf).unwrap().destination,
+        Destination::All
+    );
+}
+
+#[test]
+fn round_trips() {
+    for destination in [
+        Destination::All,
+        Destination::Device(u16::MAX),
+        Destination::Groups(HALLWAY | PORCH),
+    ] {
+        let message = button_press().with_destination(destination);
+        let mut buf = [0u8; 32];
+        let frame = postcard::to_slice(&message, &mut buf).unwrap();
+        assert_eq!(Message::decode(frame).unwrap(), message);
+    }
+}
@@ -0,0 +1,178 @@
+use spark_messages::{
+    AuthError, DecodeError, Key, MAX_FRAME_LEN, MacAddress, Message, MessageType, OpenError,
+    TAG_LEN, open, parse_key, sign, verify,
+};
+
+const KEY: Key = parse_key("000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F");
+
+const REMOTE: MacAddress = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+const OTHER_REMOTE: MacAddress = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBD];
+const LIGHT: MacAddress = [0x24, 0x6F, 0x28, 0x00, 0x00, 0x01];
+
+fn signed(frame: &[u8]) -> Vec<u8> {
+    let mut buf = [0u8; 64];
+    buf[..frame.len()].copy_from_slice(frame);
+    sign(&KEY, &REMOTE, &mut buf, frame.len()).unwrap().to_vec()
+}
+
+fn signed_message(sender: &MacAddress, message: &Message) -> Vec<u8> {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = message.encode(&mut buf).unwrap().len();
+    sign(&KEY, sender, &mut buf, len).unwrap().to_vec()
+}
+
+#[test]
+fn parses_keys() {
+    assert_eq!(KEY[0], 0x00);
+    assert_eq!(KEY[10], 0x0A);
+    assert_eq!(KEY[31], 0x1F);
+}
+
+#[test]
+fn round_trips() {
+    let frame = [1, 0, 2, 7];
+    let signed = signed(&frame);
+    assert_eq!(signed.len(), frame.len() + TAG_LEN);
+    assert_eq!(verify(&KEY, &REMOTE, &signed), Ok(&frame[..]));
+}
+
+#[test]
+fn matches_hmac_sha256() {
+    // RFC 4231 test case 2, truncated.
+    let key = {
+        let mut key = [0u8; 32];
+        key[..4].copy_from_slice(b"Jefe");
+        key
+    };
+    // The sender goes first, so it's the start of the test data.
+    let sender = *b"what d";
+    let mut buf = [0u8; 64];
+    let frame = b"o ya want for nothing?";
+    buf[..frame.len()].copy_from_slice(frame);
+
+    let signed = sign(&key, &sender, &mut buf, frame.len()).unwrap();
+    assert_eq!(
+        &signed[frame.len()..],
+        &[0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e]
+    );
+}
+
+#[test]
+fn rejects_tampered_frames() {
+    let signed = signed(&[1, 0, 2, 7]);
+
+    for i in 0..signed.len() {
+        let mut tampered = signed.clone();
+        tampered[i] ^= 0x01;
+        assert_eq!(
+            verify(&KEY, &REMOTE, &tampered),
+            Err(AuthError::BadTag),
+            "byte {i}"
+        );
+    }
+}
+
+#[test]
+fn rejects_other_keys() {
+    let signed = signed(&[1, 0, 2, 7]);
+    let mut other = KEY;
+    other[0] ^= 0x80;
+    assert_eq!(verify(&other, &REMOTE, &signed), Err(AuthError::BadTag));
+}
+
+#[test]
+fn rejects_other_senders() {
+    let signed = signed(&[1, 0, 2, 7]);
+    assert_eq!(verify(&KEY, &OTHER_REMOTE, &signed), Err(AuthError::BadTag));
+}
+
+#[test]
+fn rejects_truncated_frames() {
+    let signed = signed(&[1, 0, 2, 7]);
+
+    for len in TAG_LEN..signed.len() {
+        assert_eq!(
+            verify(&KEY, &REMOTE, &signed[..len]),
+            Err(AuthError::BadTag),
+            "length {len}"
+        );
+    }
+    for len in 0..TAG_LEN {
+        assert_eq!(
+            verify(&KEY, &REMOTE, &signed[..len]),
+            Err(AuthError::Truncated),
+            "length {len}"
+        );
+    }
+}
+
+#[test]
+fn rejects_unsigned_frames() {
+    // What a remote that predates authentication sends.
+    let mut frame = [0u8; 32];
+    frame[..4].copy_from_slice(&[0, 0, 0, 1]);
+    assert_eq!(verify(&KEY, &REMOTE, &frame), Err(AuthError::BadTag));
+
+    // It decodes, but isn't accepted.
+    assert!(Message::decode(&frame).is_ok());
+    assert_eq!(
+        open(&KEY, &REMOTE, &frame),
+        Err(OpenError::Auth(AuthError::BadTag))
+    );
+}
+
+#[test]
+fn needs_room_for_the_tag() {
+    let mut buf = [0u8; 4 + TAG_LEN - 1];
+    assert_eq!(sign(&KEY, &REMOTE, &mut buf, 4), Err(AuthError::BufferFull));
+}
+
+#[test]
+fn opens_frames_from_their_sender() {
+    let message = Message::new(MessageType::StatusRequest, 5).with_id(3);
+    let signed = signed_message(&REMOTE, &message);
+    assert_eq!(open(&KEY, &REMOTE, &signed), Ok(message));
+}
+
+#[test]
+fn rejects_frames_resent_as_another_sender() {
+    // A frame captured from one remote, sent again with the address of another.
+    let message = Message::new(MessageType::StatusRequest, 5).with_id(3);
+    let signed = signed_message(&REMOTE, &message);
+    assert_eq!(
+        open(&KEY, &OTHER_REMOTE, &signed),
+        Err(OpenError::Auth(AuthError::BadTag))
+    );
+}
+
+#[test]
+fn opens_relayed_frames_as_their_origin() {
+    let message = Message::new(MessageType::StatusRequest, 5).with_id(3);
+    let relayed = message.relayed(REMOTE, 1).unwrap();
+    let signed = signed_message(&REMOTE, &relayed);
+    assert_eq!(open(&KEY, &LIGHT, &signed), Ok(relayed.clone()));
+
+    // Relays have to be signed as the origin they name.
+    let signed = signed_message(&LIGHT, &relayed);
+    assert_eq!(
+        open(&KEY, &LIGHT, &signed),
+        Err(OpenError::Auth(AuthError::BadTag))
+    );
+}
+
+#[test]
+fn tells_undecodable_frames_from_unauthentic_ones() {
+    let signed = signed(&[]);
+    assert_eq!(
+        open(&KEY, &REMOTE, &signed),
+        Err(OpenError::Decode(DecodeError::Truncated))
+    );
+    assert_eq!(
+        open(&KEY, &OTHER_REMOTE, &signed),
+        Err(OpenError::Auth(AuthError::BadTag))
+    );
+    assert_eq!(
+        open(&KEY, &REMOTE, &signed[..TAG_LEN - 1]),
+        Err(OpenError::Auth(AuthError::Truncated))
+    );
+}
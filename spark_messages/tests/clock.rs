@@ -0,0 +1,164 @@
+use spark_messages::{ClockSync, SYNC_INTERVAL_MS, TimeResponse};
+
+/// Deterministic xorshift, so failures can be reproduced.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn between(&mut self, min: u64, max: u64) -> u64 {
+        min + self.next() % (max - min + 1)
+    }
+}
+
+/// A remote whose clock started `offset_us` before the light's and runs `drift_ppm` faster. The
+/// light's clock doubles as the true time.
+struct Remote {
+    offset_us: i64,
+    drift_ppm: f64,
+}
+
+impl Remote {
+    fn clock(&self, true_us: u64) -> u64 {
+        ((true_us as f64 * (1.0 + self.drift_ppm * 1e-6)) as i64 + self.offset_us) as u64
+    }
+}
+
+/// One exchange starting at true time `at_us`, with each leg of the trip taking between
+/// `min_latency_us` and `max_latency_us`. Returns when the response arrives.
+fn exchange(
+    sync: &mut ClockSync,
+    remote: &Remote,
+    rng: &mut Rng,
+    at_us: u64,
+    min_latency_us: u64,
+    max_latency_us: u64,
+) -> u64 {
+    let request = sync.request(at_us);
+    let received_at = at_us + rng.between(min_latency_us, max_latency_us);
+    let sent_at = received_at + rng.between(50, 500);
+    let response = request.respond(remote.clock(received_at), remote.clock(sent_at));
+    let arrived_at = sent_at + rng.between(min_latency_us, max_latency_us);
+    assert!(sync.on_response(arrived_at, &response));
+    arrived_at
+}
+
+fn error_us(sync: &ClockSync, remote: &Remote, at_us: u64) -> i64 {
+    sync.network_time(at_us) as i64 - remote.clock(at_us) as i64
+}
+
+#[test]
+fn local_time_until_synchronised() {
+    let sync = ClockSync::new();
+    assert!(!sync.is_synchronised());
+    assert_eq!(sync.network_time(1234), 1234);
+    assert_eq!(sync.offset_us(1234), None);
+}
+
+#[test]
+fn compensates_symmetric_latency_exactly() {
+    let mut sync = ClockSync::new();
+    let request = sync.request(1_000_000);
+    // The remote's clock is 5s ahead, and the trip takes 2ms each way.
+    let response = request.respond(6_002_000, 6_002_300);
+    assert!(sync.on_response(1_004_300, &response));
+
+    assert!(sync.is_synchronised());
+    assert_eq!(sync.offset_us(1_004_300), Some(5_000_000));
+    assert_eq!(sync.network_time(2_000_000), 7_000_000);
+}
+
+#[test]
+fn only_accepts_the_latest_request() {
+    let mut sync = ClockSync::new();
+    let old = sync.request(1_000);
+    let new = sync.request(2_000);
+
+    assert!(!sync.on_response(3_000, &old.respond(10_000, 10_000)));
+    let forged = TimeResponse {
+        origin_us: 2_500,
+        receive_us: 10_000,
+        transmit_us: 10_000,
+    };
+    assert!(!sync.on_response(3_000, &forged));
+    assert!(!sync.is_synchronised());
+
+    let response = new.respond(10_500, 10_500);
+    assert!(sync.on_response(3_000, &response));
+    // Replayed.
+    assert!(!sync.on_response(3_500, &response));
+    assert_eq!(sync.offset_us(3_000), Some(8_000));
+}
+
+#[test]
+fn estimates_offset_despite_jitter() {
+    for seed in 1..20 {
+        let mut rng = Rng(seed * 7919);
+        let remote = Remote {
+            offset_us: 3_000_000_000,
+            drift_ppm: 0.0,
+        };
+        let mut sync = ClockSync::new();
+
+        let mut now = 0;
+        for _ in 0..8 {
+            now = exchange(&mut sync, &remote, &mut rng, now + 1_000_000, 1_000, 6_000);
+        }
+        // Each exchange alone can be off by 2.5ms.
+        let error = error_us(&sync, &remote, now);
+        assert!(error.abs() < 1_000, "seed {seed}: off by {error}us");
+    }
+}
+
+#[test]
+fn estimates_drift_despite_jitter() {
+    for seed in 1..20 {
+        let mut rng = Rng(seed * 104729);
+        let remote = Remote {
+            offset_us: -500_000,
+            drift_ppm: 40.0,
+        };
+        let mut sync = ClockSync::new();
+
+        let mut now = 1_000_000;
+        for _ in 0..24 {
+            let at = now + SYNC_INTERVAL_MS * 1000;
+            now = exchange(&mut sync, &remote, &mut rng, at, 1_000, 3_000);
+        }
+        let drift = sync.drift_ppm().unwrap();
+        assert!((drift - 40.0).abs() < 15.0, "seed {seed}: {drift} ppm");
+
+        // Still in step right before the next exchange would have happened.
+        let error = error_us(&sync, &remote, now + SYNC_INTERVAL_MS * 1000);
+        assert!(error.abs() < 1_000, "seed {seed}: off by {error}us");
+    }
+}
+
+#[test]
+fn starts_over_when_the_remote_reboots() {
+    let mut rng = Rng(42);
+    let mut sync = ClockSync::new();
+
+    let before = Remote {
+        offset_us: 60_000_000,
+        drift_ppm: 20.0,
+    };
+    let mut now = 0;
+    for _ in 0..8 {
+        now = exchange(&mut sync, &before, &mut rng, now + 5_000_000, 1_000, 3_000);
+    }
+
+    // Its clock starts from zero again, well behind ours.
+    let after = Remote {
+        offset_us: -(now as i64),
+        drift_ppm: 20.0,
+    };
+    now = exchange(&mut sync, &after, &mut rng, now + 5_000_000, 1_000, 3_000);
+    let error = error_us(&sync, &after, now);
+    assert!(error.abs() < 2_000, "off by {error}us");
+}
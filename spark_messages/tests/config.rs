@@ -0,0 +1,145 @@
+use spark_messages::{
+    Config, ConfigEntry, ConfigError, ConfigKey, ConfigType, ConfigValue, MAX_FRAME_LEN, Message,
+    MessageType,
+};
+
+#[test]
+fn defaults_to_what_firmware_used_to_hardcode() {
+    let config = Config::default();
+    assert_eq!(
+        config,
+        Config {
+            animation_timeout_ms: 3_000,
+            brightness: 25,
+            channel: 11,
+            led_count: 8,
+            relay_hops: 0,
+        }
+    );
+    for key in ConfigKey::ALL {
+        assert_eq!(config.get(key).value, key.info().default);
+    }
+}
+
+#[test]
+fn sets_valid_values() {
+    let mut config = Config::default();
+    let entries = [
+        (ConfigKey::AnimationTimeoutMs, ConfigValue::U32(600_000)),
+        (ConfigKey::Brightness, ConfigValue::U8(1)),
+        (ConfigKey::LedCount, ConfigValue::U16(300)),
+        (ConfigKey::RelayHops, ConfigValue::U8(3)),
+    ];
+    for (key, value) in entries {
+        let entry = ConfigEntry { key, value };
+        assert_eq!(config.set(&entry), Ok(entry));
+        assert_eq!(config.get(key), entry);
+    }
+    assert_eq!(
+        config,
+        Config {
+            animation_timeout_ms: 600_000,
+            brightness: 1,
+            channel: 11,
+            led_count: 300,
+            relay_hops: 3,
+        }
+    );
+}
+
+#[test]
+fn rejects_values_of_the_wrong_type() {
+    let mut config = Config::default();
+    let entry = ConfigEntry {
+        key: ConfigKey::AnimationTimeoutMs,
+        value: ConfigValue::U8(200),
+    };
+    assert_eq!(
+        config.set(&entry),
+        Err(ConfigError::WrongType {
+            key: ConfigKey::AnimationTimeoutMs,
+            expected: ConfigType::U32,
+        })
+    );
+    assert_eq!(config, Config::default());
+}
+
+#[test]
+fn rejects_values_out_of_range() {
+    let mut config = Config::default();
+    for value in [99, 600_001] {
+        let entry = ConfigEntry {
+            key: ConfigKey::AnimationTimeoutMs,
+            value: ConfigValue::U32(value),
+        };
+        assert_eq!(
+            config.set(&entry),
+            Err(ConfigError::OutOfRange {
+                key: ConfigKey::AnimationTimeoutMs,
+                min: ConfigValue::U32(100),
+                max: ConfigValue::U32(600_000),
+            })
+        );
+    }
+    assert_eq!(config, Config::default());
+
+    // Every default is in range, and the ends of the range too.
+    for key in ConfigKey::ALL {
+        let info = key.info();
+        assert_eq!(info.key, key);
+        for value in [info.min, info.default, info.max] {
+            assert!(key.validate(value).is_ok(), "{key:?}: {value:?}");
+        }
+    }
+}
+
+#[test]
+fn refuses_read_only_settings() {
+    let mut config = Config::default();
+    assert!(!ConfigKey::Channel.info().settable);
+    assert!(ConfigKey::Channel.validate(ConfigValue::U8(1)).is_ok());
+    let entry = ConfigEntry {
+        key: ConfigKey::Channel,
+        value: ConfigValue::U8(1),
+    };
+    assert_eq!(
+        config.set(&entry),
+        Err(ConfigError::ReadOnly {
+            key: ConfigKey::Channel
+        })
+    );
+    assert_eq!(config, Config::default());
+}
+
+#[test]
+fn messages_round_trip() {
+    let messages = [
+        MessageType::ConfigGet(ConfigKey::LedCount),
+        MessageType::ConfigSet(ConfigEntry {
+            key: ConfigKey::AnimationTimeoutMs,
+            value: ConfigValue::U32(u32::MAX),
+        }),
+        MessageType::ConfigList,
+        MessageType::ConfigReply(Ok(ConfigEntry {
+            key: ConfigKey::Brightness,
+            value: ConfigValue::U8(200),
+        })),
+        MessageType::ConfigReply(Err(ConfigError::OutOfRange {
+            key: ConfigKey::AnimationTimeoutMs,
+            min: ConfigValue::U32(100),
+            max: ConfigValue::U32(600_000),
+        })),
+        MessageType::ConfigReply(Err(ConfigError::ReadOnly {
+            key: ConfigKey::Channel,
+        })),
+    ];
+
+    for message_type in messages {
+        let message = Message::new(message_type, u32::MAX);
+        assert_eq!(message.protocol_version, 1);
+
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let frame = postcard::to_slice(&message, &mut buf).unwrap();
+        assert_eq!(Message::decode(frame).unwrap(), message);
+    }
+}
@@ -0,0 +1,149 @@
+use spark_messages::{
+    COUNTER_RESERVATION, DEDUP_WINDOW_MS, DedupCache, MAX_FRAME_LEN, MacAddress, Message,
+    MessageIds, MessageType, NO_MESSAGE_ID, SendCounter,
+};
+
+const REMOTE: MacAddress = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+const OTHER_REMOTE: MacAddress = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBD];
+const THIRD_REMOTE: MacAddress = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBE];
+
+#[test]
+fn drops_repeats() {
+    let mut cache = DedupCache::<2, 4>::new(DEDUP_WINDOW_MS);
+    assert!(cache.accept(0, REMOTE, 1));
+    assert!(cache.accept(10, REMOTE, 2));
+    assert!(!cache.accept(20, REMOTE, 1));
+    assert!(!cache.accept(30, REMOTE, 2));
+
+    // IDs are per sender.
+    assert!(cache.accept(40, OTHER_REMOTE, 1));
+    assert!(!cache.accept(50, OTHER_REMOTE, 1));
+}
+
+#[test]
+fn never_drops_messages_without_an_id() {
+    let mut cache = DedupCache::<2, 4>::new(DEDUP_WINDOW_MS);
+    for now in 0..10 {
+        assert!(cache.accept(now, REMOTE, NO_MESSAGE_ID));
+    }
+}
+
+#[test]
+fn forgets_ids_after_the_window() {
+    let mut cache = DedupCache::<2, 4>::new(1_000);
+    assert!(cache.accept(0, REMOTE, 7));
+    assert!(!cache.accept(999, REMOTE, 7));
+    // The window counts from the first time the ID was seen, repeats don't extend it.
+    assert!(cache.accept(1_000, REMOTE, 7));
+    assert!(!cache.accept(1_500, REMOTE, 7));
+}
+
+#[test]
+fn evicts_the_oldest_id_when_full() {
+    let mut cache = DedupCache::<2, 4>::new(DEDUP_WINDOW_MS);
+    for id in 1..=4 {
+        assert!(cache.accept(id as u64, REMOTE, id));
+    }
+    assert!(cache.accept(5, REMOTE, 5));
+
+    // 1 made room for 5, the others are still remembered.
+    for id in 2..=5 {
+        assert!(!cache.accept(10, REMOTE, id), "{id}");
+    }
+    assert!(cache.accept(10, REMOTE, 1));
+}
+
+#[test]
+fn evicts_the_sender_heard_from_least_recently() {
+    let mut cache = DedupCache::<2, 4>::new(DEDUP_WINDOW_MS);
+    assert!(cache.accept(0, REMOTE, 1));
+    assert!(cache.accept(10, OTHER_REMOTE, 1));
+    assert!(cache.accept(20, REMOTE, 2));
+
+    // OTHER_REMOTE makes room for THIRD_REMOTE, REMOTE was heard from since.
+    assert!(cache.accept(30, THIRD_REMOTE, 1));
+    assert!(!cache.accept(40, REMOTE, 1));
+    assert!(!cache.accept(50, THIRD_REMOTE, 1));
+    assert!(cache.accept(60, OTHER_REMOTE, 1));
+}
+
+#[test]
+fn ids_wrap_around_without_the_reserved_id() {
+    let mut ids = MessageIds::starting_at(u16::MAX - 1);
+    assert_eq!(ids.next_id(), u16::MAX - 1);
+    assert_eq!(ids.next_id(), u16::MAX);
+    assert_eq!(ids.next_id(), 1);
+    assert_eq!(ids.next_id(), 2);
+
+    assert_eq!(MessageIds::starting_at(NO_MESSAGE_ID).next_id(), 1);
+    assert_eq!(MessageIds::default().next_id(), 1);
+}
+
+#[test]
+fn resumes_ids_from_the_counter_reservation() {
+    assert_eq!(MessageIds::resume(0).next_id(), 2);
+    assert_eq!(MessageIds::resume(u16::MAX as u32 - 2).next_id(), u16::MAX);
+    // Past the end of the ID space, and of the counters.
+    assert_eq!(MessageIds::resume(u16::MAX as u32 - 1).next_id(), 1);
+    assert_eq!(MessageIds::resume(u32::MAX).next_id(), 1);
+}
+
+#[test]
+fn resumed_ids_dont_repeat_ones_sent_before_a_boot() {
+    // Sending across the end of the ID space, then booting.
+    let mut counter = SendCounter::resume(u16::MAX as u32 - 10);
+    let mut ids = MessageIds::resume(u16::MAX as u32 - 10);
+    let mut reserved = u16::MAX as u32 - 10;
+    let mut sent = Vec::new();
+    for _ in 0..COUNTER_RESERVATION * 2 {
+        if let (_, Some(reservation)) = counter.next_counter() {
+            reserved = reservation;
+        }
+        sent.push(ids.next_id());
+    }
+
+    let mut ids = MessageIds::resume(reserved);
+    for _ in 0..COUNTER_RESERVATION {
+        assert!(!sent.contains(&ids.next_id()));
+    }
+}
+
+#[test]
+fn accepts_wrapped_ids_once_forgotten() {
+    let mut cache = DedupCache::<1, 4>::new(1_000);
+    let mut ids = MessageIds::starting_at(u16::MAX);
+    let first = ids.next_id();
+    assert!(cache.accept(0, REMOTE, first));
+
+    // A full cycle of IDs later, the first one comes around again.
+    let mut ids = MessageIds::starting_at(first.wrapping_add(1));
+    let mut now = 0;
+    let reused = loop {
+        now += 1;
+        let id = ids.next_id();
+        if id == first {
+            break id;
+        }
+    };
+    assert!(now >= 1_000);
+    assert!(cache.accept(now, REMOTE, reused));
+
+    // Had it come around within the window, it would have been dropped.
+    assert!(!cache.accept(now + 999, REMOTE, reused));
+}
+
+#[test]
+fn messages_round_trip() {
+    let message = Message::new(MessageType::StatusRequest, u32::MAX).with_id(u16::MAX);
+    assert_eq!(message.protocol_version, 1);
+
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = postcard::to_slice(&message, &mut buf).unwrap().len();
+    assert_eq!(Message::decode(&buf[..len]).unwrap(), message);
+
+    // Frames from senders predating message IDs end before it, or with zero padding.
+    let message = message.with_id(NO_MESSAGE_ID);
+    let len = postcard::to_slice(&message, &mut buf).unwrap().len();
+    assert_eq!(Message::decode(&buf[..len - 1]).unwrap(), message);
+    assert_eq!(Message::decode(&buf).unwrap(), message);
+}
@@ -0,0 +1,254 @@
+use spark_messages::{
+    DeliveryEvent, MacAddress, ReplayWindow, Retransmitter, RetryPolicy, SendCounter,
+};
+
+const LIGHT: MacAddress = [0x11, 0, 0, 0, 0, 1];
+const OTHER_LIGHT: MacAddress = [0x22, 0, 0, 0, 0, 2];
+
+#[test]
+fn backs_off_then_gives_up() {
+    let policy = RetryPolicy {
+        max_retries: 3,
+        initial_backoff_ms: 10,
+        max_backoff_ms: 25,
+    };
+    let mut retransmitter = Retransmitter::<&str, 4>::new(policy);
+    retransmitter.sent(0, LIGHT, 7, "press").unwrap();
+
+    let mut retransmitted_at = Vec::new();
+    let mut gave_up_at = None;
+    for now in 0..100 {
+        match retransmitter.poll(now) {
+            Some(DeliveryEvent::Retransmit {
+                dst,
+                counter,
+                payload,
+            }) => {
+                assert_eq!((dst, counter, payload), (LIGHT, 7, "press"));
+                retransmitted_at.push(now);
+            }
+            Some(DeliveryEvent::GaveUp { counter, .. }) => {
+                assert_eq!(counter, 7);
+                gave_up_at = Some(now);
+            }
+            None => {}
+        }
+    }
+
+    assert_eq!(retransmitted_at, [10, 30, 55]);
+    assert_eq!(gave_up_at, Some(80));
+    assert!(retransmitter.is_empty());
+}
+
+#[test]
+fn stops_once_acknowledged() {
+    let mut retransmitter = Retransmitter::<u8, 4>::new(RetryPolicy::default());
+    retransmitter.sent(0, LIGHT, 1, 10).unwrap();
+    retransmitter.sent(0, OTHER_LIGHT, 2, 20).unwrap();
+    assert_eq!(retransmitter.next_due_at(), Some(40));
+
+    // Acknowledgements only count from the light the message went to.
+    assert_eq!(retransmitter.on_ack(LIGHT, 2), None);
+    assert_eq!(retransmitter.on_ack(OTHER_LIGHT, 2), Some(20));
+    assert_eq!(retransmitter.on_ack(OTHER_LIGHT, 2), None);
+
+    assert!(matches!(
+        retransmitter.poll(40),
+        Some(DeliveryEvent::Retransmit { counter: 1, .. })
+    ));
+    assert_eq!(retransmitter.on_ack(LIGHT, 1), Some(10));
+    assert!(retransmitter.is_empty());
+    assert_eq!(retransmitter.next_due_at(), None);
+    assert_eq!(retransmitter.poll(1000), None);
+}
+
+#[test]
+fn bounded() {
+    let mut retransmitter = Retransmitter::<u8, 2>::new(RetryPolicy::default());
+    retransmitter.sent(0, LIGHT, 1, 1).unwrap();
+    retransmitter.sent(0, LIGHT, 2, 2).unwrap();
+    assert_eq!(retransmitter.sent(0, LIGHT, 3, 3), Err(3));
+}
+
+/// Deterministic xorshift, so failures can be reproduced.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Frame {
+    Press { counter: u32, id: u32 },
+    Ack { counter: u32 },
+}
+
+/// One direction of a radio link that drops, duplicates, delays and thereby reorders frames.
+struct LossyChannel {
+    in_flight: Vec<(u64, Frame)>,
+    drop_percent: u64,
+    duplicate_percent: u64,
+}
+
+impl LossyChannel {
+    fn send(&mut self, rng: &mut Rng, now: u64, frame: Frame) {
+        if rng.chance(self.drop_percent) {
+            return;
+        }
+        let copies = if rng.chance(self.duplicate_percent) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            self.in_flight.push((now + 1 + rng.next() % 30, frame));
+        }
+    }
+
+    fn receive(&mut self, now: u64) -> Vec<Frame> {
+        let (arrived, in_flight) = self.in_flight.iter().partition(|(at, _)| *at <= now);
+        self.in_flight = in_flight;
+        arrived.into_iter().map(|(_, frame)| frame).collect()
+    }
+}
+
+struct Outcome {
+    acted_on: Vec<u32>,
+    acknowledged: Vec<u32>,
+    given_up: Vec<u32>,
+}
+
+fn simulate(seed: u64, presses: u32, drop_percent: u64, duplicate_percent: u64) -> Outcome {
+    let mut rng = Rng(seed);
+    let mut to_light = LossyChannel {
+        in_flight: Vec::new(),
+        drop_percent,
+        duplicate_percent,
+    };
+    let mut to_remote = LossyChannel {
+        in_flight: Vec::new(),
+        drop_percent,
+        duplicate_percent,
+    };
+
+    let mut counter = SendCounter::resume(0);
+    let mut retransmitter = Retransmitter::<u32, 32>::new(RetryPolicy::default());
+    let mut window = ReplayWindow::starting_at(0);
+
+    let mut outcome = Outcome {
+        acted_on: Vec::new(),
+        acknowledged: Vec::new(),
+        given_up: Vec::new(),
+    };
+
+    for now in 0..(presses as u64 * 50 + 5_000) {
+        // The remote, pressed every 50ms.
+        if now % 50 == 0 && now / 50 < presses as u64 {
+            let id = (now / 50) as u32;
+            let (counter, _) = counter.next_counter();
+            retransmitter.sent(now, LIGHT, counter, id).unwrap();
+            to_light.send(&mut rng, now, Frame::Press { counter, id });
+        }
+        for frame in to_remote.receive(now) {
+            let Frame::Ack { counter } = frame else {
+                continue;
+            };
+            if let Some(id) = retransmitter.on_ack(LIGHT, counter) {
+                outcome.acknowledged.push(id);
+            }
+        }
+        while let Some(event) = retransmitter.poll(now) {
+            match event {
+                DeliveryEvent::Retransmit {
+                    counter, payload, ..
+                } => to_light.send(
+                    &mut rng,
+                    now,
+                    Frame::Press {
+                        counter,
+                        id: payload,
+                    },
+                ),
+                DeliveryEvent::GaveUp { payload, .. } => outcome.given_up.push(payload),
+            }
+        }
+
+        // The light.
+        for frame in to_light.receive(now) {
+            if let Frame::Press { counter, id } = frame {
+                to_remote.send(&mut rng, now, Frame::Ack { counter });
+                if window.accept(counter) {
+                    outcome.acted_on.push(id);
+                }
+            }
+        }
+    }
+
+    assert!(retransmitter.is_empty());
+    outcome
+}
+
+fn assert_consistent(outcome: &Outcome, presses: u32) {
+    let mut acted_on = outcome.acted_on.clone();
+    acted_on.sort();
+    acted_on.dedup();
+    assert_eq!(
+        acted_on.len(),
+        outcome.acted_on.len(),
+        "acted on a press twice"
+    );
+
+    for id in &outcome.acknowledged {
+        assert!(
+            acted_on.contains(id),
+            "press {id} acknowledged but not acted on"
+        );
+    }
+    for id in 0..presses {
+        let acknowledged = outcome.acknowledged.contains(&id);
+        let given_up = outcome.given_up.contains(&id);
+        assert!(
+            acknowledged != given_up,
+            "press {id} neither acknowledged nor given up"
+        );
+    }
+}
+
+#[test]
+fn delivers_every_press_once_over_a_perfect_link() {
+    let outcome = simulate(1, 100, 0, 0);
+    assert_consistent(&outcome, 100);
+    assert_eq!(outcome.acted_on, (0..100).collect::<Vec<_>>());
+    assert!(outcome.given_up.is_empty());
+}
+
+#[test]
+fn delivers_every_press_once_over_a_lossy_link() {
+    for seed in 1..20 {
+        let outcome = simulate(seed * 7919, 200, 20, 10);
+        assert_consistent(&outcome, 200);
+        // Each attempt gets through both ways 64% of the time, so giving up is rare.
+        assert!(
+            outcome.given_up.len() <= 4,
+            "seed {seed}: {:?}",
+            outcome.given_up
+        );
+    }
+}
+
+#[test]
+fn survives_a_terrible_link() {
+    for seed in 1..20 {
+        let outcome = simulate(seed * 104729, 200, 60, 30);
+        assert_consistent(&outcome, 200);
+    }
+}
@@ -0,0 +1,75 @@
+use spark_messages::{
+    Announce, FirmwareVersion, Inventory, MAX_FRAME_LEN, MacAddress, Message, MessageType,
+    PROTOCOL_VERSION, Pattern, PatternSet, ProtocolVersions,
+};
+
+const LIGHT: MacAddress = [0x11, 0, 0, 0, 0, 1];
+const OTHER_LIGHT: MacAddress = [0x22, 0, 0, 0, 0, 2];
+
+fn announce(id: u16, leds: u16) -> Announce {
+    Announce {
+        id,
+        firmware_version: FirmwareVersion::parse("1.2.3"),
+        protocol_versions: ProtocolVersions::SUPPORTED,
+        strips: heapless::Vec::from_slice(&[leds; 4]).unwrap(),
+        patterns: [Pattern::Solid, Pattern::Rainbow].into_iter().collect(),
+    }
+}
+
+#[test]
+fn announce_round_trips() {
+    let mut announce = announce(u16::MAX, u16::MAX);
+    announce
+        .strips
+        .resize(announce.strips.capacity(), u16::MAX)
+        .unwrap();
+    let message = Message::new(MessageType::Announce(announce), u32::MAX);
+    assert_eq!(message.protocol_version, 1);
+
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let frame = postcard::to_slice(&message, &mut buf).unwrap();
+    assert_eq!(Message::decode(frame).unwrap(), message);
+}
+
+#[test]
+fn supports_its_own_revision() {
+    let supported = ProtocolVersions::SUPPORTED;
+    assert!(supported.contains(0));
+    assert!(supported.contains(PROTOCOL_VERSION));
+    assert!(!supported.contains(PROTOCOL_VERSION + 1));
+}
+
+#[test]
+fn collects_patterns() {
+    let patterns: PatternSet = [Pattern::Off, Pattern::Blink].into_iter().collect();
+    assert!(patterns.contains(Pattern::Off));
+    assert!(patterns.contains(Pattern::Blink));
+    assert!(!patterns.contains(Pattern::Solid));
+    assert!(!PatternSet::new().contains(Pattern::Off));
+}
+
+#[test]
+fn keeps_the_latest_announcement() {
+    let mut inventory = Inventory::<4>::new();
+    assert!(inventory.is_empty());
+
+    assert!(inventory.on_announce(LIGHT, &announce(1, 8)));
+    assert!(inventory.on_announce(OTHER_LIGHT, &announce(2, 8)));
+    // Reconfigured since.
+    assert!(!inventory.on_announce(LIGHT, &announce(1, 30)));
+
+    assert_eq!(inventory.len(), 2);
+    assert_eq!(inventory.get(LIGHT), Some(&announce(1, 30)));
+    assert_eq!(inventory.get(OTHER_LIGHT), Some(&announce(2, 8)));
+    let ids: Vec<_> = inventory.iter().map(|(_, announce)| announce.id).collect();
+    assert_eq!(ids, [1, 2]);
+}
+
+#[test]
+fn leaves_out_lights_once_full() {
+    let mut inventory = Inventory::<1>::new();
+    assert!(inventory.on_announce(LIGHT, &announce(1, 8)));
+    assert!(!inventory.on_announce(OTHER_LIGHT, &announce(2, 8)));
+    assert_eq!(inventory.get(OTHER_LIGHT), None);
+    assert_eq!(inventory.len(), 1);
+}
@@ -0,0 +1,320 @@
+use spark_messages::{
+    ConfigEntry, ConfigKey, ConfigValue, DecodeError, Destination, FRAGMENT_LEN, Fragment,
+    FragmentError, Key, MAX_FRAGMENTS, MAX_FRAME_LEN, MacAddress, Message, MessageType,
+    REASSEMBLY_TIMEOUT_MS, Reassembler, TAG_LEN, fragment, parse_key, sign, verify,
+};
+
+const REMOTE: MacAddress = [0x11, 0, 0, 0, 0, 1];
+const OTHER_REMOTE: MacAddress = [0x22, 0, 0, 0, 0, 2];
+
+const KEY: Key = parse_key("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + 3) as u8).collect()
+}
+
+fn fragments(transfer: u16, payload: &[u8]) -> Vec<Fragment> {
+    fragment(transfer, payload).unwrap().collect()
+}
+
+/// Feed `fragments` in the given order, returning the payload once complete.
+fn reassemble<const T: usize>(
+    reassembler: &mut Reassembler<1024, T>,
+    fragments: &[Fragment],
+    order: &[usize],
+) -> Option<Vec<u8>> {
+    let mut complete = None;
+    for &i in order {
+        if let Some(payload) = reassembler.on_fragment(0, REMOTE, &fragments[i]).unwrap() {
+            assert!(complete.is_none(), "completed twice");
+            complete = Some(payload.to_vec());
+        }
+    }
+    complete
+}
+
+#[test]
+fn splits_into_full_fragments() {
+    let fragments = fragments(9, &payload(2 * FRAGMENT_LEN + 1));
+
+    assert_eq!(fragments.len(), 3);
+    for (i, fragment) in fragments.iter().enumerate() {
+        assert_eq!(
+            (fragment.transfer, fragment.index, fragment.count),
+            (9, i as u8, 3)
+        );
+    }
+    assert_eq!(fragments[0].data.len(), FRAGMENT_LEN);
+    assert_eq!(fragments[2].data.len(), 1);
+
+    assert_eq!(fragments_for(0), 1);
+    assert_eq!(fragments_for(FRAGMENT_LEN), 1);
+    assert_eq!(fragments_for(FRAGMENT_LEN + 1), 2);
+    assert!(matches!(
+        fragment(0, &payload(MAX_FRAGMENTS * FRAGMENT_LEN + 1)),
+        Err(FragmentError::TooLarge)
+    ));
+}
+
+fn fragments_for(len: usize) -> usize {
+    fragment(0, &payload(len)).unwrap().count()
+}
+
+#[test]
+fn full_fragment_fits_in_a_frame() {
+    let fragment = Fragment {
+        transfer: u16::MAX,
+        index: MAX_FRAGMENTS as u8 - 1,
+        count: MAX_FRAGMENTS as u8,
+        data: heapless::Vec::from_slice(&payload(FRAGMENT_LEN)).unwrap(),
+    };
+    let message = Message::new(MessageType::Fragment(fragment), u32::MAX);
+
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = postcard::to_slice(&message, &mut buf).unwrap().len();
+    let frame = sign(&KEY, &REMOTE, &mut buf, len).unwrap();
+    assert!(frame.len() <= MAX_FRAME_LEN);
+    assert_eq!(frame.len(), len + TAG_LEN);
+
+    let decoded = Message::decode(verify(&KEY, &REMOTE, frame).unwrap()).unwrap();
+    assert_eq!(decoded, message);
+}
+
+#[test]
+fn reassembles_in_order() {
+    let payload = payload(700);
+    let fragments = fragments(1, &payload);
+    let mut reassembler = Reassembler::<1024, 2>::new();
+
+    assert_eq!(
+        reassemble(&mut reassembler, &fragments, &[0, 1, 2, 3]),
+        Some(payload)
+    );
+}
+
+#[test]
+fn reassembles_out_of_order() {
+    let payload = payload(700);
+    let fragments = fragments(1, &payload);
+    let mut reassembler = Reassembler::<1024, 2>::new();
+
+    assert_eq!(
+        reassemble(&mut reassembler, &fragments, &[3, 1, 0, 2]),
+        Some(payload)
+    );
+}
+
+#[test]
+fn ignores_duplicates() {
+    let payload = payload(700);
+    let fragments = fragments(1, &payload);
+    let mut reassembler = Reassembler::<1024, 2>::new();
+
+    assert_eq!(
+        reassemble(&mut reassembler, &fragments, &[2, 2, 0, 3, 0, 1, 1, 3, 2]),
+        Some(payload)
+    );
+}
+
+#[test]
+fn empty_payload() {
+    let fragments = fragments(1, &[]);
+    let mut reassembler = Reassembler::<1024, 2>::new();
+
+    assert_eq!(reassemble(&mut reassembler, &fragments, &[0]), Some(vec![]));
+}
+
+#[test]
+fn waits_for_missing_fragments() {
+    let payload = payload(700);
+    let fragments = fragments(1, &payload);
+    let mut reassembler = Reassembler::<1024, 2>::new();
+
+    assert_eq!(reassemble(&mut reassembler, &fragments, &[0, 1, 3]), None);
+    assert_eq!(
+        reassemble(&mut reassembler, &fragments, &[2]),
+        Some(payload)
+    );
+}
+
+#[test]
+fn drops_incomplete_transfers_after_timeout() {
+    let payload = payload(700);
+    let fragments = fragments(1, &payload);
+    let mut reassembler = Reassembler::<1024, 1>::new();
+
+    for fragment in &fragments[..3] {
+        assert_eq!(reassembler.on_fragment(0, REMOTE, fragment), Ok(None));
+    }
+    // Too late, the transfer starts over and needs every fragment again.
+    let late = REASSEMBLY_TIMEOUT_MS;
+    assert_eq!(
+        reassembler.on_fragment(late, REMOTE, &fragments[3]),
+        Ok(None)
+    );
+    for fragment in &fragments[..2] {
+        assert_eq!(reassembler.on_fragment(late, REMOTE, fragment), Ok(None));
+    }
+    assert_eq!(
+        reassembler.on_fragment(late, REMOTE, &fragments[2]),
+        Ok(Some(&payload[..]))
+    );
+}
+
+#[test]
+fn keeps_transfers_apart() {
+    let first = payload(300);
+    let second: Vec<u8> = payload(500).into_iter().rev().collect();
+    let first_fragments = fragments(1, &first);
+    let second_fragments = fragments(1, &second);
+    let mut reassembler = Reassembler::<1024, 2>::new();
+
+    // Same transfer number, different senders.
+    assert_eq!(
+        reassembler.on_fragment(0, REMOTE, &first_fragments[0]),
+        Ok(None)
+    );
+    assert_eq!(
+        reassembler.on_fragment(0, OTHER_REMOTE, &second_fragments[1]),
+        Ok(None)
+    );
+    assert_eq!(
+        reassembler.on_fragment(0, OTHER_REMOTE, &second_fragments[0]),
+        Ok(None)
+    );
+    assert_eq!(
+        reassembler.on_fragment(0, REMOTE, &first_fragments[1]),
+        Ok(Some(&first[..]))
+    );
+    assert_eq!(
+        reassembler.on_fragment(0, OTHER_REMOTE, &second_fragments[2]),
+        Ok(Some(&second[..]))
+    );
+}
+
+#[test]
+fn bounded() {
+    let payload = payload(700);
+    let mut reassembler = Reassembler::<1024, 2>::new();
+
+    let mut first = fragment(1, &payload).unwrap();
+    let mut second = fragment(2, &payload).unwrap();
+    let mut third = fragment(3, &payload).unwrap();
+    let first = first.next().unwrap();
+    assert_eq!(reassembler.on_fragment(0, REMOTE, &first), Ok(None));
+    assert_eq!(
+        reassembler.on_fragment(0, REMOTE, &second.next().unwrap()),
+        Ok(None)
+    );
+    assert_eq!(
+        reassembler.on_fragment(0, REMOTE, &third.next().unwrap()),
+        Err(FragmentError::Busy)
+    );
+    // Once a transfer times out, its slot is free again.
+    assert_eq!(
+        reassembler.on_fragment(REASSEMBLY_TIMEOUT_MS, REMOTE, &third.next().unwrap()),
+        Ok(None)
+    );
+
+    let mut small = Reassembler::<300, 1>::new();
+    let fragments = fragments(4, &payload);
+    assert_eq!(
+        small.on_fragment(0, REMOTE, &fragments[2]),
+        Err(FragmentError::TooLarge)
+    );
+}
+
+#[test]
+fn complete_transfers_make_room() {
+    let payload = payload(300);
+    let mut reassembler = Reassembler::<1024, 1>::new();
+
+    let first = fragments(1, &payload);
+    let second = fragments(2, &payload);
+    assert_eq!(
+        reassemble(&mut reassembler, &first, &[0, 1]),
+        Some(payload.clone())
+    );
+    assert_eq!(
+        reassemble(&mut reassembler, &second, &[1, 0]),
+        Some(payload)
+    );
+}
+
+#[test]
+fn rejects_malformed_fragments() {
+    let payload = payload(700);
+    let fragments = fragments(1, &payload);
+    let mut reassembler = Reassembler::<1024, 2>::new();
+
+    let mut out_of_range = fragments[3].clone();
+    out_of_range.index = 4;
+    assert_eq!(
+        reassembler.on_fragment(0, REMOTE, &out_of_range),
+        Err(FragmentError::Malformed)
+    );
+
+    let mut short = fragments[1].clone();
+    short.data.pop();
+    assert_eq!(
+        reassembler.on_fragment(0, REMOTE, &short),
+        Err(FragmentError::Malformed)
+    );
+
+    assert_eq!(reassembler.on_fragment(0, REMOTE, &fragments[0]), Ok(None));
+    let mut recounted = fragments[1].clone();
+    recounted.count = 5;
+    assert_eq!(
+        reassembler.on_fragment(0, REMOTE, &recounted),
+        Err(FragmentError::Malformed)
+    );
+}
+
+#[test]
+fn reassembles_messages() {
+    let config_set = MessageType::ConfigSet(ConfigEntry {
+        key: ConfigKey::RelayHops,
+        value: ConfigValue::U8(2),
+    });
+    let mut buf = [0u8; 64];
+    let payload = postcard::to_slice(&config_set, &mut buf).unwrap();
+    let fragments = fragments(4, payload);
+    let mut reassembler = Reassembler::<1024, 2>::new();
+    let payload = reassemble(&mut reassembler, &fragments, &[0]).unwrap();
+
+    // The last fragment to arrive addresses the message, and gets it answered.
+    let last = Message::new(MessageType::Fragment(fragments[0].clone()), 9)
+        .with_destination(Destination::Device(3))
+        .with_id(5);
+    let message = last.reassembled(&payload).unwrap();
+    assert_eq!(message.message_type, config_set);
+    assert_eq!(message.counter, 9);
+    assert_eq!(message.destination, Destination::Device(3));
+    assert_eq!(message.id, 5);
+}
+
+#[test]
+fn checks_reassembled_messages() {
+    let last = Message::new(MessageType::Fragment(fragments(4, &[])[0].clone()), 9);
+    assert_eq!(
+        last.reassembled(&[0xFF, 0x01]),
+        Err(DecodeError::UnknownType {
+            protocol_version: 1
+        })
+    );
+
+    let mut buf = [0u8; 8];
+    let status_request = postcard::to_slice(&MessageType::StatusRequest, &mut buf).unwrap();
+    assert!(last.reassembled(status_request).is_ok());
+    assert_eq!(last.reassembled(&[]), Err(DecodeError::Truncated));
+    let stamped_too_old = Message {
+        protocol_version: 0,
+        ..last
+    };
+    assert_eq!(
+        stamped_too_old.reassembled(status_request),
+        Err(DecodeError::BadVersion {
+            protocol_version: 0
+        })
+    );
+}
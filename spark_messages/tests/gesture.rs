@@ -0,0 +1,213 @@
+use spark_messages::{
+    ButtonNumber, ButtonSequence, ButtonSet, Gesture, GestureDetector, MAX_FRAME_LEN,
+    MAX_SEQUENCE_LEN, Message, MessageType,
+};
+
+use ButtonNumber::{Button1, Button2, Button3, Button4};
+
+const WINDOW_MS: u64 = 1_000;
+
+fn sequence(buttons: &[ButtonNumber]) -> Gesture {
+    Gesture::Sequence(ButtonSequence {
+        buttons: heapless::Vec::from_slice(buttons).unwrap(),
+    })
+}
+
+/// Press and release `button` 50 ms later, starting at `now`. Returns what the release ended.
+fn tap(detector: &mut GestureDetector, now: u64, button: ButtonNumber) -> Option<Gesture> {
+    assert_eq!(detector.on_press(now, button), None);
+    detector.on_release(now + 50, button)
+}
+
+#[test]
+fn single_taps_are_not_gestures() {
+    let mut detector = GestureDetector::new(WINDOW_MS);
+    assert_eq!(tap(&mut detector, 0, Button1), None);
+    assert_eq!(detector.next_due_at(), Some(WINDOW_MS));
+    assert_eq!(detector.poll(WINDOW_MS), None);
+    assert_eq!(detector.next_due_at(), None);
+}
+
+#[test]
+fn detects_chords_once_released() {
+    let mut detector = GestureDetector::new(WINDOW_MS);
+    assert_eq!(detector.on_press(0, Button1), None);
+    assert_eq!(detector.on_press(30, Button3), None);
+    // Pressing twice changes nothing.
+    assert_eq!(detector.on_press(40, Button3), None);
+    assert_eq!(detector.on_release(500, Button1), None);
+    assert_eq!(
+        detector.on_release(520, Button3),
+        Some(Gesture::Chord([Button1, Button3].into_iter().collect()))
+    );
+
+    // Reported once, and not as taps either.
+    assert_eq!(detector.on_release(530, Button3), None);
+    assert_eq!(detector.next_due_at(), None);
+}
+
+#[test]
+fn tells_which_buttons_make_a_chord() {
+    let mut detector = GestureDetector::new(WINDOW_MS);
+    detector.on_press(0, Button4);
+    assert!(detector.chord().is_empty());
+    detector.on_press(30, Button2);
+    let chord: ButtonSet = [Button2, Button4].into_iter().collect();
+    assert_eq!(detector.chord(), chord);
+
+    // Until the next press of a button alone.
+    detector.on_release(500, Button4);
+    detector.on_release(520, Button2);
+    assert_eq!(detector.chord(), chord);
+    detector.on_press(600, Button2);
+    assert!(detector.chord().is_empty());
+}
+
+#[test]
+fn chords_are_the_most_buttons_held_together() {
+    let mut detector = GestureDetector::new(WINDOW_MS);
+    detector.on_press(0, Button1);
+    detector.on_press(10, Button2);
+    detector.on_press(20, Button4);
+    detector.on_release(100, Button2);
+    // Held with Button1 and Button4, but never more than three at once.
+    detector.on_press(110, Button3);
+    detector.on_release(200, Button1);
+    detector.on_release(200, Button3);
+
+    let chord = detector.on_release(210, Button4);
+    assert_eq!(
+        chord,
+        Some(Gesture::Chord(
+            [Button1, Button2, Button4].into_iter().collect()
+        ))
+    );
+    let Some(Gesture::Chord(buttons)) = chord else {
+        unreachable!()
+    };
+    assert_eq!(buttons.len(), 3);
+    assert_eq!(
+        buttons.iter().collect::<Vec<_>>(),
+        [Button1, Button2, Button4]
+    );
+}
+
+#[test]
+fn detects_sequences_once_the_window_is_over() {
+    let mut detector = GestureDetector::new(WINDOW_MS);
+    assert_eq!(tap(&mut detector, 0, Button2), None);
+    assert_eq!(tap(&mut detector, 200, Button2), None);
+    assert_eq!(tap(&mut detector, 400, Button1), None);
+    assert_eq!(detector.next_due_at(), Some(WINDOW_MS));
+
+    assert_eq!(detector.poll(WINDOW_MS - 1), None);
+    assert_eq!(
+        detector.poll(WINDOW_MS),
+        Some(sequence(&[Button2, Button2, Button1]))
+    );
+    assert_eq!(detector.poll(WINDOW_MS), None);
+    assert_eq!(detector.next_due_at(), None);
+}
+
+#[test]
+fn taps_after_the_window_start_the_next_sequence() {
+    let mut detector = GestureDetector::new(WINDOW_MS);
+    tap(&mut detector, 0, Button1);
+    tap(&mut detector, 500, Button2);
+
+    // Nobody polled in time.
+    assert_eq!(
+        detector.on_press(1_200, Button3),
+        Some(sequence(&[Button1, Button2]))
+    );
+    assert_eq!(detector.on_release(1_250, Button3), None);
+    assert_eq!(detector.next_due_at(), Some(1_200 + WINDOW_MS));
+}
+
+#[test]
+fn taps_pressed_within_the_window_count_when_released_after_it() {
+    let mut detector = GestureDetector::new(WINDOW_MS);
+    tap(&mut detector, 0, Button1);
+    detector.on_press(900, Button4);
+
+    // Still held.
+    assert_eq!(detector.poll(WINDOW_MS), None);
+    assert_eq!(
+        detector.on_release(1_300, Button4),
+        Some(sequence(&[Button1, Button4]))
+    );
+}
+
+#[test]
+fn sequences_end_when_full() {
+    let mut detector = GestureDetector::new(WINDOW_MS);
+    let buttons = [Button1, Button2, Button3, Button4].repeat(2);
+    assert_eq!(buttons.len(), MAX_SEQUENCE_LEN);
+
+    let (last, first) = buttons.split_last().unwrap();
+    for (i, &button) in first.iter().enumerate() {
+        assert_eq!(tap(&mut detector, i as u64 * 100, button), None);
+    }
+    assert_eq!(tap(&mut detector, 700, *last), Some(sequence(&buttons)));
+    assert_eq!(detector.next_due_at(), None);
+}
+
+#[test]
+fn chords_interrupt_sequences() {
+    let mut detector = GestureDetector::new(WINDOW_MS);
+    tap(&mut detector, 0, Button1);
+    tap(&mut detector, 100, Button2);
+
+    assert_eq!(detector.on_press(200, Button3), None);
+    assert_eq!(
+        detector.on_press(220, Button4),
+        Some(sequence(&[Button1, Button2]))
+    );
+    assert_eq!(
+        detector.on_release(400, Button3),
+        None,
+        "Button4 is still held"
+    );
+    assert_eq!(
+        detector.on_release(410, Button4),
+        Some(Gesture::Chord([Button3, Button4].into_iter().collect()))
+    );
+    assert_eq!(detector.next_due_at(), None);
+
+    // A single tap before a chord is dropped.
+    tap(&mut detector, 500, Button1);
+    detector.on_press(600, Button1);
+    assert_eq!(detector.on_press(610, Button2), None);
+    assert_eq!(detector.next_due_at(), None);
+}
+
+#[test]
+fn button_sets() {
+    let mut set = ButtonSet::new();
+    assert!(set.is_empty());
+    set.insert(Button4);
+    set.insert(Button2);
+    set.insert(Button4);
+    assert_eq!(set.len(), 2);
+    assert!(set.contains(Button2) && set.contains(Button4));
+    assert!(!set.contains(Button1));
+    set.remove(Button4);
+    assert_eq!(set.iter().collect::<Vec<_>>(), [Button2]);
+}
+
+#[test]
+fn messages_round_trip() {
+    let messages: [MessageType; 2] = [
+        Gesture::Chord([Button1, Button2, Button3, Button4].into_iter().collect()).into(),
+        sequence(&[Button4; MAX_SEQUENCE_LEN]).into(),
+    ];
+
+    for message_type in messages {
+        let message = Message::new(message_type, u32::MAX);
+        assert_eq!(message.protocol_version, 1);
+
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let frame = postcard::to_slice(&message, &mut buf).unwrap();
+        assert_eq!(Message::decode(frame).unwrap(), message);
+    }
+}
@@ -0,0 +1,132 @@
+use spark_messages::{
+    ButtonEventType, ButtonNumber, HoldRepeater, MAX_FRAME_LEN, Message, MessageType, Ramp,
+};
+
+use ButtonEventType::{HoldRepeat, Pressed, Released};
+use ButtonNumber::{Button1, Button2, Button3};
+
+const DELAY_MS: u64 = 500;
+const INTERVAL_MS: u64 = 100;
+
+fn repeat(elapsed_ms: u32) -> ButtonEventType {
+    HoldRepeat { elapsed_ms }
+}
+
+#[test]
+fn clicks_do_not_repeat() {
+    let mut repeater = HoldRepeater::new(DELAY_MS, INTERVAL_MS);
+    assert_eq!(repeater.on_press(1_000, Button1), Some(Pressed));
+    assert_eq!(repeater.next_due_at(), Some(1_000 + DELAY_MS));
+    assert_eq!(repeater.poll(1_000 + DELAY_MS - 1), None);
+    assert_eq!(repeater.on_release(Button1), Some(Released));
+    assert_eq!(repeater.next_due_at(), None);
+    assert_eq!(repeater.poll(2_000), None);
+}
+
+#[test]
+fn repeats_while_held() {
+    let mut repeater = HoldRepeater::new(DELAY_MS, INTERVAL_MS);
+    repeater.on_press(1_000, Button2);
+
+    for i in 0..5 {
+        let due = 1_000 + DELAY_MS + i * INTERVAL_MS;
+        assert_eq!(repeater.next_due_at(), Some(due));
+        assert_eq!(
+            repeater.poll(due),
+            Some((Button2, repeat((due - 1_000) as u32)))
+        );
+        assert_eq!(repeater.poll(due), None);
+    }
+
+    assert_eq!(repeater.on_release(Button2), Some(Released));
+    assert_eq!(repeater.next_due_at(), None);
+}
+
+#[test]
+fn skips_missed_repeats() {
+    let mut repeater = HoldRepeater::new(DELAY_MS, INTERVAL_MS);
+    repeater.on_press(0, Button1);
+
+    // Polled late, three repeats in: one repeat, reporting the time actually held.
+    assert_eq!(repeater.poll(DELAY_MS + 250), Some((Button1, repeat(750))));
+    assert_eq!(repeater.poll(DELAY_MS + 250), None);
+    assert_eq!(repeater.next_due_at(), Some(DELAY_MS + 300));
+}
+
+#[test]
+fn buttons_repeat_independently() {
+    let mut repeater = HoldRepeater::new(DELAY_MS, INTERVAL_MS);
+    repeater.on_press(0, Button3);
+    repeater.on_press(50, Button1);
+    // Pressing again changes nothing.
+    assert_eq!(repeater.on_press(60, Button3), None);
+
+    assert_eq!(repeater.next_due_at(), Some(DELAY_MS));
+    assert_eq!(repeater.poll(DELAY_MS + 50), Some((Button1, repeat(500))));
+    assert_eq!(repeater.poll(DELAY_MS + 50), Some((Button3, repeat(550))));
+    assert_eq!(repeater.poll(DELAY_MS + 50), None);
+
+    assert_eq!(repeater.on_release(Button3), Some(Released));
+    assert_eq!(repeater.on_release(Button3), None);
+    assert_eq!(repeater.on_release(Button2), None);
+    assert_eq!(repeater.next_due_at(), Some(DELAY_MS + 150));
+}
+
+#[test]
+fn ramps_from_the_first_repeat() {
+    let mut ramp = Ramp::new(200);
+    assert_eq!(ramp.on_event(&Pressed), 0);
+    assert!(!ramp.is_holding());
+
+    assert_eq!(ramp.on_event(&repeat(500)), 0);
+    assert!(ramp.is_holding());
+    // 200 steps per second is 20 per 100 ms.
+    assert_eq!(ramp.on_event(&repeat(600)), 20);
+    assert_eq!(ramp.on_event(&repeat(700)), 20);
+    // Repeats 800 to 1000 got lost.
+    assert_eq!(ramp.on_event(&repeat(1_100)), 80);
+    // And a duplicate arrived.
+    assert_eq!(ramp.on_event(&repeat(1_100)), 0);
+
+    assert_eq!(ramp.on_event(&Released), 0);
+    assert!(!ramp.is_holding());
+}
+
+#[test]
+fn ramps_without_losing_fractions() {
+    let mut ramp = Ramp::new(255);
+    ramp.on_event(&repeat(500));
+    let steps: u32 = (1..=10)
+        .map(|i| ramp.on_event(&repeat(500 + i * 100)))
+        .sum();
+    assert_eq!(steps, 255);
+}
+
+#[test]
+fn ramps_restart_when_a_release_is_lost() {
+    let mut ramp = Ramp::new(1_000);
+    ramp.on_event(&repeat(500));
+    assert_eq!(ramp.on_event(&repeat(2_000)), 1_500);
+
+    // The next hold, with the release in between lost.
+    assert_eq!(ramp.on_event(&repeat(500)), 0);
+    assert_eq!(ramp.on_event(&repeat(600)), 100);
+}
+
+#[test]
+fn messages_round_trip() {
+    let messages =
+        [Pressed, Released, repeat(u32::MAX)].map(|event_type| MessageType::ButtonEvent {
+            button_number: Button1,
+            event_type,
+        });
+
+    for message_type in messages {
+        let message = Message::new(message_type, u32::MAX);
+        assert_eq!(message.protocol_version, 1);
+
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let frame = postcard::to_slice(&message, &mut buf).unwrap();
+        assert_eq!(Message::decode(frame).unwrap(), message);
+    }
+}
@@ -0,0 +1,214 @@
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+use serde::{Deserialize, Serialize};
+use spark_messages::{Journal, JournalError};
+
+const ERASE_SIZE: usize = 256;
+/// Size of a journal record holding a `State`.
+const RECORD_LEN: usize = 20;
+
+/// NOR flash in RAM: erasing sets bytes to 0xFF, writing can only clear bits.
+struct MockFlash {
+    data: Vec<u8>,
+    erases: usize,
+    /// Fail the write after this many more bytes have been written, simulating a power cut.
+    cut_after: Option<usize>,
+}
+
+#[derive(Debug)]
+struct PowerCut;
+
+impl NorFlashError for PowerCut {
+    fn kind(&self) -> NorFlashErrorKind {
+        NorFlashErrorKind::Other
+    }
+}
+
+impl MockFlash {
+    fn new(len: usize) -> Self {
+        Self {
+            data: vec![0xFF; len],
+            erases: 0,
+            cut_after: None,
+        }
+    }
+}
+
+impl ErrorType for MockFlash {
+    type Error = PowerCut;
+}
+
+impl ReadNorFlash for MockFlash {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), PowerCut> {
+        assert_eq!(offset as usize % Self::READ_SIZE, 0);
+        assert_eq!(bytes.len() % Self::READ_SIZE, 0);
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for MockFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), PowerCut> {
+        assert_eq!(from as usize % ERASE_SIZE, 0);
+        assert_eq!(to as usize % ERASE_SIZE, 0);
+        self.data[from as usize..to as usize].fill(0xFF);
+        self.erases += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), PowerCut> {
+        assert_eq!(offset as usize % Self::WRITE_SIZE, 0);
+        assert_eq!(bytes.len() % Self::WRITE_SIZE, 0);
+        for (i, byte) in bytes.iter().enumerate() {
+            if let Some(cut_after) = self.cut_after.as_mut() {
+                if *cut_after == 0 {
+                    return Err(PowerCut);
+                }
+                *cut_after -= 1;
+            }
+            self.data[offset as usize + i] &= byte;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct State {
+    counter: u32,
+    name: [u8; 6],
+}
+
+fn state(counter: u32) -> State {
+    State {
+        counter,
+        name: *b"spark!",
+    }
+}
+
+fn reopen(flash: &mut MockFlash) -> Journal<&mut MockFlash> {
+    Journal::new(flash, ERASE_SIZE as u32, 2 * ERASE_SIZE as u32).unwrap()
+}
+
+#[test]
+fn starts_empty() {
+    let mut flash = MockFlash::new(4 * ERASE_SIZE);
+    let mut buf = [0u8; 64];
+    assert_eq!(reopen(&mut flash).load::<State>(&mut buf).unwrap(), None);
+}
+
+#[test]
+fn loads_the_latest_snapshot() {
+    let mut flash = MockFlash::new(4 * ERASE_SIZE);
+    let mut buf = [0u8; 64];
+
+    let mut journal = reopen(&mut flash);
+    for counter in 0..5 {
+        journal.store(&state(counter), &mut buf).unwrap();
+    }
+    assert_eq!(journal.load(&mut buf).unwrap(), Some(state(4)));
+
+    let mut journal = reopen(&mut flash);
+    assert_eq!(journal.load(&mut buf).unwrap(), Some(state(4)));
+    journal.store(&state(5), &mut buf).unwrap();
+
+    let mut journal = reopen(&mut flash);
+    assert_eq!(journal.load(&mut buf).unwrap(), Some(state(5)));
+}
+
+#[test]
+fn stays_inside_its_region() {
+    let mut flash = MockFlash::new(4 * ERASE_SIZE);
+    let mut buf = [0u8; 64];
+
+    let mut journal = reopen(&mut flash);
+    for counter in 0..100 {
+        journal.store(&state(counter), &mut buf).unwrap();
+    }
+
+    assert!(flash.data[..ERASE_SIZE].iter().all(|&b| b == 0xFF));
+    assert!(flash.data[3 * ERASE_SIZE..].iter().all(|&b| b == 0xFF));
+}
+
+#[test]
+fn only_erases_when_a_half_is_full() {
+    let mut flash = MockFlash::new(4 * ERASE_SIZE);
+    let mut buf = [0u8; 64];
+
+    let mut journal = reopen(&mut flash);
+    // 12 byte header plus 7 bytes of state, padded to 20.
+    for counter in 0..(ERASE_SIZE / RECORD_LEN) as u32 {
+        journal.store(&state(counter), &mut buf).unwrap();
+    }
+    assert_eq!(flash.erases, 0);
+
+    let mut journal = reopen(&mut flash);
+    for counter in 0..100 {
+        journal.store(&state(counter), &mut buf).unwrap();
+        assert_eq!(journal.load(&mut buf).unwrap(), Some(state(counter)));
+    }
+    let records_per_half = ERASE_SIZE / RECORD_LEN;
+    assert_eq!(flash.erases, 100usize.div_ceil(records_per_half));
+
+    let mut journal = reopen(&mut flash);
+    assert_eq!(journal.load(&mut buf).unwrap(), Some(state(99)));
+}
+
+#[test]
+fn survives_power_cuts() {
+    let mut buf = [0u8; 64];
+
+    // Cut the power at every point of a write, including the one that switches halves.
+    for stored in [3, (ERASE_SIZE / RECORD_LEN) as u32] {
+        // The last byte is padding, so the record is complete without it.
+        for cut_after in 0..RECORD_LEN - 1 {
+            let mut flash = MockFlash::new(4 * ERASE_SIZE);
+            let mut journal = reopen(&mut flash);
+            for counter in 0..stored {
+                journal.store(&state(counter), &mut buf).unwrap();
+            }
+
+            flash.cut_after = Some(cut_after);
+            let mut journal = reopen(&mut flash);
+            assert!(matches!(
+                journal.store(&state(100), &mut buf),
+                Err(JournalError::Flash(PowerCut))
+            ));
+
+            flash.cut_after = None;
+            let mut journal = reopen(&mut flash);
+            assert_eq!(
+                journal.load(&mut buf).unwrap(),
+                Some(state(stored - 1)),
+                "cut after {cut_after} bytes"
+            );
+
+            // The torn record isn't written over.
+            journal.store(&state(101), &mut buf).unwrap();
+            let mut journal = reopen(&mut flash);
+            assert_eq!(journal.load(&mut buf).unwrap(), Some(state(101)));
+        }
+    }
+}
+
+#[test]
+fn rejects_snapshots_that_dont_fit() {
+    let mut flash = MockFlash::new(4 * ERASE_SIZE);
+    let mut journal = reopen(&mut flash);
+
+    let mut buf = [0u8; RECORD_LEN - 1];
+    assert!(matches!(
+        journal.store(&state(0), &mut buf),
+        Err(JournalError::TooLarge)
+    ));
+}
@@ -0,0 +1,147 @@
+use std::{env, fs};
+
+use spark_messages::{
+    ButtonEventType, ButtonNumber, ButtonSequence, ConfigEntry, ConfigError, ConfigKey, ConfigType,
+    ConfigValue, DEFAULT_ANIMATION, Destination, Fragment, Message, MessageType, json_schema,
+};
+
+const SCHEMA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/schema/message.schema.json");
+
+#[test]
+fn schema_is_unchanged() {
+    let schema = serde_json::to_string_pretty(&json_schema()).unwrap() + "\n";
+    if env::var_os("UPDATE_SCHEMA").is_some() {
+        fs::write(SCHEMA, schema).unwrap();
+        return;
+    }
+
+    let snapshot = fs::read_to_string(SCHEMA).unwrap_or_default();
+    assert!(
+        schema == snapshot,
+        "the JSON schema of the protocol changed. If that's intended, run the tests with \
+         UPDATE_SCHEMA=1 and commit {SCHEMA}"
+    );
+}
+
+#[test]
+fn serializes_stably() {
+    let message = Message::new(
+        MessageType::ButtonEvent {
+            button_number: ButtonNumber::Button2,
+            event_type: ButtonEventType::ShortPress { count: 3 },
+        },
+        9,
+    )
+    .with_destination(Destination::Groups(0b101));
+    let json = r#"{"protocol_version":0,"message_type":{"ButtonEvent":{"button_number":"Button2","event_type":{"ShortPress":{"count":3}}}},"counter":9,"destination":{"Groups":5},"id":0,"hops":0,"origin":null}"#;
+    assert_eq!(message.to_json(), json);
+    assert_eq!(Message::from_json(json).unwrap(), message);
+
+    let message = Message::new(MessageType::StatusRequest, 0);
+    let json = r#"{"protocol_version":1,"message_type":"StatusRequest","counter":0,"destination":"All","id":0,"hops":0,"origin":null}"#;
+    assert_eq!(message.to_json(), json);
+    assert_eq!(Message::from_json(json).unwrap(), message);
+}
+
+/// JSON as written before message IDs, without `id`, `hops` and `origin`.
+#[test]
+fn reads_json_predating_message_ids() {
+    let message = Message::new(
+        MessageType::ButtonEvent {
+            button_number: ButtonNumber::Button2,
+            event_type: ButtonEventType::ShortPress { count: 3 },
+        },
+        9,
+    )
+    .with_destination(Destination::Groups(0b101));
+    let json = r#"{"protocol_version":0,"message_type":{"ButtonEvent":{"button_number":"Button2","event_type":{"ShortPress":{"count":3}}}},"counter":9,"destination":{"Groups":5}}"#;
+    assert_eq!(Message::from_json(json).unwrap(), message);
+
+    let message = Message::new(MessageType::StatusRequest, 0);
+    let json =
+        r#"{"protocol_version":1,"message_type":"StatusRequest","counter":0,"destination":"All"}"#;
+    assert_eq!(Message::from_json(json).unwrap(), message);
+
+    // Revision 0 had none of the appended fields.
+    let message = Message {
+        protocol_version: 0,
+        ..Message::new(MessageType::StatusRequest, 0)
+    };
+    let json = r#"{"protocol_version":0,"message_type":"StatusRequest"}"#;
+    assert_eq!(Message::from_json(json).unwrap(), message);
+}
+
+/// JSON as written before relaying, without `hops` and `origin`.
+#[test]
+fn reads_json_predating_relaying() {
+    let message = Message::new(
+        MessageType::ButtonEvent {
+            button_number: ButtonNumber::Button2,
+            event_type: ButtonEventType::ShortPress { count: 3 },
+        },
+        9,
+    )
+    .with_destination(Destination::Groups(0b101));
+    let json = r#"{"protocol_version":0,"message_type":{"ButtonEvent":{"button_number":"Button2","event_type":{"ShortPress":{"count":3}}}},"counter":9,"destination":{"Groups":5},"id":0}"#;
+    assert_eq!(Message::from_json(json).unwrap(), message);
+
+    let message = Message::new(MessageType::StatusRequest, 0);
+    let json = r#"{"protocol_version":1,"message_type":"StatusRequest","counter":0,"destination":"All","id":0}"#;
+    assert_eq!(Message::from_json(json).unwrap(), message);
+}
+
+#[test]
+fn rejects_bad_json() {
+    for json in [
+        "",
+        "{}",
+        r#"{"protocol_version":1,"counter":0}"#,
+        r#"{"protocol_version":1,"message_type":"Dance","counter":0,"destination":"All","id":0,"hops":0,"origin":null}"#,
+        // One byte too many.
+        &format!(
+            r#"{{"protocol_version":1,"message_type":{{"ButtonSequence":{{"buttons":[{}"Button1"]}}}},"counter":0,"destination":"All","id":0,"hops":0,"origin":null}}"#,
+            r#""Button1","#.repeat(8)
+        ),
+    ] {
+        assert!(Message::from_json(json).is_err(), "{json}");
+    }
+}
+
+#[test]
+fn messages_round_trip() {
+    let messages = [
+        MessageType::SetAnimation(DEFAULT_ANIMATION),
+        MessageType::Fragment(Fragment {
+            transfer: 1,
+            index: 2,
+            count: 3,
+            data: heapless::Vec::from_slice(&[0, 1, 255]).unwrap(),
+        }),
+        MessageType::ConfigReply(Ok(ConfigEntry {
+            key: ConfigKey::LedCount,
+            value: ConfigValue::U16(300),
+        })),
+        MessageType::ConfigReply(Err(ConfigError::WrongType {
+            key: ConfigKey::Brightness,
+            expected: ConfigType::U8,
+        })),
+        MessageType::ButtonChord(
+            [ButtonNumber::Button1, ButtonNumber::Button4]
+                .into_iter()
+                .collect(),
+        ),
+        MessageType::ButtonSequence(ButtonSequence {
+            buttons: heapless::Vec::from_slice(&[ButtonNumber::Button3; 2]).unwrap(),
+        }),
+        MessageType::LinkStats {
+            peer: [1, 2, 3, 4, 5, 6],
+            stats: None,
+        },
+    ];
+
+    for message_type in messages {
+        let message = Message::new(message_type, u32::MAX).with_destination(Destination::Device(7));
+        assert_eq!(message.protocol_version, 1);
+        assert_eq!(Message::from_json(&message.to_json()).unwrap(), message);
+    }
+}
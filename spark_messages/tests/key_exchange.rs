@@ -0,0 +1,204 @@
+use spark_messages::{
+    KeyConfirm, KeyExchange, KeyExchangeError, KeyShare, MAX_FRAME_LEN, MacAddress, Message,
+    MessageType, Role, parse_key,
+};
+
+const REMOTE: MacAddress = [0xAA, 0, 0, 0, 0, 1];
+const LIGHT: MacAddress = [0x11, 0, 0, 0, 0, 3];
+const OTHER: MacAddress = [0xBB, 0, 0, 0, 0, 2];
+
+// The X25519 test vectors of RFC 7748, section 6.1, the remote playing Alice and the light Bob.
+const REMOTE_SECRET: [u8; 32] =
+    parse_key("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+const REMOTE_PUBLIC_KEY: [u8; 32] =
+    parse_key("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a");
+const LIGHT_SECRET: [u8; 32] =
+    parse_key("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+const LIGHT_PUBLIC_KEY: [u8; 32] =
+    parse_key("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f");
+
+// HKDF-SHA256 and HMAC-SHA256 of the shared secret of the test vectors, computed independently.
+const LMK: [u8; 16] = [
+    0x68, 0x5a, 0x9e, 0x5e, 0xff, 0xa3, 0x5b, 0xa8, 0x5e, 0xf0, 0x42, 0x29, 0xb5, 0x90, 0xe1, 0x8b,
+];
+const REMOTE_TAG: [u8; 32] =
+    parse_key("0346d450b69525a8e833f4364c58fbe0144f489c30d851afe309be422e5e5a8d");
+const LIGHT_TAG: [u8; 32] =
+    parse_key("45baf1cc53331c890f98738a18f0e93ef09cdaa22f7c36b1ad399c3fbfb2b2b0");
+
+fn exchanges() -> (KeyExchange, KeyExchange) {
+    (
+        KeyExchange::new(Role::Remote, REMOTE, LIGHT, REMOTE_SECRET),
+        KeyExchange::new(Role::Light, LIGHT, REMOTE, LIGHT_SECRET),
+    )
+}
+
+/// Both sides after swapping key shares.
+fn shared() -> (KeyExchange, KeyExchange) {
+    let (mut remote, mut light) = exchanges();
+    light.on_share(REMOTE, &remote.share()).unwrap();
+    remote.on_share(LIGHT, &light.share()).unwrap();
+    (remote, light)
+}
+
+#[test]
+fn derives_the_key_of_the_test_vectors() {
+    let (mut remote, mut light) = exchanges();
+    assert_eq!(remote.share().public_key, REMOTE_PUBLIC_KEY);
+    assert_eq!(light.share().public_key, LIGHT_PUBLIC_KEY);
+
+    assert_eq!(light.on_share(REMOTE, &remote.share()), Ok(None));
+    assert_eq!(remote.on_share(LIGHT, &light.share()), Ok(None));
+
+    let remote_confirm = remote.confirm().unwrap();
+    assert_eq!(remote_confirm.tag, REMOTE_TAG);
+    let light_confirm = light.confirm().unwrap();
+    assert_eq!(light_confirm.tag, LIGHT_TAG);
+
+    assert_eq!(light.on_confirm(REMOTE, &remote_confirm), Ok(None));
+    assert_eq!(remote.on_confirm(LIGHT, &light_confirm), Ok(None));
+    assert_eq!(remote.lmk(), Some(LMK));
+    assert_eq!(light.lmk(), Some(LMK));
+}
+
+#[test]
+fn needs_both_sides_to_confirm() {
+    let (mut remote, mut light) = shared();
+    assert_eq!(remote.lmk(), None);
+    assert_eq!(light.pending(), None);
+
+    // The remote's user confirms first, the light hasn't yet.
+    let remote_confirm = remote.confirm().unwrap();
+    assert_eq!(
+        remote.pending(),
+        Some(MessageType::KeyConfirm(remote_confirm))
+    );
+    assert_eq!(light.on_confirm(REMOTE, &remote_confirm), Ok(None));
+    assert_eq!(light.lmk(), None);
+    assert_eq!(remote.lmk(), None);
+
+    let light_confirm = light.confirm().unwrap();
+    assert_eq!(light.lmk(), Some(LMK));
+    assert_eq!(remote.on_confirm(LIGHT, &light_confirm), Ok(None));
+    assert_eq!(remote.lmk(), Some(LMK));
+    assert_eq!(remote.pending(), None);
+}
+
+#[test]
+fn answers_repeated_confirmations() {
+    let (mut remote, mut light) = shared();
+    let light_confirm = light.confirm().unwrap();
+    assert_eq!(remote.on_confirm(LIGHT, &light_confirm), Ok(None));
+
+    // The remote's confirmation got lost, so the light sends its own again.
+    let remote_confirm = remote.confirm().unwrap();
+    assert_eq!(remote.pending(), None);
+    assert_eq!(
+        remote.on_confirm(LIGHT, &light_confirm),
+        Ok(Some(remote_confirm))
+    );
+    assert_eq!(light.on_confirm(REMOTE, &remote_confirm), Ok(None));
+    assert_eq!(light.pending(), None);
+    assert_eq!(light.lmk(), Some(LMK));
+}
+
+#[test]
+fn resends_the_share_until_the_peer_answers() {
+    let (remote, mut light) = exchanges();
+    let share = MessageType::KeyShare(remote.share());
+    assert_eq!(remote.pending(), Some(share));
+
+    // The first share goes unanswered, as the light sends its own anyway. Repeats mean the
+    // remote didn't get it.
+    assert_eq!(light.on_share(REMOTE, &remote.share()), Ok(None));
+    assert_eq!(light.pending(), None);
+    assert_eq!(
+        light.on_share(REMOTE, &remote.share()),
+        Ok(Some(light.share()))
+    );
+}
+
+#[test]
+fn fails_if_a_share_was_garbled() {
+    let (mut remote, mut light) = exchanges();
+    let mut garbled = remote.share();
+    garbled.public_key[0] ^= 1;
+    light.on_share(REMOTE, &garbled).unwrap();
+    remote.on_share(LIGHT, &light.share()).unwrap();
+
+    let remote_confirm = remote.confirm().unwrap();
+    let light_confirm = light.confirm().unwrap();
+    assert_eq!(
+        light.on_confirm(REMOTE, &remote_confirm),
+        Err(KeyExchangeError::BadConfirmation)
+    );
+    assert_eq!(
+        remote.on_confirm(LIGHT, &light_confirm),
+        Err(KeyExchangeError::BadConfirmation)
+    );
+    assert_eq!(remote.lmk(), None);
+    assert_eq!(light.lmk(), None);
+}
+
+#[test]
+fn rejects_other_devices() {
+    let (remote, mut light) = exchanges();
+    assert_eq!(
+        light.on_share(OTHER, &remote.share()),
+        Err(KeyExchangeError::WrongPeer)
+    );
+
+    let (mut remote, mut light) = shared();
+    let remote_confirm = remote.confirm().unwrap();
+    assert_eq!(
+        light.on_confirm(OTHER, &remote_confirm),
+        Err(KeyExchangeError::WrongPeer)
+    );
+}
+
+#[test]
+fn rejects_changed_and_weak_keys() {
+    let (_, mut light) = shared();
+    let other = KeyExchange::new(Role::Remote, REMOTE, LIGHT, [7; 32]);
+    assert_eq!(
+        light.on_share(REMOTE, &other.share()),
+        Err(KeyExchangeError::KeyChanged)
+    );
+
+    // The identity point makes for an all-zero shared secret.
+    let (_, mut light) = exchanges();
+    let weak = KeyShare {
+        public_key: [0; 32],
+    };
+    assert_eq!(
+        light.on_share(REMOTE, &weak),
+        Err(KeyExchangeError::WeakKey)
+    );
+}
+
+#[test]
+fn confirms_only_after_the_shares() {
+    let (mut remote, mut light) = exchanges();
+    assert_eq!(remote.confirm(), Err(KeyExchangeError::NoPeerKey));
+    assert_eq!(
+        light.on_confirm(REMOTE, &KeyConfirm { tag: REMOTE_TAG }),
+        Err(KeyExchangeError::NoPeerKey)
+    );
+}
+
+#[test]
+fn messages_round_trip() {
+    let message_types = [
+        MessageType::KeyShare(KeyShare {
+            public_key: REMOTE_PUBLIC_KEY,
+        }),
+        MessageType::KeyConfirm(KeyConfirm { tag: LIGHT_TAG }),
+    ];
+    for message_type in message_types {
+        let message = Message::new(message_type, u32::MAX);
+        assert_eq!(message.protocol_version, 1);
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let frame = postcard::to_slice(&message, &mut buf).unwrap();
+        assert_eq!(Message::decode(frame), Ok(message));
+    }
+}
@@ -0,0 +1,161 @@
+use spark_messages::{
+    HEARTBEAT_INTERVAL_MS, Heartbeat, LinkMonitor, LinkStats, MAX_FRAME_LEN, MacAddress, Message,
+    MessageType, ResetReason,
+};
+
+const LIGHT: MacAddress = [0x11, 0, 0, 0, 0, 1];
+const OTHER_LIGHT: MacAddress = [0x22, 0, 0, 0, 0, 2];
+
+fn heartbeat(seq: u32, uptime_ms: u64) -> Heartbeat {
+    Heartbeat {
+        seq,
+        uptime_ms,
+        reset_reason: ResetReason::PowerOn,
+    }
+}
+
+/// A light that booted at `boot` sends heartbeat `seq`, received at `now`.
+fn receive(links: &mut LinkMonitor<4>, now: u64, boot: u64, seq: u32) -> bool {
+    links.on_frame(now, LIGHT, -60);
+    links.on_heartbeat(LIGHT, &heartbeat(seq, now - boot))
+}
+
+#[test]
+fn knows_nothing_of_unheard_peers() {
+    let mut links = LinkMonitor::<4>::new();
+    assert_eq!(links.stats(0, LIGHT), None);
+    assert!(!links.on_heartbeat(LIGHT, &heartbeat(0, 0)));
+    assert_eq!(links.stats(0, LIGHT), None);
+}
+
+#[test]
+fn tracks_last_seen_and_signal_strength() {
+    let mut links = LinkMonitor::<4>::new();
+    links.on_frame(1_000, LIGHT, -40);
+    assert_eq!(
+        links.stats(1_500, LIGHT),
+        Some(LinkStats {
+            rssi: -40,
+            rssi_avg: -40,
+            since_last_seen_ms: 500,
+            heartbeats: 0,
+            heartbeats_lost: 0,
+            restarts: 0,
+            last_heartbeat: None,
+        })
+    );
+
+    // The average follows along, without jumping at every frame.
+    links.on_frame(2_000, LIGHT, -80);
+    let stats = links.stats(2_000, LIGHT).unwrap();
+    assert_eq!(stats.rssi, -80);
+    assert_eq!(stats.rssi_avg, -45);
+    for now in 0..50 {
+        links.on_frame(2_000 + now, LIGHT, -80);
+    }
+    let stats = links.stats(3_000, LIGHT).unwrap();
+    assert_eq!(stats.rssi_avg, -80);
+    assert_eq!(stats.since_last_seen_ms, 951);
+}
+
+#[test]
+fn counts_lost_heartbeats() {
+    let mut links = LinkMonitor::<4>::new();
+    for seq in [0, 1, 2, 5, 6, 9] {
+        let now = 100 + seq as u64 * HEARTBEAT_INTERVAL_MS;
+        assert!(!receive(&mut links, now, 0, seq));
+    }
+
+    let stats = links.stats(100_000, LIGHT).unwrap();
+    assert_eq!(stats.heartbeats, 6);
+    assert_eq!(stats.heartbeats_lost, 4);
+    assert_eq!(stats.loss_rate(), 0.4);
+    assert_eq!(stats.restarts, 0);
+    assert_eq!(stats.last_heartbeat.unwrap().seq, 9);
+}
+
+#[test]
+fn ignores_repeated_heartbeats() {
+    let mut links = LinkMonitor::<4>::new();
+    receive(&mut links, 100, 0, 0);
+    links.on_heartbeat(LIGHT, &heartbeat(0, 100));
+
+    let stats = links.stats(100, LIGHT).unwrap();
+    assert_eq!(stats.heartbeats, 1);
+    assert_eq!(stats.loss_rate(), 0.0);
+}
+
+#[test]
+fn notices_restarts() {
+    let mut links = LinkMonitor::<4>::new();
+    for seq in 0..10 {
+        receive(&mut links, seq as u64 * HEARTBEAT_INTERVAL_MS, 0, seq);
+    }
+
+    // Rebooted after heartbeat 9, and heartbeat 0 of the new boot got lost.
+    let boot = 95_000;
+    assert!(receive(&mut links, boot + HEARTBEAT_INTERVAL_MS, boot, 1));
+    assert!(!receive(
+        &mut links,
+        boot + 2 * HEARTBEAT_INTERVAL_MS,
+        boot,
+        2
+    ));
+
+    let stats = links.stats(120_000, LIGHT).unwrap();
+    assert_eq!(stats.restarts, 1);
+    assert_eq!(stats.heartbeats, 12);
+    assert_eq!(stats.heartbeats_lost, 1);
+}
+
+#[test]
+fn forgets_the_quietest_peer_once_full() {
+    let mut links = LinkMonitor::<2>::new();
+    links.on_frame(0, LIGHT, -50);
+    links.on_frame(10, OTHER_LIGHT, -50);
+    links.on_frame(20, LIGHT, -50);
+
+    let third = [0x33, 0, 0, 0, 0, 3];
+    links.on_frame(30, third, -50);
+    assert!(links.stats(30, LIGHT).is_some());
+    assert!(links.stats(30, third).is_some());
+    assert_eq!(links.stats(30, OTHER_LIGHT), None);
+}
+
+#[test]
+fn messages_round_trip() {
+    let stats = LinkStats {
+        rssi: i8::MIN,
+        rssi_avg: i8::MAX,
+        since_last_seen_ms: u64::MAX,
+        heartbeats: u32::MAX,
+        heartbeats_lost: u32::MAX,
+        restarts: u32::MAX,
+        last_heartbeat: Some(Heartbeat {
+            seq: u32::MAX,
+            uptime_ms: u64::MAX,
+            reset_reason: ResetReason::Brownout,
+        }),
+    };
+    let messages = [
+        MessageType::Heartbeat(heartbeat(u32::MAX, u64::MAX)),
+        MessageType::LinkStatsRequest { peer: LIGHT },
+        MessageType::LinkStats {
+            peer: LIGHT,
+            stats: Some(stats),
+        },
+        MessageType::LinkStats {
+            peer: OTHER_LIGHT,
+            stats: None,
+        },
+    ];
+
+    for message_type in messages {
+        let message = Message::new(message_type, u32::MAX);
+        assert_eq!(message.protocol_version, 1);
+
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let frame = postcard::to_slice(&message, &mut buf).unwrap();
+        assert_eq!(Message::decode(frame).unwrap(), message);
+    }
+}
@@ -0,0 +1,500 @@
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+use sha2::{Digest, Sha256};
+use spark_messages::{
+    Destination, Key, MAX_FRAME_LEN, MacAddress, Message, MessageType, OTA_CHUNK_LEN, OtaBegin,
+    OtaChunk, OtaError, OtaReceiver, OtaState, OtaStatus, parse_key, sign,
+};
+
+const ERASE_SIZE: usize = 256;
+/// Where the update region starts in the mock flash, and how long it is.
+const REGION: u32 = 2 * ERASE_SIZE as u32;
+const REGION_LEN: u32 = 64 * ERASE_SIZE as u32;
+
+const KEY: Key = parse_key("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+const REMOTE: MacAddress = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+
+/// NOR flash in RAM: erasing sets bytes to 0xFF, writing can only clear bits.
+struct MockFlash {
+    data: Vec<u8>,
+    /// Fail the write after this many more, having written half of it.
+    fail_write_after: Option<usize>,
+    /// Flip a bit when this byte gets written.
+    corrupt: Option<usize>,
+}
+
+#[derive(Debug)]
+struct WriteFailed;
+
+impl NorFlashError for WriteFailed {
+    fn kind(&self) -> NorFlashErrorKind {
+        NorFlashErrorKind::Other
+    }
+}
+
+impl MockFlash {
+    fn new() -> Self {
+        Self {
+            data: vec![0xFF; REGION as usize + REGION_LEN as usize + ERASE_SIZE],
+            fail_write_after: None,
+            corrupt: None,
+        }
+    }
+
+    fn region(&self, len: usize) -> &[u8] {
+        &self.data[REGION as usize..REGION as usize + len]
+    }
+}
+
+impl ErrorType for MockFlash {
+    type Error = WriteFailed;
+}
+
+impl ReadNorFlash for MockFlash {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), WriteFailed> {
+        assert_eq!(offset as usize % Self::READ_SIZE, 0);
+        assert_eq!(bytes.len() % Self::READ_SIZE, 0);
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for MockFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), WriteFailed> {
+        assert_eq!(from as usize % ERASE_SIZE, 0);
+        assert_eq!(to as usize % ERASE_SIZE, 0);
+        self.data[from as usize..to as usize].fill(0xFF);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), WriteFailed> {
+        assert_eq!(offset as usize % Self::WRITE_SIZE, 0);
+        assert_eq!(bytes.len() % Self::WRITE_SIZE, 0);
+        let fail = self.fail_write_after == Some(0);
+        self.fail_write_after = self.fail_write_after.map(|n| n.saturating_sub(1));
+        let bytes = if fail {
+            &bytes[..bytes.len() / 2]
+        } else {
+            bytes
+        };
+
+        for (i, byte) in bytes.iter().enumerate() {
+            let offset = offset as usize + i;
+            // Writing over bytes that weren't erased is a bug.
+            assert_eq!(
+                self.data[offset] & byte,
+                *byte,
+                "writing over unerased flash"
+            );
+            self.data[offset] &= byte;
+            if self.corrupt == Some(offset) {
+                self.data[offset] ^= 0x10;
+                self.corrupt = None;
+            }
+        }
+
+        if fail {
+            self.fail_write_after = None;
+            return Err(WriteFailed);
+        }
+        Ok(())
+    }
+}
+
+fn image(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + i / 256) as u8).collect()
+}
+
+fn begin(image: &[u8]) -> OtaBegin {
+    OtaBegin {
+        size: image.len() as u32,
+        sha256: Sha256::digest(image).into(),
+    }
+}
+
+fn chunk(image: &[u8], offset: usize) -> OtaChunk {
+    let end = (offset + OTA_CHUNK_LEN).min(image.len());
+    OtaChunk {
+        offset: offset as u32,
+        data: heapless::Vec::from_slice(&image[offset..end]).unwrap(),
+    }
+}
+
+fn status(state: OtaState, received: usize) -> OtaStatus {
+    OtaStatus {
+        state,
+        received: received as u32,
+        error: None,
+    }
+}
+
+fn receiver(flash: &mut MockFlash) -> OtaReceiver<&mut MockFlash> {
+    OtaReceiver::new(flash, REGION, REGION_LEN)
+}
+
+/// Send the chunks from `from` up to `to`, which the receiver expects to be in order.
+fn send_chunks(
+    receiver: &mut OtaReceiver<&mut MockFlash>,
+    image: &[u8],
+    from: usize,
+    to: usize,
+) -> Option<OtaStatus> {
+    let mut last = None;
+    for offset in (from..to).step_by(OTA_CHUNK_LEN) {
+        assert_eq!(last, None, "answered a chunk in order");
+        last = receiver.on_chunk(&chunk(image, offset));
+    }
+    last
+}
+
+#[test]
+fn receives_an_image() {
+    let image = image(10 * OTA_CHUNK_LEN + 3);
+    let mut flash = MockFlash::new();
+    let mut receiver = receiver(&mut flash);
+
+    assert_eq!(receiver.status(), status(OtaState::Idle, 0));
+    assert_eq!(
+        receiver.on_begin(&begin(&image)),
+        status(OtaState::Receiving, 0)
+    );
+    assert_eq!(
+        send_chunks(&mut receiver, &image, 0, image.len()),
+        Some(status(OtaState::Complete, image.len()))
+    );
+    assert!(!receiver.is_committed());
+    assert_eq!(
+        receiver.on_commit(),
+        status(OtaState::Committed, image.len())
+    );
+    assert!(receiver.is_committed());
+
+    // Repeats, e.g. after the status got lost, don't change anything.
+    assert_eq!(
+        receiver.on_begin(&begin(&image)),
+        status(OtaState::Committed, image.len())
+    );
+    assert_eq!(
+        receiver.on_commit(),
+        status(OtaState::Committed, image.len())
+    );
+
+    let flash = receiver.into_inner();
+    assert_eq!(flash.region(image.len()), image);
+    // Nothing outside the region was touched.
+    assert!(flash.data[..REGION as usize].iter().all(|&b| b == 0xFF));
+    let end = (REGION as usize + image.len()).next_multiple_of(ERASE_SIZE);
+    assert!(flash.data[end..].iter().all(|&b| b == 0xFF));
+}
+
+#[test]
+fn rewinds_after_a_lost_chunk() {
+    let image = image(8 * OTA_CHUNK_LEN);
+    let mut flash = MockFlash::new();
+    let mut receiver = receiver(&mut flash);
+    receiver.on_begin(&begin(&image));
+
+    assert_eq!(
+        send_chunks(&mut receiver, &image, 0, 3 * OTA_CHUNK_LEN),
+        None
+    );
+    // Chunk 3 gets lost, every later one is answered with where to pick up from.
+    for i in 4..8 {
+        assert_eq!(
+            receiver.on_chunk(&chunk(&image, i * OTA_CHUNK_LEN)),
+            Some(status(OtaState::Receiving, 3 * OTA_CHUNK_LEN))
+        );
+    }
+    // So are repeats.
+    assert_eq!(
+        receiver.on_chunk(&chunk(&image, OTA_CHUNK_LEN)),
+        Some(status(OtaState::Receiving, 3 * OTA_CHUNK_LEN))
+    );
+
+    assert_eq!(
+        send_chunks(&mut receiver, &image, 3 * OTA_CHUNK_LEN, image.len()),
+        Some(status(OtaState::Complete, image.len()))
+    );
+    assert_eq!(
+        receiver.on_commit(),
+        status(OtaState::Committed, image.len())
+    );
+}
+
+#[test]
+fn resumes_a_session() {
+    let image = image(20 * OTA_CHUNK_LEN + 100);
+    let mut flash = MockFlash::new();
+    let mut receiver = receiver(&mut flash);
+    receiver.on_begin(&begin(&image));
+    send_chunks(&mut receiver, &image, 0, 12 * OTA_CHUNK_LEN);
+
+    // The sender lost the link for a while, and asks where to carry on.
+    assert_eq!(
+        receiver.on_begin(&begin(&image)),
+        status(OtaState::Receiving, 12 * OTA_CHUNK_LEN)
+    );
+    send_chunks(&mut receiver, &image, 12 * OTA_CHUNK_LEN, image.len());
+    assert_eq!(
+        receiver.on_commit(),
+        status(OtaState::Committed, image.len())
+    );
+}
+
+#[test]
+fn starts_over_for_another_image() {
+    let old = image(6 * OTA_CHUNK_LEN);
+    let new: Vec<u8> = image(9 * OTA_CHUNK_LEN).iter().map(|b| !b).collect();
+    let mut flash = MockFlash::new();
+    let mut receiver = receiver(&mut flash);
+    receiver.on_begin(&begin(&old));
+    send_chunks(&mut receiver, &old, 0, 4 * OTA_CHUNK_LEN);
+
+    // The mock flash checks that the old image gets erased before being written over.
+    assert_eq!(
+        receiver.on_begin(&begin(&new)),
+        status(OtaState::Receiving, 0)
+    );
+    send_chunks(&mut receiver, &new, 0, new.len());
+    assert_eq!(receiver.on_commit(), status(OtaState::Committed, new.len()));
+
+    let flash = receiver.into_inner();
+    assert_eq!(flash.region(new.len()), new);
+}
+
+#[test]
+fn verifies_what_is_in_flash() {
+    let image = image(5 * OTA_CHUNK_LEN + 1);
+    let mut flash = MockFlash::new();
+    flash.corrupt = Some(REGION as usize + 700);
+    let mut receiver = receiver(&mut flash);
+    receiver.on_begin(&begin(&image));
+
+    send_chunks(&mut receiver, &image, 0, image.len());
+    assert_eq!(
+        receiver.on_commit(),
+        OtaStatus {
+            state: OtaState::Receiving,
+            received: 0,
+            error: Some(OtaError::HashMismatch),
+        }
+    );
+    assert!(!receiver.is_committed());
+
+    // The image is sent again, over what's left of the first attempt.
+    send_chunks(&mut receiver, &image, 0, image.len());
+    assert_eq!(
+        receiver.on_commit(),
+        status(OtaState::Committed, image.len())
+    );
+}
+
+#[test]
+fn starts_over_when_the_hash_doesnt_match() {
+    let image = image(5 * OTA_CHUNK_LEN + 1);
+    let mut flash = MockFlash::new();
+    let mut receiver = receiver(&mut flash);
+    let mut announced = begin(&image);
+    announced.sha256[0] ^= 1;
+    receiver.on_begin(&announced);
+
+    for _ in 0..2 {
+        send_chunks(&mut receiver, &image, 0, image.len());
+        assert_eq!(receiver.on_commit().error, Some(OtaError::HashMismatch));
+        assert_eq!(receiver.status().received, 0);
+    }
+}
+
+#[test]
+fn retries_failed_writes() {
+    let image = image(4 * OTA_CHUNK_LEN);
+    let mut flash = MockFlash::new();
+    flash.fail_write_after = Some(2);
+    let mut receiver = receiver(&mut flash);
+    receiver.on_begin(&begin(&image));
+
+    send_chunks(&mut receiver, &image, 0, 2 * OTA_CHUNK_LEN);
+    assert_eq!(
+        receiver.on_chunk(&chunk(&image, 2 * OTA_CHUNK_LEN)),
+        Some(OtaStatus {
+            state: OtaState::Receiving,
+            received: 2 * OTA_CHUNK_LEN as u32,
+            error: Some(OtaError::Flash),
+        })
+    );
+
+    assert_eq!(
+        send_chunks(&mut receiver, &image, 2 * OTA_CHUNK_LEN, image.len()),
+        Some(status(OtaState::Complete, image.len()))
+    );
+    assert_eq!(
+        receiver.on_commit(),
+        status(OtaState::Committed, image.len())
+    );
+}
+
+#[test]
+fn rejects_out_of_place_messages() {
+    let image = image(OTA_CHUNK_LEN + 100);
+    let mut flash = MockFlash::new();
+    let mut receiver = receiver(&mut flash);
+
+    assert_eq!(
+        receiver.on_chunk(&chunk(&image, 0)).unwrap().error,
+        Some(OtaError::NoSession)
+    );
+    assert_eq!(receiver.on_commit().error, Some(OtaError::NoSession));
+
+    let too_large = OtaBegin {
+        size: REGION_LEN + 1,
+        sha256: [0; 32],
+    };
+    assert_eq!(
+        receiver.on_begin(&too_large),
+        OtaStatus {
+            state: OtaState::Idle,
+            received: 0,
+            error: Some(OtaError::TooLarge),
+        }
+    );
+
+    receiver.on_begin(&begin(&image));
+    send_chunks(&mut receiver, &image, 0, OTA_CHUNK_LEN);
+    assert_eq!(
+        receiver.on_commit(),
+        OtaStatus {
+            state: OtaState::Receiving,
+            received: OTA_CHUNK_LEN as u32,
+            error: Some(OtaError::Incomplete),
+        }
+    );
+
+    // Chunks that would leave the next write unaligned, or run past the image, are ignored.
+    let mut unaligned = chunk(&image, OTA_CHUNK_LEN);
+    unaligned.data.pop();
+    let past_the_end = chunk(&self::image(2 * OTA_CHUNK_LEN), OTA_CHUNK_LEN);
+    for chunk in [unaligned, past_the_end] {
+        assert_eq!(
+            receiver.on_chunk(&chunk),
+            Some(status(OtaState::Receiving, OTA_CHUNK_LEN))
+        );
+    }
+}
+
+#[test]
+fn chunks_fit_in_a_frame() {
+    let message = Message::new(
+        MessageType::OtaChunk(OtaChunk {
+            offset: u32::MAX,
+            data: heapless::Vec::from_slice(&[0xFF; OTA_CHUNK_LEN]).unwrap(),
+        }),
+        u32::MAX,
+    )
+    .with_destination(Destination::Groups(u32::MAX));
+
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = postcard::to_slice(&message, &mut buf).unwrap().len();
+    sign(&KEY, &REMOTE, &mut buf, len).unwrap();
+}
+
+/// Deterministic xorshift, so failures can be reproduced.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+}
+
+/// Send an image over a link that drops `drop_percent` of the frames either way, the way a
+/// sender would: a window of chunks at a time, carrying on from whatever status comes back.
+/// Returns how many frames it took.
+fn simulate(seed: u64, image: &[u8], drop_percent: u64) -> usize {
+    const WINDOW: usize = 8;
+
+    let mut rng = Rng(seed);
+    let mut flash = MockFlash::new();
+    let mut receiver = receiver(&mut flash);
+    let mut next = None;
+    let mut frames = 0;
+
+    loop {
+        assert!(frames < 100 * image.len() / OTA_CHUNK_LEN, "gave up");
+
+        let mut answers = Vec::new();
+        match next {
+            // Don't know where the receiver is at.
+            None => {
+                frames += 1;
+                if !rng.chance(drop_percent) {
+                    answers.push(receiver.on_begin(&begin(image)));
+                }
+            }
+            Some(offset) if offset < image.len() => {
+                for offset in (offset..image.len()).step_by(OTA_CHUNK_LEN).take(WINDOW) {
+                    frames += 1;
+                    if !rng.chance(drop_percent) {
+                        answers.extend(receiver.on_chunk(&chunk(image, offset)));
+                    }
+                }
+                next = Some((offset + WINDOW * OTA_CHUNK_LEN).min(image.len()));
+            }
+            // The commit doubles as asking for the status.
+            Some(_) => {
+                frames += 1;
+                if !rng.chance(drop_percent) {
+                    answers.push(receiver.on_commit());
+                }
+                next = None;
+            }
+        }
+
+        match answers
+            .into_iter()
+            .filter(|_| !rng.chance(drop_percent))
+            .last()
+        {
+            Some(status) if status.state == OtaState::Committed => break,
+            Some(status) => next = Some(status.received as usize),
+            None => {}
+        }
+    }
+
+    let flash = receiver.into_inner();
+    assert_eq!(flash.region(image.len()), image);
+    frames
+}
+
+#[test]
+fn sends_an_image_over_a_perfect_link() {
+    let image = image(50 * OTA_CHUNK_LEN + 17);
+    // Begin, chunks, commit.
+    assert_eq!(simulate(1, &image, 0), 1 + 51 + 1);
+}
+
+#[test]
+fn sends_an_image_over_a_lossy_link() {
+    let image = image(50 * OTA_CHUNK_LEN + 17);
+    for seed in 1..20 {
+        simulate(seed * 7919, &image, 20);
+        simulate(seed * 104729, &image, 50);
+    }
+}
@@ -0,0 +1,228 @@
+use spark_messages::{
+    CONFIRM_TIMEOUT_MS, Capabilities, HANDSHAKE_RETRY_MS, HANDSHAKE_TIMEOUT_MS, Handshake,
+    HandshakeConfirm, InitiatorState, MacAddress, PAIRING_WINDOW_MS, PairingInitiator,
+    PairingResponder, ResponderState,
+};
+
+const REMOTE_A: MacAddress = [0xAA, 0, 0, 0, 0, 1];
+const REMOTE_B: MacAddress = [0xBB, 0, 0, 0, 0, 2];
+const LIGHT: MacAddress = [0x11, 0, 0, 0, 0, 3];
+const CAPABILITIES: Capabilities = Capabilities {
+    strips: 4,
+    leds_per_strip: 8,
+};
+
+fn light() -> PairingResponder {
+    let mut light = PairingResponder::new(LIGHT, CAPABILITIES);
+    light.start(0);
+    light
+}
+
+#[test]
+fn pairs() {
+    let mut remote = PairingInitiator::new(REMOTE_A);
+    let mut light = light();
+
+    let handshake = remote.start(100);
+    let response = light.on_handshake(110, REMOTE_A, &handshake).unwrap();
+    assert_eq!(response.light_mac, LIGHT);
+    assert_eq!(response.capabilities, CAPABILITIES);
+
+    let confirm = remote.on_response(LIGHT, &response).unwrap();
+    assert_eq!(
+        remote.state(),
+        InitiatorState::Paired {
+            light_mac: LIGHT,
+            capabilities: CAPABILITIES
+        }
+    );
+    assert_eq!(light.on_confirm(REMOTE_A, &confirm), Some(REMOTE_A));
+    assert_eq!(light.state(), ResponderState::Idle);
+}
+
+#[test]
+fn remote_retries_then_times_out() {
+    let mut remote = PairingInitiator::new(REMOTE_A);
+    remote.start(0);
+
+    assert_eq!(remote.poll(HANDSHAKE_RETRY_MS - 1), None);
+    assert!(remote.poll(HANDSHAKE_RETRY_MS).is_some());
+    assert_eq!(remote.poll(HANDSHAKE_RETRY_MS + 1), None);
+
+    assert_eq!(remote.poll(HANDSHAKE_TIMEOUT_MS), None);
+    assert_eq!(remote.state(), InitiatorState::TimedOut);
+
+    // Late responses don't pair a remote that already gave up.
+    let mut light = light();
+    let response = light
+        .on_handshake(
+            1,
+            REMOTE_A,
+            &Handshake {
+                remote_mac: REMOTE_A,
+            },
+        )
+        .unwrap();
+    assert_eq!(remote.on_response(LIGHT, &response), None);
+}
+
+#[test]
+fn light_ignores_handshakes_outside_pairing_window() {
+    let handshake = Handshake {
+        remote_mac: REMOTE_A,
+    };
+
+    let mut idle = PairingResponder::new(LIGHT, CAPABILITIES);
+    assert_eq!(idle.on_handshake(0, REMOTE_A, &handshake), None);
+
+    let mut light = light();
+    assert_eq!(light.poll(PAIRING_WINDOW_MS), None);
+    assert_eq!(light.state(), ResponderState::Idle);
+    assert_eq!(
+        light.on_handshake(PAIRING_WINDOW_MS, REMOTE_A, &handshake),
+        None
+    );
+}
+
+#[test]
+fn light_ignores_spoofed_handshakes() {
+    let mut light = light();
+    let handshake = Handshake {
+        remote_mac: REMOTE_A,
+    };
+    assert_eq!(light.on_handshake(0, REMOTE_B, &handshake), None);
+}
+
+#[test]
+fn duplicate_handshakes_get_the_same_answer() {
+    let mut remote = PairingInitiator::new(REMOTE_A);
+    let mut light = light();
+
+    let handshake = remote.start(0);
+    let first = light.on_handshake(0, REMOTE_A, &handshake).unwrap();
+    let handshake = remote.poll(HANDSHAKE_RETRY_MS).unwrap();
+    let second = light
+        .on_handshake(HANDSHAKE_RETRY_MS, REMOTE_A, &handshake)
+        .unwrap();
+    assert_eq!(first, second);
+
+    let confirm = remote.on_response(LIGHT, &second).unwrap();
+    assert_eq!(light.on_confirm(REMOTE_A, &confirm), Some(REMOTE_A));
+    // The remote's handshake was still in flight.
+    assert_eq!(
+        light.on_handshake(HANDSHAKE_RETRY_MS + 1, REMOTE_A, &handshake),
+        None
+    );
+}
+
+#[test]
+fn lost_response_is_sent_again() {
+    let mut remote = PairingInitiator::new(REMOTE_A);
+    let mut light = light();
+
+    let handshake = remote.start(0);
+    light.on_handshake(0, REMOTE_A, &handshake).unwrap();
+
+    assert_eq!(light.poll(HANDSHAKE_RETRY_MS - 1), None);
+    let (dst, response) = light.poll(HANDSHAKE_RETRY_MS).unwrap();
+    assert_eq!(dst, REMOTE_A);
+
+    let confirm = remote.on_response(LIGHT, &response).unwrap();
+    assert_eq!(light.on_confirm(REMOTE_A, &confirm), Some(REMOTE_A));
+}
+
+#[test]
+fn lost_confirm_is_sent_again() {
+    let mut remote = PairingInitiator::new(REMOTE_A);
+    let mut light = light();
+
+    let handshake = remote.start(0);
+    let response = light.on_handshake(0, REMOTE_A, &handshake).unwrap();
+    let _lost = remote.on_response(LIGHT, &response).unwrap();
+
+    let (_, response) = light.poll(HANDSHAKE_RETRY_MS).unwrap();
+    let confirm = remote.on_response(LIGHT, &response).unwrap();
+    assert_eq!(light.on_confirm(REMOTE_A, &confirm), Some(REMOTE_A));
+}
+
+#[test]
+fn confirm_for_another_light_is_ignored() {
+    let mut light = light();
+    light
+        .on_handshake(
+            0,
+            REMOTE_A,
+            &Handshake {
+                remote_mac: REMOTE_A,
+            },
+        )
+        .unwrap();
+
+    let confirm = HandshakeConfirm {
+        light_mac: [0x22, 0, 0, 0, 0, 4],
+    };
+    assert_eq!(light.on_confirm(REMOTE_A, &confirm), None);
+}
+
+#[test]
+fn second_remote_waits_for_the_first() {
+    let mut remote_a = PairingInitiator::new(REMOTE_A);
+    let mut remote_b = PairingInitiator::new(REMOTE_B);
+    let mut light = light();
+
+    let handshake_a = remote_a.start(0);
+    let handshake_b = remote_b.start(0);
+
+    let response = light.on_handshake(0, REMOTE_A, &handshake_a).unwrap();
+    assert_eq!(light.on_handshake(1, REMOTE_B, &handshake_b), None);
+
+    // A confirm from the losing remote doesn't count either.
+    let confirm = HandshakeConfirm { light_mac: LIGHT };
+    assert_eq!(light.on_confirm(REMOTE_B, &confirm), None);
+
+    let confirm = remote_a.on_response(LIGHT, &response).unwrap();
+    assert_eq!(light.on_confirm(REMOTE_A, &confirm), Some(REMOTE_A));
+
+    // Pairing mode is over, so B never gets an answer.
+    let handshake_b = remote_b.poll(HANDSHAKE_RETRY_MS).unwrap();
+    assert_eq!(
+        light.on_handshake(HANDSHAKE_RETRY_MS, REMOTE_B, &handshake_b),
+        None
+    );
+    remote_b.poll(HANDSHAKE_TIMEOUT_MS);
+    assert_eq!(remote_b.state(), InitiatorState::TimedOut);
+}
+
+#[test]
+fn second_remote_pairs_when_the_first_goes_quiet() {
+    let mut remote_b = PairingInitiator::new(REMOTE_B);
+    let mut light = light();
+
+    light
+        .on_handshake(
+            0,
+            REMOTE_A,
+            &Handshake {
+                remote_mac: REMOTE_A,
+            },
+        )
+        .unwrap();
+    let handshake_b = remote_b.start(0);
+    assert_eq!(light.on_handshake(0, REMOTE_B, &handshake_b), None);
+
+    // A never confirms.
+    assert_eq!(light.poll(CONFIRM_TIMEOUT_MS), None);
+    assert_eq!(
+        light.state(),
+        ResponderState::Listening {
+            window_ends_at: PAIRING_WINDOW_MS
+        }
+    );
+
+    let handshake_b = remote_b.poll(CONFIRM_TIMEOUT_MS).unwrap();
+    let response = light
+        .on_handshake(CONFIRM_TIMEOUT_MS, REMOTE_B, &handshake_b)
+        .unwrap();
+    let confirm = remote_b.on_response(LIGHT, &response).unwrap();
+    assert_eq!(light.on_confirm(REMOTE_B, &confirm), Some(REMOTE_B));
+}
@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+
+use spark_messages::{
+    DEDUP_WINDOW_MS, DedupCache, Destination, Key, MAX_FRAME_LEN, MAX_RELAY_HOPS, MacAddress,
+    Message, MessageType, NO_MESSAGE_ID, open, parse_key, sign,
+};
+
+const KEY: Key = parse_key("000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F");
+
+const REMOTE: MacAddress = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+
+const fn light_mac(n: u8) -> MacAddress {
+    [0x24, 0x6F, 0x28, 0x00, 0x00, n]
+}
+
+fn status_request(id: u16) -> Message {
+    Message::new(MessageType::StatusRequest, 1).with_id(id)
+}
+
+/// A light as far as relaying goes.
+struct Light {
+    mac: MacAddress,
+    relay_hops: u8,
+    dedup: DedupCache<4, 16>,
+    /// The hop count of every message the light acted on.
+    received: Vec<u8>,
+}
+
+/// Lights, and who hears whom.
+struct Network {
+    lights: Vec<Light>,
+    /// `(listener, transmitter)` pairs, links only go one way.
+    links: Vec<(MacAddress, MacAddress)>,
+    /// `(light, remote)` pairs sharing a link key. The remote's frames are encrypted then, only
+    /// the lights sharing a key with it can read them.
+    link_keys: Vec<(MacAddress, MacAddress)>,
+}
+
+impl Network {
+    /// Lights `1..=count` in a line starting at the remote, each hearing only its neighbours.
+    fn line(count: u8, relay_hops: u8) -> Self {
+        let mut network = Self::new((1..=count).map(|n| (light_mac(n), relay_hops)));
+        let mut previous = REMOTE;
+        for n in 1..=count {
+            network.link(previous, light_mac(n));
+            previous = light_mac(n);
+        }
+        network
+    }
+
+    fn new(lights: impl IntoIterator<Item = (MacAddress, u8)>) -> Self {
+        let lights = lights
+            .into_iter()
+            .map(|(mac, relay_hops)| Light {
+                mac,
+                relay_hops,
+                dedup: DedupCache::new(DEDUP_WINDOW_MS),
+                received: Vec::new(),
+            })
+            .collect();
+        Self {
+            lights,
+            links: Vec::new(),
+            link_keys: Vec::new(),
+        }
+    }
+
+    /// Let `a` and `b` hear each other.
+    fn link(&mut self, a: MacAddress, b: MacAddress) {
+        self.links.push((a, b));
+        self.links.push((b, a));
+    }
+
+    /// Transmit `message` from `src`, then every copy relayed until the flood dies out. Returns
+    /// how many frames went out.
+    fn flood(&mut self, now: u64, src: MacAddress, message: Message) -> usize {
+        let mut queue = VecDeque::from([(src, message)]);
+        let mut sent = 0;
+        while let Some((transmitter, message)) = queue.pop_front() {
+            sent += 1;
+            let mut buf = [0u8; MAX_FRAME_LEN];
+            let len = message.encode(&mut buf).unwrap().len();
+            let signed = sign(&KEY, &message.origin(transmitter), &mut buf, len).unwrap();
+
+            let encrypted = self
+                .link_keys
+                .iter()
+                .any(|&(_, remote)| remote == transmitter);
+            let listeners = self.lights.iter_mut().filter(|light| {
+                self.links.contains(&(light.mac, transmitter))
+                    && (!encrypted || self.link_keys.contains(&(light.mac, transmitter)))
+            });
+            for light in listeners {
+                let message = open(&KEY, &transmitter, signed).unwrap();
+                if !light
+                    .dedup
+                    .accept(now, message.origin(transmitter), message.id)
+                {
+                    continue;
+                }
+                light.received.push(message.hops);
+                if let Some(relayed) = message.relayed(transmitter, light.relay_hops) {
+                    queue.push_back((light.mac, relayed));
+                }
+            }
+        }
+        sent
+    }
+
+    fn received(&self, n: u8) -> &[u8] {
+        let light = self.lights.iter().find(|light| light.mac == light_mac(n));
+        &light.unwrap().received
+    }
+}
+
+#[test]
+fn relays_name_the_origin_and_count_hops() {
+    let message = status_request(7).with_destination(Destination::Device(3));
+    assert_eq!(message.origin(REMOTE), REMOTE);
+
+    let once = message.relayed(REMOTE, 2).unwrap();
+    assert_eq!(once.hops, 1);
+    assert_eq!(once.origin(light_mac(1)), REMOTE);
+    let twice = once.relayed(light_mac(1), 2).unwrap();
+    assert_eq!(twice.hops, 2);
+    assert_eq!(twice.origin(light_mac(2)), REMOTE);
+    assert_eq!(twice.relayed(light_mac(2), 2), None);
+
+    // Everything else is passed on as is.
+    assert_eq!(
+        Message {
+            hops: 0,
+            origin: None,
+            ..twice
+        },
+        message
+    );
+}
+
+#[test]
+fn never_relays_messages_without_an_id() {
+    let message = status_request(NO_MESSAGE_ID);
+    assert_eq!(message.relayed(REMOTE, MAX_RELAY_HOPS), None);
+}
+
+#[test]
+fn relays_frames_from_keyed_remotes() {
+    // Both light 1 and light 2 hear the remote, but only light 1 shares a link key with it and can
+    // read its frames. Light 2 gets the copy light 1 relays.
+    let mut network = Network::line(3, MAX_RELAY_HOPS);
+    network.link(REMOTE, light_mac(2));
+    network.link_keys.push((light_mac(1), REMOTE));
+    assert_eq!(network.flood(0, REMOTE, status_request(1)), 4);
+    assert_eq!(network.received(1), [0]);
+    assert_eq!(network.received(2), [1]);
+    assert_eq!(network.received(3), [2]);
+}
+
+#[test]
+fn reaches_lights_out_of_range_of_the_remote() {
+    let mut network = Network::line(4, 3);
+    // The last light doesn't relay, the message went through 3 lights already.
+    assert_eq!(network.flood(0, REMOTE, status_request(1)), 4);
+    for n in 1..=4 {
+        assert_eq!(network.received(n), [n - 1], "light {n}");
+    }
+}
+
+#[test]
+fn stops_after_the_configured_hops() {
+    let mut network = Network::line(4, 2);
+    assert_eq!(network.flood(0, REMOTE, status_request(1)), 3);
+    assert_eq!(network.received(3), [2]);
+    assert!(network.received(4).is_empty());
+}
+
+#[test]
+fn lights_not_relaying_end_the_flood() {
+    let mut network = Network::new([
+        (light_mac(1), MAX_RELAY_HOPS),
+        (light_mac(2), 0),
+        (light_mac(3), MAX_RELAY_HOPS),
+    ]);
+    network.link(REMOTE, light_mac(1));
+    network.link(light_mac(1), light_mac(2));
+    network.link(light_mac(2), light_mac(3));
+
+    network.flood(0, REMOTE, status_request(1));
+    assert_eq!(network.received(2), [1]);
+    assert!(network.received(3).is_empty());
+}
+
+#[test]
+fn floods_die_out_in_loops() {
+    // Every light hears every other, and the remote hears them all.
+    let mut network = Network::new((1..=5).map(|n| (light_mac(n), MAX_RELAY_HOPS)));
+    for a in 1..=5 {
+        network.link(REMOTE, light_mac(a));
+        for b in a + 1..=5 {
+            network.link(light_mac(a), light_mac(b));
+        }
+    }
+
+    // The remote's frame, then one copy from each light.
+    assert_eq!(network.flood(0, REMOTE, status_request(1)), 6);
+    for n in 1..=5 {
+        assert_eq!(network.received(n), [0], "light {n}");
+    }
+
+    // New messages get through again.
+    network.flood(10, REMOTE, status_request(2));
+    for n in 1..=5 {
+        assert_eq!(network.received(n), [0, 0], "light {n}");
+    }
+}
+
+#[test]
+fn takes_the_shortest_path_first() {
+    // Light 3 gets the message through light 1, and through lights 1 and 2, and acts on the first
+    // copy. Light 4 only hears light 3.
+    let mut network = Network::new((1..=4).map(|n| (light_mac(n), MAX_RELAY_HOPS)));
+    network.link(REMOTE, light_mac(1));
+    network.link(light_mac(1), light_mac(2));
+    network.link(light_mac(1), light_mac(3));
+    network.link(light_mac(2), light_mac(3));
+    network.link(light_mac(3), light_mac(4));
+
+    assert_eq!(network.flood(0, REMOTE, status_request(1)), 5);
+    assert_eq!(network.received(1), [0]);
+    assert_eq!(network.received(2), [1]);
+    assert_eq!(network.received(3), [1]);
+    assert_eq!(network.received(4), [2]);
+}
+
+#[test]
+fn hears_lights_that_cannot_hear_back() {
+    // Light 2 hears light 1, but light 1 doesn't hear light 2.
+    let mut network = Network::new((1..=2).map(|n| (light_mac(n), MAX_RELAY_HOPS)));
+    network.link(REMOTE, light_mac(1));
+    network.links.push((light_mac(2), light_mac(1)));
+
+    assert_eq!(network.flood(0, REMOTE, status_request(1)), 3);
+    assert_eq!(network.received(1), [0]);
+    assert_eq!(network.received(2), [1]);
+}
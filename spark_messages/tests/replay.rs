@@ -0,0 +1,204 @@
+use spark_messages::{
+    COUNTER_RESERVATION, REPLAY_WINDOW_SIZE, ReceiveWindow, ReplayWindow, SendCounter,
+};
+
+#[test]
+fn accepts_each_counter_once() {
+    let mut window = ReplayWindow::starting_at(0);
+    assert!(!window.accept(0));
+
+    for counter in 1..100 {
+        assert!(window.accept(counter));
+        assert!(!window.accept(counter));
+    }
+}
+
+#[test]
+fn accepts_reordered_counters() {
+    let mut window = ReplayWindow::starting_at(0);
+
+    for counter in [3, 1, 2, 5, 4, 10, 6] {
+        assert!(window.accept(counter), "counter {counter}");
+    }
+    for counter in [1, 2, 3, 4, 5, 6, 10] {
+        assert!(!window.accept(counter), "replayed counter {counter}");
+    }
+    for counter in [7, 8, 9] {
+        assert!(window.accept(counter), "late counter {counter}");
+    }
+    assert_eq!(window.newest(), 10);
+}
+
+#[test]
+fn rejects_counters_that_fell_out_of_the_window() {
+    let mut window = ReplayWindow::starting_at(0);
+    assert!(window.accept(1));
+    assert!(window.accept(1 + REPLAY_WINDOW_SIZE + 1));
+
+    // Never seen, but too old to tell.
+    assert!(!window.check(2));
+    assert!(!window.accept(2));
+    assert!(window.accept(3));
+}
+
+#[test]
+fn rejects_counters_before_the_start() {
+    let mut window = ReplayWindow::starting_at(1000);
+    assert!(!window.accept(999));
+    assert!(!window.accept(1000 - REPLAY_WINDOW_SIZE));
+    assert!(!window.accept(1000));
+    assert!(window.accept(1001));
+}
+
+#[test]
+fn wraps_around() {
+    let mut window = ReplayWindow::starting_at(u32::MAX - 2);
+
+    for counter in [u32::MAX - 1, 1, u32::MAX, 0, 2] {
+        assert!(window.accept(counter), "counter {counter}");
+    }
+    for counter in [u32::MAX - 1, u32::MAX, 0, 1, 2] {
+        assert!(!window.accept(counter), "replayed counter {counter}");
+    }
+    assert_eq!(window.newest(), 2);
+}
+
+#[test]
+fn sender_wraps_around() {
+    let mut sender = SendCounter::resume(u32::MAX - 1);
+    let mut window = ReplayWindow::starting_at(u32::MAX - 1);
+
+    for _ in 0..4 {
+        let (counter, _) = sender.next_counter();
+        assert!(window.accept(counter), "counter {counter}");
+    }
+    assert_eq!(window.newest(), 2);
+}
+
+#[test]
+fn sender_reserves_counters() {
+    let mut sender = SendCounter::resume(0);
+
+    assert_eq!(sender.next_counter(), (1, Some(COUNTER_RESERVATION)));
+    for counter in 2..=COUNTER_RESERVATION {
+        assert_eq!(sender.next_counter(), (counter, None));
+    }
+    assert_eq!(
+        sender.next_counter(),
+        (COUNTER_RESERVATION + 1, Some(2 * COUNTER_RESERVATION))
+    );
+}
+
+#[test]
+fn survives_sender_reboot() {
+    let mut window = ReplayWindow::starting_at(0);
+
+    let mut sender = SendCounter::resume(0);
+    let mut persisted = 0;
+    for _ in 0..10 {
+        let (counter, reservation) = sender.next_counter();
+        if let Some(reservation) = reservation {
+            persisted = reservation;
+        }
+        assert!(window.accept(counter));
+    }
+
+    // Counters after a reboot are ahead of anything sent before.
+    let mut sender = SendCounter::resume(persisted);
+    let (counter, reservation) = sender.next_counter();
+    assert_eq!(reservation, Some(persisted + COUNTER_RESERVATION));
+    assert!(window.accept(counter));
+}
+
+#[test]
+fn survives_receiver_reboot() {
+    let mut window = ReplayWindow::starting_at(0);
+    for counter in [1, 2, 4] {
+        assert!(window.accept(counter));
+    }
+
+    let mut buf = [0u8; 16];
+    let persisted = postcard::to_slice(&window, &mut buf).unwrap();
+    let mut window: ReplayWindow = postcard::from_bytes(persisted).unwrap();
+
+    for counter in [1, 2, 4] {
+        assert!(!window.accept(counter), "replayed counter {counter}");
+    }
+    assert!(window.accept(3));
+    assert!(window.accept(5));
+}
+
+#[test]
+fn receivers_reserve_counters() {
+    let mut window = ReceiveWindow::resume(0);
+    assert_eq!(window.accept(1), (true, Some(COUNTER_RESERVATION)));
+    assert_eq!(window.accept(1), (false, None));
+    assert_eq!(window.accept(COUNTER_RESERVATION), (true, None));
+    assert_eq!(
+        window.accept(COUNTER_RESERVATION + 5),
+        (true, Some(2 * COUNTER_RESERVATION + 4))
+    );
+
+    // Unreserved counters don't move the reservation, the next reserved one does.
+    let mut window = ReceiveWindow::resume(0);
+    assert!(window.accept_unreserved(COUNTER_RESERVATION + 1));
+    assert!(!window.accept_unreserved(COUNTER_RESERVATION + 1));
+    assert_eq!(
+        window.accept(COUNTER_RESERVATION + 2),
+        (true, Some(2 * COUNTER_RESERVATION + 1))
+    );
+}
+
+#[test]
+fn receivers_resume_at_their_reservation() {
+    let mut window = ReceiveWindow::resume(0);
+    let mut buf = [0u8; 8];
+    let mut persisted: &[u8] = &[];
+    for counter in 1..=10 {
+        let (accepted, reservation) = window.accept(counter);
+        assert!(accepted);
+        if reservation.is_some() {
+            persisted = postcard::to_slice(&window, &mut buf).unwrap();
+        }
+    }
+
+    // Everything up to the reservation counts as seen after a reboot.
+    let mut window: ReceiveWindow = postcard::from_bytes(persisted).unwrap();
+    assert_eq!(window.newest(), COUNTER_RESERVATION);
+    for counter in [5, 10, 11, COUNTER_RESERVATION] {
+        assert_eq!(window.accept(counter), (false, None), "counter {counter}");
+    }
+    assert!(window.accept(COUNTER_RESERVATION + 1).0);
+}
+
+#[test]
+fn senders_skip_past_restarted_receivers() {
+    let mut sender = SendCounter::resume(0);
+    let mut window = ReceiveWindow::resume(0);
+    let mut reserved = 0;
+    for _ in 0..10 {
+        let (counter, _) = sender.next_counter();
+        let (accepted, reservation) = window.accept(counter);
+        assert!(accepted);
+        reserved = reservation.unwrap_or(reserved);
+    }
+
+    // The receiver restarts, the sender notices.
+    let mut window = ReceiveWindow::resume(reserved);
+    assert_eq!(
+        sender.skip(),
+        Some(COUNTER_RESERVATION + 10 + COUNTER_RESERVATION - 1)
+    );
+    let (counter, _) = sender.next_counter();
+    assert_eq!(
+        window.accept(counter),
+        (true, Some(counter + COUNTER_RESERVATION - 1))
+    );
+}
+
+#[test]
+fn senders_skip_within_their_reservation() {
+    let mut sender = SendCounter::resume(0);
+    assert_eq!(sender.skip(), Some(2 * COUNTER_RESERVATION - 1));
+    assert_eq!(sender.next_counter(), (COUNTER_RESERVATION + 1, None));
+}
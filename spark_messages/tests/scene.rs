@@ -0,0 +1,114 @@
+use spark_messages::{
+    DEFAULT_ANIMATION, MAX_FRAME_LEN, MAX_SCENES, MAX_STRIPS, Message, MessageType, Pattern, Scene,
+    SceneBook, SceneError, SceneSet, SceneStore, SetAnimation,
+};
+
+fn scene(pattern: Pattern) -> Scene {
+    Scene::new(SetAnimation {
+        pattern,
+        ..DEFAULT_ANIMATION
+    })
+}
+
+#[test]
+fn stores_and_recalls() {
+    let mut book = SceneBook::new();
+    assert!(book.slots().is_empty());
+    assert_eq!(book.recall(0), Err(SceneError::Empty { slot: 0 }));
+
+    let slots = book.store(3, scene(Pattern::Rainbow)).unwrap();
+    assert_eq!(slots.iter().collect::<Vec<_>>(), [3]);
+    book.store(0, scene(Pattern::Breathe)).unwrap();
+    assert_eq!(book.recall(3), Ok(&scene(Pattern::Rainbow)));
+    assert_eq!(book.recall(0), Ok(&scene(Pattern::Breathe)));
+
+    // Storing again replaces the scene.
+    let slots = book.store(3, scene(Pattern::Blink)).unwrap();
+    assert_eq!(slots.len(), 2);
+    assert_eq!(book.recall(3), Ok(&scene(Pattern::Blink)));
+}
+
+#[test]
+fn rejects_missing_slots() {
+    let mut book = SceneBook::new();
+    let slot = MAX_SCENES as u8;
+    assert_eq!(
+        book.store(slot, scene(Pattern::Solid)),
+        Err(SceneError::NoSuchSlot { slot })
+    );
+    assert_eq!(book.recall(slot), Err(SceneError::NoSuchSlot { slot }));
+    assert_eq!(
+        book.recall(u8::MAX),
+        Err(SceneError::NoSuchSlot { slot: u8::MAX })
+    );
+    assert!(book.slots().is_empty());
+}
+
+#[test]
+fn strips_default_to_full_level() {
+    let mut scene = scene(Pattern::Solid);
+    assert_eq!(scene.strip_level(0), 255);
+    scene.strip_levels.extend_from_slice(&[0, 128]).unwrap();
+    assert_eq!(scene.strip_level(0), 0);
+    assert_eq!(scene.strip_level(1), 128);
+    assert_eq!(scene.strip_level(MAX_STRIPS), 255);
+}
+
+#[test]
+fn scene_sets() {
+    let mut set = SceneSet::new();
+    assert!(set.is_empty());
+    set.insert(7);
+    set.insert(0);
+    set.insert(7);
+    assert_eq!(set.len(), 2);
+    assert!(set.contains(0) && set.contains(7));
+    assert!(!set.contains(1));
+    assert!(!set.contains(u8::MAX));
+    assert_eq!(set.iter().collect::<Vec<_>>(), [0, 7]);
+}
+
+#[test]
+fn books_survive_storage() {
+    let mut book = SceneBook::new();
+    book.store(1, scene(Pattern::HueSweep)).unwrap();
+    let mut buf = [0u8; 512];
+    let bytes = postcard::to_slice(&book, &mut buf).unwrap();
+    assert_eq!(postcard::from_bytes::<SceneBook>(bytes).unwrap(), book);
+}
+
+#[test]
+fn messages_round_trip() {
+    let full = Scene {
+        animation: SetAnimation {
+            duration_ms: Some(u32::MAX),
+            ..DEFAULT_ANIMATION
+        },
+        strip_levels: heapless::Vec::from_slice(&[255; MAX_STRIPS]).unwrap(),
+    };
+    let messages = [
+        MessageType::SceneStore(SceneStore {
+            slot: u8::MAX,
+            scene: Some(full),
+        }),
+        MessageType::SceneStore(SceneStore {
+            slot: 0,
+            scene: None,
+        }),
+        MessageType::SceneRecall { slot: u8::MAX },
+        MessageType::SceneList,
+        MessageType::SceneReply(Ok((0..MAX_SCENES as u8).collect())),
+        MessageType::SceneReply(Err(SceneError::NoSuchSlot { slot: u8::MAX })),
+        MessageType::SceneReply(Err(SceneError::Empty { slot: 0 })),
+        MessageType::SceneReply(Err(SceneError::NothingShowing)),
+    ];
+
+    for message_type in messages {
+        let message = Message::new(message_type, u32::MAX);
+        assert_eq!(message.protocol_version, 1);
+
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let frame = postcard::to_slice(&message, &mut buf).unwrap();
+        assert_eq!(Message::decode(frame).unwrap(), message);
+    }
+}
@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+
+use spark_messages::{
+    ButtonEventType, ButtonNumber, DecodeError, Destination, EncodeError, FRAGMENT_LEN, Fragment,
+    FrameError, FrameReader, MAX_SERIAL_FRAME_LEN, Message, MessageType, encode_frame,
+};
+
+/// A byte stream that hands out what was written in reads of varying sizes, like a serial port.
+struct Pipe {
+    bytes: VecDeque<u8>,
+    rng: u32,
+}
+
+impl Pipe {
+    fn new(seed: u32) -> Self {
+        Self {
+            bytes: VecDeque::new(),
+            rng: seed,
+        }
+    }
+
+    fn random(&mut self) -> u32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes);
+    }
+
+    fn write_message(&mut self, message: &Message) {
+        let mut buf = [0u8; MAX_SERIAL_FRAME_LEN];
+        let frame = encode_frame(message, &mut buf).unwrap();
+        self.write(frame);
+    }
+
+    /// Up to 16 bytes of garbage, zeros included.
+    fn write_noise(&mut self) {
+        let len = self.random() % 17;
+        for _ in 0..len {
+            let byte = match self.random() % 4 {
+                0 => 0,
+                _ => self.random() as u8,
+            };
+            self.bytes.push_back(byte);
+        }
+    }
+
+    /// Between 1 and 20 bytes, fewer if that's all there is.
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = (1 + self.random() as usize % 20)
+            .min(buf.len())
+            .min(self.bytes.len());
+        for (slot, byte) in buf.iter_mut().zip(self.bytes.drain(..len)) {
+            *slot = byte;
+        }
+        len
+    }
+
+    /// Read until the pipe is empty, returning what the reader made of it.
+    fn read_all(&mut self, reader: &mut FrameReader) -> Vec<Result<Message, FrameError>> {
+        let mut results = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            let len = self.read(&mut buf);
+            if len == 0 {
+                return results;
+            }
+            results.extend(reader.feed(&buf[..len]));
+        }
+    }
+}
+
+fn messages() -> Vec<Message> {
+    let mut messages = vec![
+        Message::new(MessageType::StatusRequest, 0),
+        Message::new(
+            MessageType::ButtonEvent {
+                button_number: ButtonNumber::Button2,
+                event_type: ButtonEventType::ShortPress { count: 3 },
+            },
+            1,
+        )
+        .with_destination(Destination::Groups(0b101)),
+        // Lots of zeros to stuff, and longer than a COBS block.
+        Message::new(
+            MessageType::Fragment(Fragment {
+                transfer: 0,
+                index: 0,
+                count: 1,
+                data: heapless::Vec::from_slice(&[0; FRAGMENT_LEN]).unwrap(),
+            }),
+            0,
+        ),
+    ];
+    for counter in 0..20 {
+        messages.push(Message::new(
+            MessageType::StatusRequest,
+            counter * 1_000_003,
+        ));
+    }
+    messages
+}
+
+#[test]
+fn round_trips() {
+    let mut reader = FrameReader::new();
+    for message in messages() {
+        let mut buf = [0u8; MAX_SERIAL_FRAME_LEN];
+        let frame = encode_frame(&message, &mut buf).unwrap();
+
+        // Zeros only delimit frames.
+        assert_eq!(frame.first(), Some(&0));
+        assert_eq!(frame.last(), Some(&0));
+        assert!(!frame[1..frame.len() - 1].contains(&0));
+
+        let results: Vec<_> = reader.feed(frame).collect();
+        assert_eq!(results, [Ok(message)]);
+    }
+}
+
+#[test]
+fn survives_split_reads() {
+    for seed in 1..50 {
+        let mut pipe = Pipe::new(seed);
+        for message in messages() {
+            pipe.write_message(&message);
+        }
+
+        let mut reader = FrameReader::new();
+        let received = pipe.read_all(&mut reader);
+        let expected: Vec<_> = messages().into_iter().map(Ok).collect();
+        assert_eq!(received, expected, "seed {seed}");
+    }
+}
+
+#[test]
+fn survives_noise_between_frames() {
+    for seed in 1..50 {
+        let mut pipe = Pipe::new(seed);
+        for message in messages() {
+            pipe.write_noise();
+            pipe.write_message(&message);
+        }
+        pipe.write_noise();
+
+        let mut reader = FrameReader::new();
+        let received: Vec<_> = pipe
+            .read_all(&mut reader)
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect();
+        assert_eq!(received, messages(), "seed {seed}");
+    }
+}
+
+#[test]
+fn survives_log_lines_between_frames() {
+    let mut pipe = Pipe::new(7);
+    pipe.write(b"got message: StatusRequest\r\n");
+    pipe.write_message(&messages()[1]);
+    pipe.write(b"failed to send\r\n");
+    pipe.write_message(&messages()[0]);
+
+    let mut reader = FrameReader::new();
+    let received = pipe.read_all(&mut reader);
+    // The log lines end up as frames of their own, which don't check out.
+    assert_eq!(received.len(), 4);
+    assert!(received[0].is_err() && received[2].is_err());
+    assert_eq!(received[1], Ok(messages()[1].clone()));
+    assert_eq!(received[3], Ok(messages()[0].clone()));
+}
+
+#[test]
+fn detects_corruption() {
+    let message = &messages()[1];
+    let mut buf = [0u8; MAX_SERIAL_FRAME_LEN];
+    let len = encode_frame(message, &mut buf).unwrap().len();
+
+    for i in 1..len - 1 {
+        for flip in [0x01, 0x80, 0xFF] {
+            let mut frame = buf[..len].to_vec();
+            frame[i] ^= flip;
+
+            let mut reader = FrameReader::new();
+            let results: Vec<_> = reader.feed(&frame).collect();
+            assert!(!results.is_empty(), "byte {i} ^ {flip:#x}");
+            assert!(
+                results.iter().all(Result::is_err),
+                "byte {i} ^ {flip:#x}: {results:?}"
+            );
+
+            // And the next frame gets through.
+            let results: Vec<_> = reader.feed(&buf[..len]).collect();
+            assert_eq!(results, [Ok(message.clone())]);
+        }
+    }
+}
+
+#[test]
+fn resynchronises_after_overflow() {
+    let mut pipe = Pipe::new(3);
+    pipe.write(&[0x55; 3 * MAX_SERIAL_FRAME_LEN]);
+    pipe.write_message(&messages()[2]);
+
+    let mut reader = FrameReader::new();
+    assert_eq!(
+        pipe.read_all(&mut reader),
+        [Err(FrameError::Overflow), Ok(messages()[2].clone())]
+    );
+}
+
+#[test]
+fn reports_undecodable_messages() {
+    let mut reader = FrameReader::new();
+
+    // Too short to hold a CRC. Empty frames are skipped.
+    let results: Vec<_> = reader.feed(&[0, 0, 2, 1, 0]).collect();
+    assert_eq!(results, [Err(FrameError::Malformed)]);
+
+    // Intact, but message type 100 doesn't exist.
+    let body = [9, 100];
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(&body);
+    let mut unstuffed = body.to_vec();
+    unstuffed.extend(crc.to_le_bytes());
+    let mut frame = vec![0; 16];
+    let len = cobs::encode(&unstuffed, &mut frame[1..]);
+    frame.truncate(len + 2);
+
+    let results: Vec<_> = reader.feed(&frame).collect();
+    assert_eq!(
+        results,
+        [Err(FrameError::Decode(DecodeError::UnknownType {
+            protocol_version: 9
+        }))]
+    );
+}
+
+#[test]
+fn needs_room_for_the_frame() {
+    let message = &messages()[2];
+    let mut buf = [0u8; MAX_SERIAL_FRAME_LEN];
+    let len = encode_frame(message, &mut buf).unwrap().len();
+
+    for short in [0, 1, len / 2, len - 1] {
+        assert_eq!(
+            encode_frame(message, &mut buf[..short]),
+            Err(EncodeError::BufferFull)
+        );
+    }
+    assert!(encode_frame(message, &mut buf[..len]).is_ok());
+}
@@ -0,0 +1,166 @@
+use spark_messages::{
+    MAX_FRAME_LEN, MAX_FRAME_PIXELS, Message, MessageType, PixelBuffer, PixelFrame,
+    PixelFrameError, STREAM_TIMEOUT_MS,
+};
+
+const RED: [u8; 3] = [0xFF, 0, 0];
+const GREEN: [u8; 3] = [0, 0xFF, 0];
+const BLUE: [u8; 3] = [0, 0, 0xFF];
+const DARK: [u8; 3] = [0; 3];
+
+fn pixels(strip: u8, offset: u16, pixels: &[[u8; 3]], frame: Option<u32>) -> PixelFrame {
+    PixelFrame {
+        strip,
+        offset,
+        pixels: heapless::Vec::from_slice(pixels).unwrap(),
+        frame,
+    }
+}
+
+fn commit(frame: u32) -> PixelFrame {
+    pixels(0, 0, &[], Some(frame))
+}
+
+#[test]
+fn shows_unnumbered_pixels_right_away() {
+    let mut buffer = PixelBuffer::<2, 4>::new();
+    assert_eq!(
+        buffer.on_frame(0, &pixels(1, 1, &[RED, GREEN], None)),
+        Ok(true)
+    );
+    assert_eq!(buffer.shown(), &[[DARK; 4], [DARK, RED, GREEN, DARK]]);
+}
+
+#[test]
+fn shows_numbered_frames_once_committed() {
+    let mut buffer = PixelBuffer::<2, 4>::new();
+    assert_eq!(
+        buffer.on_frame(0, &pixels(0, 0, &[RED; 4], Some(1))),
+        Ok(false)
+    );
+    assert_eq!(
+        buffer.on_frame(1, &pixels(1, 2, &[BLUE; 2], Some(1))),
+        Ok(false)
+    );
+    assert_eq!(buffer.shown(), &[[DARK; 4]; 2]);
+
+    assert_eq!(buffer.on_frame(2, &commit(1)), Ok(true));
+    assert_eq!(buffer.shown(), &[[RED; 4], [DARK, DARK, BLUE, BLUE]]);
+
+    // The next frame starts from what shows.
+    buffer
+        .on_frame(3, &pixels(0, 0, &[GREEN], Some(2)))
+        .unwrap();
+    buffer.on_frame(4, &commit(2)).unwrap();
+    assert_eq!(
+        buffer.shown(),
+        &[[GREEN, RED, RED, RED], [DARK, DARK, BLUE, BLUE]]
+    );
+}
+
+#[test]
+fn drops_frames_never_committed() {
+    let mut buffer = PixelBuffer::<1, 2>::new();
+    buffer.on_frame(0, &pixels(0, 0, &[RED], Some(1))).unwrap();
+    // Frame 1's commit got lost.
+    buffer
+        .on_frame(1, &pixels(0, 1, &[GREEN], Some(2)))
+        .unwrap();
+    buffer.on_frame(2, &commit(2)).unwrap();
+    assert_eq!(buffer.shown(), &[[DARK, GREEN]]);
+}
+
+#[test]
+fn ignores_late_frames() {
+    let mut buffer = PixelBuffer::<1, 2>::new();
+    buffer.on_frame(0, &pixels(0, 0, &[RED], Some(5))).unwrap();
+    assert_eq!(
+        buffer.on_frame(1, &pixels(0, 0, &[GREEN], Some(4))),
+        Err(PixelFrameError::Late)
+    );
+    buffer.on_frame(2, &commit(5)).unwrap();
+    assert_eq!(
+        buffer.on_frame(3, &pixels(0, 1, &[GREEN], Some(5))),
+        Err(PixelFrameError::Late)
+    );
+    assert_eq!(buffer.on_frame(4, &commit(5)), Err(PixelFrameError::Late));
+    assert_eq!(buffer.shown(), &[[RED, DARK]]);
+}
+
+#[test]
+fn frame_numbers_wrap_around() {
+    let mut buffer = PixelBuffer::<1, 1>::new();
+    buffer.on_frame(0, &commit(u32::MAX)).unwrap();
+    buffer.on_frame(1, &pixels(0, 0, &[BLUE], Some(0))).unwrap();
+    assert_eq!(buffer.on_frame(2, &commit(0)), Ok(true));
+    assert_eq!(buffer.shown(), &[[BLUE]]);
+}
+
+#[test]
+fn unnumbered_pixels_carry_over_into_the_staged_frame() {
+    let mut buffer = PixelBuffer::<1, 2>::new();
+    buffer.on_frame(0, &pixels(0, 0, &[RED], Some(1))).unwrap();
+    buffer.on_frame(1, &pixels(0, 1, &[BLUE], None)).unwrap();
+    assert_eq!(buffer.shown(), &[[DARK, BLUE]]);
+    buffer.on_frame(2, &commit(1)).unwrap();
+    assert_eq!(buffer.shown(), &[[RED, BLUE]]);
+}
+
+#[test]
+fn rejects_pixels_the_light_does_not_have() {
+    let mut buffer = PixelBuffer::<2, 4>::new();
+    assert_eq!(
+        buffer.on_frame(0, &pixels(2, 0, &[RED], None)),
+        Err(PixelFrameError::NoSuchStrip { strips: 2 })
+    );
+    assert_eq!(
+        buffer.on_frame(0, &pixels(0, 3, &[RED, RED], None)),
+        Err(PixelFrameError::OutOfRange { leds: 4 })
+    );
+    assert_eq!(
+        buffer.on_frame(0, &pixels(0, u16::MAX, &[], None)),
+        Err(PixelFrameError::OutOfRange { leds: 4 })
+    );
+    assert_eq!(buffer.shown(), &[[DARK; 4]; 2]);
+}
+
+#[test]
+fn stops_streaming_after_the_timeout() {
+    let mut buffer = PixelBuffer::<1, 1>::new();
+    assert!(!buffer.is_streaming(0));
+    buffer
+        .on_frame(100, &pixels(0, 0, &[RED], Some(9)))
+        .unwrap();
+    assert!(buffer.is_streaming(100 + STREAM_TIMEOUT_MS - 1));
+    assert!(!buffer.is_streaming(100 + STREAM_TIMEOUT_MS));
+
+    // A host starting over counts frames from the start again, and nothing staged before shows.
+    let later = 100 + STREAM_TIMEOUT_MS;
+    buffer
+        .on_frame(later, &pixels(0, 0, &[GREEN], Some(0)))
+        .unwrap();
+    assert!(buffer.is_streaming(later));
+    buffer.on_frame(later, &commit(0)).unwrap();
+    assert_eq!(buffer.shown(), &[[GREEN]]);
+}
+
+#[test]
+fn messages_round_trip() {
+    let message_types = [
+        MessageType::PixelFrame(pixels(
+            u8::MAX,
+            u16::MAX,
+            &[[0xFF; 3]; MAX_FRAME_PIXELS],
+            Some(u32::MAX),
+        )),
+        MessageType::PixelFrame(commit(0)),
+        MessageType::PixelFrame(pixels(3, 0, &[RED, GREEN, BLUE], None)),
+    ];
+    for message_type in message_types {
+        let message = Message::new(message_type, u32::MAX);
+        assert_eq!(message.protocol_version, 1);
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let frame = postcard::to_slice(&message, &mut buf).unwrap();
+        assert_eq!(Message::decode(frame), Ok(message));
+    }
+}
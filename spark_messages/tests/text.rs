@@ -0,0 +1,518 @@
+use core::fmt::Write;
+
+use spark_messages::{
+    Address, Announce, ButtonEventType, ButtonNumber, ButtonSequence, Capabilities, Color,
+    ConfigEntry, ConfigError, ConfigKey, ConfigType, ConfigValue, DEFAULT_ANIMATION, Destination,
+    ErrorCode, FRAGMENT_LEN, FirmwareVersion, Fragment, Handshake, HandshakeConfirm,
+    HandshakeResponse, Heartbeat, KeyConfirm, KeyShare, LightMode, LightStatus, LinkStats,
+    MAX_FRAME_PIXELS, MAX_SEQUENCE_LEN, MAX_STRIPS, Message, MessageType, OTA_CHUNK_LEN, OtaBegin,
+    OtaChunk, OtaError, OtaState, OtaStatus, ParseError, Pattern, PixelFrame, ProtocolVersions,
+    ResetReason, Scene, SceneError, SceneStore, SetAnimation, TimeRequest, TimeResponse,
+};
+
+use ButtonNumber::{Button1, Button2, Button3, Button4};
+
+const MAC: [u8; 6] = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+
+fn parse(line: &str) -> Result<Message, ParseError> {
+    line.parse()
+}
+
+fn heartbeat() -> Heartbeat {
+    Heartbeat {
+        seq: 41,
+        uptime_ms: 410_000,
+        reset_reason: ResetReason::Watchdog,
+    }
+}
+
+/// At least one of every message type, with every optional field both set and not.
+fn message_types() -> Vec<MessageType> {
+    vec![
+        MessageType::ButtonEvent {
+            button_number: Button2,
+            event_type: ButtonEventType::ShortPress { count: 3 },
+        },
+        MessageType::ButtonEvent {
+            button_number: Button4,
+            event_type: ButtonEventType::LongPress,
+        },
+        MessageType::ButtonEvent {
+            button_number: Button1,
+            event_type: ButtonEventType::Pressed,
+        },
+        MessageType::ButtonEvent {
+            button_number: Button1,
+            event_type: ButtonEventType::Released,
+        },
+        MessageType::ButtonEvent {
+            button_number: Button3,
+            event_type: ButtonEventType::HoldRepeat { elapsed_ms: 1_200 },
+        },
+        MessageType::Handshake(Handshake { remote_mac: MAC }),
+        MessageType::HandshakeResponse(HandshakeResponse {
+            light_mac: MAC,
+            capabilities: Capabilities {
+                strips: 4,
+                leds_per_strip: 300,
+            },
+        }),
+        MessageType::HandshakeConfirm(HandshakeConfirm { light_mac: MAC }),
+        MessageType::Ack { counter: u32::MAX },
+        MessageType::Fragment(Fragment {
+            transfer: 7,
+            index: 1,
+            count: 2,
+            data: (0..FRAGMENT_LEN).map(|i| i as u8).collect(),
+        }),
+        MessageType::Fragment(Fragment {
+            transfer: 0,
+            index: 0,
+            count: 1,
+            data: heapless::Vec::new(),
+        }),
+        MessageType::SetAnimation(SetAnimation {
+            pattern: Pattern::HueSweep,
+            color: Color::Rgb {
+                r: 255,
+                g: 128,
+                b: 0,
+            },
+            speed: 40,
+            brightness: 200,
+            duration_ms: Some(5_000),
+        }),
+        MessageType::SetAnimation(DEFAULT_ANIMATION),
+        MessageType::StatusRequest,
+        MessageType::LightStatus(LightStatus {
+            mode: LightMode::Timed,
+            pattern: Pattern::Breathe,
+            brightness: 25,
+            uptime_ms: u64::MAX,
+            firmware_version: FirmwareVersion::parse("1.2.3"),
+            last_error: Some(ErrorCode::SendFailed),
+        }),
+        MessageType::LightStatus(LightStatus {
+            mode: LightMode::Streaming,
+            pattern: Pattern::Off,
+            brightness: 255,
+            uptime_ms: 1,
+            firmware_version: FirmwareVersion::parse("0.1.0"),
+            last_error: None,
+        }),
+        MessageType::LightStatus(LightStatus {
+            mode: LightMode::Off,
+            pattern: Pattern::Off,
+            brightness: 0,
+            uptime_ms: 0,
+            firmware_version: FirmwareVersion::parse("0.1.0"),
+            last_error: None,
+        }),
+        MessageType::SetAddress(Address {
+            id: 12,
+            groups: 0b101,
+        }),
+        MessageType::TimeRequest(TimeRequest { origin_us: 123 }),
+        MessageType::TimeResponse(TimeResponse {
+            origin_us: 123,
+            receive_us: 1_000_456,
+            transmit_us: 1_000_789,
+        }),
+        MessageType::OtaBegin(OtaBegin {
+            size: 1 << 20,
+            sha256: core::array::from_fn(|i| 0xF0 | i as u8),
+        }),
+        MessageType::OtaChunk(OtaChunk {
+            offset: 4096,
+            data: heapless::Vec::from_slice(&[0xAB; OTA_CHUNK_LEN]).unwrap(),
+        }),
+        MessageType::OtaStatus(OtaStatus {
+            state: OtaState::Receiving,
+            received: 4096,
+            error: Some(OtaError::HashMismatch),
+        }),
+        MessageType::OtaStatus(OtaStatus {
+            state: OtaState::Idle,
+            received: 0,
+            error: None,
+        }),
+        MessageType::OtaCommit,
+        MessageType::ConfigGet(ConfigKey::AnimationTimeoutMs),
+        MessageType::ConfigSet(ConfigEntry {
+            key: ConfigKey::LedCount,
+            value: ConfigValue::U16(300),
+        }),
+        // Of the wrong type, such as from a newer revision.
+        MessageType::ConfigSet(ConfigEntry {
+            key: ConfigKey::Brightness,
+            value: ConfigValue::U32(70_000),
+        }),
+        MessageType::ConfigList,
+        MessageType::ConfigReply(Ok(ConfigEntry {
+            key: ConfigKey::Channel,
+            value: ConfigValue::U8(6),
+        })),
+        MessageType::ConfigReply(Err(ConfigError::WrongType {
+            key: ConfigKey::Brightness,
+            expected: ConfigType::U8,
+        })),
+        MessageType::ConfigReply(Err(ConfigError::OutOfRange {
+            key: ConfigKey::LedCount,
+            min: ConfigValue::U16(1),
+            max: ConfigValue::U16(1024),
+        })),
+        MessageType::ConfigReply(Err(ConfigError::ReadOnly {
+            key: ConfigKey::Channel,
+        })),
+        MessageType::Discover,
+        MessageType::Announce(Announce {
+            id: 3,
+            firmware_version: FirmwareVersion::parse("2.0.1"),
+            protocol_versions: ProtocolVersions::SUPPORTED,
+            strips: heapless::Vec::from_slice(&[8; MAX_STRIPS]).unwrap(),
+            patterns: [Pattern::Solid, Pattern::Rainbow].into_iter().collect(),
+        }),
+        MessageType::Announce(Announce {
+            id: 4,
+            firmware_version: FirmwareVersion::parse("2.0.1"),
+            protocol_versions: ProtocolVersions {
+                oldest: 1,
+                newest: 1,
+            },
+            strips: heapless::Vec::new(),
+            patterns: [].into_iter().collect(),
+        }),
+        MessageType::Heartbeat(heartbeat()),
+        MessageType::LinkStatsRequest { peer: MAC },
+        MessageType::LinkStats {
+            peer: MAC,
+            stats: None,
+        },
+        MessageType::LinkStats {
+            peer: MAC,
+            stats: Some(LinkStats {
+                rssi: -61,
+                rssi_avg: -58,
+                since_last_seen_ms: 1_200,
+                heartbeats: 40,
+                heartbeats_lost: 2,
+                restarts: 1,
+                last_heartbeat: Some(heartbeat()),
+            }),
+        },
+        MessageType::LinkStats {
+            peer: MAC,
+            stats: Some(LinkStats {
+                rssi: 0,
+                rssi_avg: 0,
+                since_last_seen_ms: 0,
+                heartbeats: 0,
+                heartbeats_lost: 0,
+                restarts: 0,
+                last_heartbeat: None,
+            }),
+        },
+        MessageType::ButtonChord([Button1, Button3].into_iter().collect()),
+        MessageType::ButtonChord([].into_iter().collect()),
+        MessageType::ButtonSequence(ButtonSequence {
+            buttons: heapless::Vec::from_slice(&[Button4; MAX_SEQUENCE_LEN]).unwrap(),
+        }),
+        MessageType::SceneStore(SceneStore {
+            slot: 7,
+            scene: Some(Scene {
+                animation: SetAnimation {
+                    duration_ms: Some(60_000),
+                    ..DEFAULT_ANIMATION
+                },
+                strip_levels: heapless::Vec::from_slice(&[255, 0, 128]).unwrap(),
+            }),
+        }),
+        MessageType::SceneStore(SceneStore {
+            slot: 0,
+            scene: Some(Scene::new(DEFAULT_ANIMATION)),
+        }),
+        MessageType::SceneStore(SceneStore {
+            slot: 2,
+            scene: None,
+        }),
+        MessageType::SceneRecall { slot: 255 },
+        MessageType::SceneList,
+        MessageType::SceneReply(Ok([0, 3, 7].into_iter().collect())),
+        MessageType::SceneReply(Ok([].into_iter().collect())),
+        MessageType::SceneReply(Err(SceneError::NoSuchSlot { slot: 9 })),
+        MessageType::SceneReply(Err(SceneError::Empty { slot: 1 })),
+        MessageType::SceneReply(Err(SceneError::NothingShowing)),
+        MessageType::KeyShare(KeyShare {
+            public_key: [0xAB; 32],
+        }),
+        MessageType::KeyConfirm(KeyConfirm { tag: [0; 32] }),
+        MessageType::PixelFrame(PixelFrame {
+            strip: u8::MAX,
+            offset: u16::MAX,
+            pixels: heapless::Vec::from_slice(&[[0xFF, 0x80, 0]; MAX_FRAME_PIXELS]).unwrap(),
+            frame: Some(u32::MAX),
+        }),
+        MessageType::PixelFrame(PixelFrame {
+            strip: 0,
+            offset: 0,
+            pixels: heapless::Vec::new(),
+            frame: Some(0),
+        }),
+        MessageType::PixelFrame(PixelFrame {
+            strip: 1,
+            offset: 2,
+            pixels: heapless::Vec::from_slice(&[[1, 2, 3]]).unwrap(),
+            frame: None,
+        }),
+    ]
+}
+
+#[test]
+fn messages_round_trip() {
+    let destinations = [
+        Destination::All,
+        Destination::Device(u16::MAX),
+        Destination::Groups(0x8000_0001),
+    ];
+    for message_type in message_types() {
+        let options = [0, 1, u32::MAX]
+            .into_iter()
+            .zip(destinations)
+            .zip([0, 1, u16::MAX])
+            .zip([(0, None), (1, Some(MAC)), (u8::MAX, Some([0xFF; 6]))]);
+        for (((counter, destination), id), (hops, origin)) in options {
+            let message = Message {
+                hops,
+                origin,
+                ..Message::new(message_type.clone(), counter)
+                    .with_destination(destination)
+                    .with_id(id)
+            };
+            let text = message.to_string();
+            assert!(!text.contains(['\n', '\r']), "{text}");
+            assert_eq!(parse(&text), Ok(message), "{text}");
+        }
+    }
+}
+
+#[test]
+fn formats_without_allocating() {
+    let message = Message::new(
+        MessageType::ButtonEvent {
+            button_number: Button2,
+            event_type: ButtonEventType::ShortPress { count: 3 },
+        },
+        9,
+    )
+    .with_destination(Destination::Groups(0b101))
+    .with_id(77);
+
+    let mut line = heapless::String::<64>::new();
+    write!(line, "{message}").unwrap();
+    assert_eq!(line, "button 2 short 3 to=groups:0x5 counter=9 id=77");
+    assert_eq!(parse(&line), Ok(message));
+}
+
+#[test]
+fn formats_relayed_messages() {
+    let message = Message::new(MessageType::StatusRequest, 4)
+        .with_id(5)
+        .relayed(MAC, 3)
+        .unwrap()
+        .relayed([0xFF; 6], 3)
+        .unwrap();
+    let line = "status counter=4 id=5 hops=2 from=12:34:56:78:9a:bc";
+    assert_eq!(message.to_string(), line);
+    assert_eq!(parse(line), Ok(message));
+}
+
+#[test]
+fn formats_readably() {
+    let cases = [
+        (
+            MessageType::SetAnimation(SetAnimation {
+                pattern: Pattern::Rainbow,
+                color: Color::Hsv {
+                    hue: 10,
+                    sat: 20,
+                    val: 30,
+                },
+                speed: 40,
+                brightness: 50,
+                duration_ms: None,
+            }),
+            "anim rainbow color=hsv:10,20,30 speed=40 brightness=50",
+        ),
+        (
+            MessageType::Handshake(Handshake { remote_mac: MAC }),
+            "handshake 12:34:56:78:9a:bc",
+        ),
+        (
+            MessageType::ConfigSet(ConfigEntry {
+                key: ConfigKey::Brightness,
+                value: ConfigValue::U8(40),
+            }),
+            "config-set brightness 40",
+        ),
+        (
+            MessageType::ConfigSet(ConfigEntry {
+                key: ConfigKey::Brightness,
+                value: ConfigValue::U16(40),
+            }),
+            "config-set brightness u16:40",
+        ),
+        (
+            MessageType::ButtonSequence(ButtonSequence {
+                buttons: heapless::Vec::from_slice(&[Button2, Button2, Button1]).unwrap(),
+            }),
+            "sequence 2,2,1",
+        ),
+        (
+            MessageType::Announce(Announce {
+                id: 3,
+                firmware_version: FirmwareVersion::parse("2.0.1"),
+                protocol_versions: ProtocolVersions::SUPPORTED,
+                strips: heapless::Vec::from_slice(&[8, 300]).unwrap(),
+                patterns: [Pattern::Rainbow, Pattern::Solid].into_iter().collect(),
+            }),
+            "announce id=3 firmware=2.0.1 protocols=0-1 strips=8,300 patterns=solid,rainbow",
+        ),
+        (
+            MessageType::SceneStore(SceneStore {
+                slot: 1,
+                scene: Some(Scene {
+                    animation: SetAnimation {
+                        pattern: Pattern::Breathe,
+                        ..DEFAULT_ANIMATION
+                    },
+                    strip_levels: heapless::Vec::from_slice(&[255, 64]).unwrap(),
+                }),
+            }),
+            "scene-store 1 breathe color=hsv:0,255,255 speed=64 brightness=25 levels=ff40",
+        ),
+        (
+            MessageType::SceneReply(Ok([4, 1].into_iter().collect())),
+            "scene-reply 1,4",
+        ),
+        (
+            MessageType::PixelFrame(PixelFrame {
+                strip: 2,
+                offset: 4,
+                pixels: heapless::Vec::from_slice(&[[0xFF, 0x80, 0], [0, 0, 0]]).unwrap(),
+                frame: Some(7),
+            }),
+            "pixels 2 offset=4 data=ff8000000000 frame=7",
+        ),
+    ];
+    for (message_type, text) in cases {
+        assert_eq!(Message::new(message_type, 0).to_string(), text);
+    }
+}
+
+#[test]
+fn parses_hand_written_lines() {
+    // Options in any order, extra spaces, and hexadecimal.
+    assert_eq!(
+        parse("  anim   blink brightness=0x80 counter=5  speed=200 "),
+        Ok(Message::new(
+            MessageType::SetAnimation(SetAnimation {
+                pattern: Pattern::Blink,
+                speed: 200,
+                brightness: 128,
+                ..DEFAULT_ANIMATION
+            }),
+            5
+        ))
+    );
+    assert_eq!(
+        parse("anim rainbow speed=40"),
+        Ok(Message::new(
+            MessageType::SetAnimation(SetAnimation {
+                pattern: Pattern::Rainbow,
+                speed: 40,
+                ..DEFAULT_ANIMATION
+            }),
+            0
+        ))
+    );
+    assert_eq!(
+        parse("config-set led-count 0x12c to=device:3"),
+        Ok(Message::new(
+            MessageType::ConfigSet(ConfigEntry {
+                key: ConfigKey::LedCount,
+                value: ConfigValue::U16(300),
+            }),
+            0
+        )
+        .with_destination(Destination::Device(3)))
+    );
+    assert_eq!(
+        parse("chord 4,1"),
+        Ok(Message::new(
+            MessageType::ButtonChord([Button1, Button4].into_iter().collect()),
+            0
+        ))
+    );
+    assert_eq!(
+        parse("fragment transfer=1 index=0 count=1 data=00FFaa"),
+        Ok(Message::new(
+            MessageType::Fragment(Fragment {
+                transfer: 1,
+                index: 0,
+                count: 1,
+                data: heapless::Vec::from_slice(&[0x00, 0xFF, 0xAA]).unwrap(),
+            }),
+            0
+        ))
+    );
+}
+
+#[test]
+fn rejects_bad_lines() {
+    let cases = [
+        ("", ParseError::Empty),
+        ("   ", ParseError::Empty),
+        ("blink", ParseError::UnknownCommand),
+        ("to=all", ParseError::Empty),
+        ("button", ParseError::Missing("button")),
+        ("button 5 long", ParseError::Invalid("button")),
+        ("button 1 short", ParseError::Missing("count")),
+        ("button 1 double", ParseError::Invalid("event")),
+        ("button 1 hold", ParseError::Missing("elapsed")),
+        ("button 1 long 2", ParseError::Unexpected),
+        ("status please", ParseError::Unexpected),
+        ("status speed=4", ParseError::Unexpected),
+        ("anim rainbow speed=1 speed=2", ParseError::Unexpected),
+        ("anim rainbow speed=256", ParseError::Invalid("speed")),
+        ("anim rainbow color=hsv:1,2", ParseError::Invalid("color")),
+        ("anim plaid", ParseError::Invalid("pattern")),
+        ("status to=device", ParseError::Invalid("to")),
+        ("handshake 12:34:56:78:9a", ParseError::Invalid("mac")),
+        ("handshake 12:34:56:78:9a:bc:de", ParseError::Invalid("mac")),
+        ("config-set brightness 256", ParseError::Invalid("value")),
+        ("config-set brightness u64:1", ParseError::Invalid("value")),
+        ("ota-begin size=1 sha256=00", ParseError::Invalid("sha256")),
+        (
+            "fragment transfer=1 index=0 count=1 data=0",
+            ParseError::Invalid("data"),
+        ),
+        (
+            "link-stats 12:34:56:78:9a:bc rssi=-50",
+            ParseError::Missing("rssi-avg"),
+        ),
+        ("sequence 1,2,3,4,1,2,3,4,1", ParseError::Invalid("buttons")),
+        ("scene-store", ParseError::Missing("slot")),
+        ("scene-store 1 speed=4", ParseError::Unexpected),
+        ("scene-recall 256", ParseError::Invalid("slot")),
+        ("scene-reply 1,8", ParseError::Invalid("slots")),
+        ("scene-reply empty", ParseError::Missing("slot")),
+        ("pixels 0 offset=0 data=ff80", ParseError::Invalid("data")),
+        ("pixels 0 data=ff8000", ParseError::Missing("offset")),
+    ];
+    for (line, error) in cases {
+        assert_eq!(parse(line), Err(error), "{line:?}");
+    }
+
+    let long = "status ".repeat(20);
+    assert_eq!(parse(&long), Err(ParseError::TooLong));
+}
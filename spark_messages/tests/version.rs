@@ -0,0 +1,310 @@
+use spark_messages::{
+    ButtonEventType, ButtonNumber, Color, DecodeError, Destination, EncodeError, ErrorCode,
+    FRAGMENT_LEN, FirmwareVersion, Fragment, LightMode, LightStatus, MAX_ENCODED_LEN,
+    MAX_FRAME_LEN, MAX_RELAY_HOPS, Message, MessageType, Pattern, SetAnimation,
+};
+
+/// The message model as shipped in protocol revision 0, used to play the part of old firmware.
+mod v0 {
+    use serde::{Deserialize, Serialize};
+
+    pub const PROTOCOL_VERSION: u8 = 0;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub struct Message {
+        pub protocol_version: u8,
+        pub message_type: MessageType,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub enum ButtonEventType {
+        ShortPress { count: usize },
+        LongPress,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub enum ButtonNumber {
+        Button1,
+        Button2,
+        Button3,
+        Button4,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    pub enum MessageType {
+        ButtonEvent {
+            button_number: ButtonNumber,
+            event_type: ButtonEventType,
+        },
+    }
+}
+
+#[test]
+fn decodes_v0_frames() {
+    let old = v0::Message {
+        protocol_version: v0::PROTOCOL_VERSION,
+        message_type: v0::MessageType::ButtonEvent {
+            button_number: v0::ButtonNumber::Button3,
+            event_type: v0::ButtonEventType::ShortPress { count: 2 },
+        },
+    };
+    let mut buf = [0u8; 32];
+    let frame = postcard::to_slice(&old, &mut buf).unwrap();
+
+    let message = Message::decode(frame).unwrap();
+    assert_eq!(message.protocol_version, 0);
+    assert_eq!(message.counter, 0);
+    assert_eq!(
+        message.message_type,
+        MessageType::ButtonEvent {
+            button_number: ButtonNumber::Button3,
+            event_type: ButtonEventType::ShortPress { count: 2 },
+        }
+    );
+}
+
+#[test]
+fn v0_firmware_decodes_new_frames() {
+    let message = Message::new(
+        MessageType::ButtonEvent {
+            button_number: ButtonNumber::Button1,
+            event_type: ButtonEventType::LongPress,
+        },
+        300,
+    );
+    let mut buf = [0u8; 32];
+    let frame = postcard::to_slice(&message, &mut buf).unwrap();
+
+    let old = postcard::from_bytes::<v0::Message>(frame).unwrap();
+    // v0 lights drop anything that isn't an exact version match.
+    assert_eq!(old.protocol_version, v0::PROTOCOL_VERSION);
+    assert_eq!(
+        old.message_type,
+        v0::MessageType::ButtonEvent {
+            button_number: v0::ButtonNumber::Button1,
+            event_type: v0::ButtonEventType::LongPress,
+        }
+    );
+}
+
+#[test]
+fn ignores_padding_and_appended_fields() {
+    let message = Message::new(
+        MessageType::ButtonEvent {
+            button_number: ButtonNumber::Button4,
+            event_type: ButtonEventType::ShortPress { count: 1 },
+        },
+        7,
+    );
+    let mut buf = [0u8; 32];
+    let len = postcard::to_slice(&message, &mut buf).unwrap().len();
+
+    // v0 remotes send the whole zero-padded buffer.
+    assert_eq!(Message::decode(&buf).unwrap(), message);
+
+    // A newer revision may append fields after the message type.
+    buf[0] = 9;
+    buf[len..len + 3].copy_from_slice(&[0xAA, 0xBB, 0xCC]);
+    let decoded = Message::decode(&buf[..len + 3]).unwrap();
+    assert_eq!(decoded.protocol_version, 9);
+    assert_eq!(decoded.message_type, message.message_type);
+    assert_eq!(decoded.counter, message.counter);
+}
+
+#[test]
+fn rejects_unknown_types() {
+    // Message type discriminant 100 doesn't exist (yet).
+    assert_eq!(
+        Message::decode(&[9, 100, 0, 0]),
+        Err(DecodeError::UnknownType {
+            protocol_version: 9
+        })
+    );
+    // Neither does pattern 100.
+    let mut buf = [0u8; MAX_ENCODED_LEN];
+    let frame = set_animation().encode(&mut buf).unwrap();
+    let pattern = frame
+        .iter()
+        .position(|&b| b == Pattern::Rainbow as u8)
+        .unwrap();
+    buf[pattern] = 100;
+    assert_eq!(
+        Message::decode(&buf),
+        Err(DecodeError::UnknownType {
+            protocol_version: 1
+        })
+    );
+}
+
+#[test]
+fn rejects_truncated_frames() {
+    assert_eq!(Message::decode(&[]), Err(DecodeError::Truncated));
+
+    let mut buf = [0u8; MAX_ENCODED_LEN];
+    let message = set_animation()
+        .with_destination(Destination::Groups(u32::MAX))
+        .with_id(u16::MAX)
+        .relayed([0xFF; 6], MAX_RELAY_HOPS)
+        .unwrap();
+    let frame = message.encode(&mut buf).unwrap();
+    // A one byte counter, then a destination of one byte for the variant and five for the groups,
+    // a three byte ID, a one byte hop count, and an origin of one byte for `Some` and six for the
+    // MAC address.
+    let type_end = frame.len() - 18;
+    let destination_end = type_end + 7;
+    let id_end = destination_end + 3;
+
+    // Older revisions end frames right after the message type, the counter, the destination, the
+    // ID or the hop count.
+    for len in [type_end, type_end + 1, destination_end, id_end, id_end + 1] {
+        assert!(Message::decode(&frame[..len]).is_ok(), "{len} bytes");
+    }
+    let truncated = (1..type_end)
+        .chain(type_end + 2..destination_end)
+        .chain(destination_end + 1..id_end)
+        .chain(id_end + 2..frame.len());
+    for len in truncated {
+        assert_eq!(
+            Message::decode(&frame[..len]),
+            Err(DecodeError::Truncated),
+            "{len} bytes"
+        );
+    }
+}
+
+#[test]
+fn rejects_frames_older_than_their_message() {
+    let mut buf = [0u8; MAX_ENCODED_LEN];
+    let frame = set_animation().encode(&mut buf).unwrap();
+    let len = frame.len();
+
+    // Revision 0 had no animations.
+    buf[0] = 0;
+    assert_eq!(
+        Message::decode(&buf[..len]),
+        Err(DecodeError::BadVersion {
+            protocol_version: 0
+        })
+    );
+}
+
+#[test]
+fn encodes_without_padding() {
+    let message = set_animation();
+    let mut buf = [0xFFu8; MAX_ENCODED_LEN];
+    let frame = message.encode(&mut buf).unwrap();
+    assert_eq!(
+        frame.len(),
+        postcard::to_slice(&message, &mut [0u8; 32]).unwrap().len()
+    );
+    assert_eq!(Message::decode(frame).unwrap(), message);
+
+    let too_small = &mut [0u8; MAX_ENCODED_LEN][..frame.len() - 1];
+    assert_eq!(message.encode(too_small), Err(EncodeError::BufferFull));
+}
+
+#[test]
+fn every_message_fits_in_a_signed_frame() {
+    // The largest message there is.
+    let message = Message::new(
+        MessageType::Fragment(Fragment {
+            transfer: u16::MAX,
+            index: u8::MAX,
+            count: u8::MAX,
+            data: heapless::Vec::from_slice(&[0xFF; FRAGMENT_LEN]).unwrap(),
+        }),
+        u32::MAX,
+    )
+    .with_destination(Destination::Groups(u32::MAX))
+    .with_id(u16::MAX);
+    let message = Message {
+        hops: MAX_RELAY_HOPS,
+        origin: Some([0xFF; 6]),
+        ..message
+    };
+    let mut buf = [0u8; MAX_ENCODED_LEN];
+    assert!(message.encode(&mut buf).is_ok());
+}
+
+fn set_animation() -> Message {
+    Message::new(
+        MessageType::SetAnimation(SetAnimation {
+            pattern: Pattern::Rainbow,
+            color: Color::Hsv {
+                hue: 10,
+                sat: 20,
+                val: 30,
+            },
+            speed: 40,
+            brightness: 50,
+            duration_ms: None,
+        }),
+        60,
+    )
+}
+
+#[test]
+fn set_animation_round_trips() {
+    let message = Message::new(
+        MessageType::SetAnimation(SetAnimation {
+            pattern: Pattern::Rainbow,
+            color: Color::Rgb {
+                r: 255,
+                g: 128,
+                b: 0,
+            },
+            speed: 40,
+            brightness: 200,
+            duration_ms: Some(u32::MAX),
+        }),
+        u32::MAX,
+    );
+    assert_eq!(message.protocol_version, 1);
+
+    // Has to fit in the buffers firmware sends from.
+    let mut buf = [0u8; 32];
+    let frame = postcard::to_slice(&message, &mut buf).unwrap();
+    assert_eq!(Message::decode(frame).unwrap(), message);
+}
+
+#[test]
+fn light_status_round_trips() {
+    let message = Message::new(
+        MessageType::LightStatus(LightStatus {
+            mode: LightMode::Latched,
+            pattern: Pattern::Breathe,
+            brightness: 25,
+            uptime_ms: u64::MAX,
+            firmware_version: FirmwareVersion::parse("1.2.3"),
+            last_error: Some(ErrorCode::Fragment),
+        }),
+        u32::MAX,
+    );
+    assert_eq!(message.protocol_version, 1);
+
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let frame = postcard::to_slice(&message, &mut buf).unwrap();
+    assert_eq!(Message::decode(frame).unwrap(), message);
+}
+
+#[test]
+fn parses_firmware_versions() {
+    const VERSION: FirmwareVersion = FirmwareVersion::parse(env!("CARGO_PKG_VERSION"));
+    assert_eq!(
+        VERSION,
+        FirmwareVersion {
+            major: 0,
+            minor: 1,
+            patch: 0
+        }
+    );
+    assert_eq!(
+        FirmwareVersion::parse("12.0.255-rc.1+build"),
+        FirmwareVersion {
+            major: 12,
+            minor: 0,
+            patch: 255
+        }
+    );
+    assert!(FirmwareVersion::parse("1.10.0") > FirmwareVersion::parse("1.9.9"));
+}